@@ -1,11 +1,18 @@
 //! Generates test case JSON-RPC responses for appearances of a single address
 
-use std::{fs::File, path::PathBuf};
+use std::{fs, path::PathBuf};
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use serde::{Deserialize, Serialize};
+use unchained_utils::{
+    chain::ChainConfig,
+    files::ChunksDir,
+    query::{Page, Pagination},
+    structure::TransactionId,
+    Address, BlockRange,
+};
 
-use crate::{cli::RangeParam, utils::unchained_index_to_location};
+use crate::{source::parse_source, utils::unchained_index_to_location};
 
 /// Response to address_getAppearances
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -13,61 +20,93 @@ use crate::{cli::RangeParam, utils::unchained_index_to_location};
 pub struct AppearancesResponse {
     pub id: u32,
     pub jsonrpc: String,
-    pub result: Vec<RelevantTransaction>,
+    pub result: AppearancesResult,
+}
+
+/// Appearances, either all at once or as a page with a continuation.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AppearancesResult {
+    All(Vec<RelevantTransaction>),
+    Paged(PagedAppearances),
+}
+
+/// One page of appearances.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PagedAppearances {
+    pub appearances: Vec<RelevantTransaction>,
+    /// Cursor to request the next page. Null for the last page.
+    pub continuation: Option<Continuation>,
+}
+
+/// The last appearance of a page, used to continue from.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Continuation {
+    pub block_number: String,
+    /// Raw UnchainedIndex transaction index (may be a sentinel value).
+    pub transaction_index: String,
 }
 
 impl AppearancesResponse {
+    /// Creates a response from a chifra export.
+    ///
+    /// As for the other sources, an address that is not in the export gives an
+    /// empty response. Sentinels without a location should be hidden by the
+    /// pagination (see [crate::utils::hidden_sentinels]) so that they do not
+    /// count towards the limit.
     pub fn create(
         value: AppearanceSource,
-        address: String,
-        range: RangeParam,
-        start_block: Option<u32>,
-        end_block: Option<u32>,
+        address: Address,
+        desired: BlockRange,
+        pagination: &Pagination,
+        paged: bool,
+        chain: &ChainConfig,
     ) -> anyhow::Result<Self> {
-        match (start_block, end_block, &range) {
-            (None, _, RangeParam::Single | RangeParam::Custom) => {
-                bail!("Must provide start block for specific blocks")
-            }
-            (_, None, RangeParam::Custom) => {
-                bail!("Must provide end block for custom block range")
-            }
-            (Some(start), Some(end), RangeParam::Custom) => {
-                if end < start {
-                    bail!("Custom range start must be earlier than end")
-                }
-            }
-            _ => {}
-        }
-
-        let result: Vec<RelevantTransaction> = value
-            .data
+        let page = pagination.paginate(value.appearances_of(&address, &desired));
+        Ok(Self::from_page(page, paged, chain))
+    }
+    /// Creates a response from local chunk files.
+    pub fn create_from_chunks(
+        dir: &ChunksDir,
+        address: Address,
+        desired: BlockRange,
+        pagination: &Pagination,
+        paged: bool,
+        chain: &ChainConfig,
+    ) -> anyhow::Result<Self> {
+        let page = dir.appearances_page(&address, &desired, pagination)?;
+        Ok(Self::from_page(page, paged, chain))
+    }
+    /// Creates a response from a page of appearances, interpreting sentinels
+    /// for the chain.
+    pub fn from_page(page: Page, paged: bool, chain: &ChainConfig) -> Self {
+        let appearances: Vec<RelevantTransaction> = page
+            .appearances
             .into_iter()
-            .filter(|x| x.address == address)
-            .filter(|x| match &range {
-                RangeParam::All => true,
-                RangeParam::Single => match start_block {
-                    Some(block) => x.block_number == block,
-                    None => false,
-                },
-                RangeParam::Custom => match (start_block, end_block) {
-                    (Some(start), Some(end)) => x.block_number >= start && x.block_number <= end,
-                    _ => false,
-                },
-            })
-            .filter_map(|x| match unchained_index_to_location(x.transaction_index) {
-                Some(location) => Some(RelevantTransaction {
-                    block_number: format!("{:#x}", x.block_number),
+            .filter_map(|x| {
+                unchained_index_to_location(x.index, chain).map(|location| RelevantTransaction {
+                    block_number: format!("{:#x}", x.block),
                     location,
-                }),
-                None => None,
+                })
             })
             .collect();
-
-        Ok(AppearancesResponse {
+        let result = match paged {
+            true => AppearancesResult::Paged(PagedAppearances {
+                appearances,
+                continuation: page.next.map(|next| Continuation {
+                    block_number: format!("{:#x}", next.block),
+                    transaction_index: format!("{:#x}", next.index),
+                }),
+            }),
+            false => AppearancesResult::All(appearances),
+        };
+        AppearancesResponse {
             id: 1,
             jsonrpc: "2.0".to_string(),
             result,
-        })
+        }
     }
 }
 
@@ -86,6 +125,7 @@ pub struct RelevantTransaction {
 /// ```
 /// chifra list <transaction> --fmt json | jq
 /// ```
+/// CSV, TXT and NDJSON exports are also accepted. See [crate::source].
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct AppearanceSource {
     data: Vec<AppearanceData>,
@@ -93,17 +133,29 @@ pub struct AppearanceSource {
 
 impl AppearanceSource {
     pub fn from_file(path: &PathBuf) -> anyhow::Result<AppearanceSource> {
-        let file = File::open(path).or_else(|e| bail!("Could not open {:?} {}", path, e))?;
-
-        let data: AppearanceSource = serde_json::from_reader(file)?;
-        Ok(data)
+        let content =
+            fs::read_to_string(path).or_else(|e| bail!("Could not open {:?} {}", path, e))?;
+        let data = parse_source(&content).with_context(|| format!("Could not parse {:?}", path))?;
+        Ok(AppearanceSource { data })
+    }
+    /// Appearances of a single address within a block range.
+    pub fn appearances_of(&self, address: &Address, range: &BlockRange) -> Vec<TransactionId> {
+        self.data
+            .iter()
+            .filter(|x| x.address == *address)
+            .map(|x| TransactionId {
+                block: x.block_number,
+                index: x.transaction_index,
+            })
+            .filter(|x| range.contains(x))
+            .collect()
     }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AppearanceData {
-    address: String,
-    block_number: u32,
-    transaction_index: u32,
+    pub address: Address,
+    pub block_number: u32,
+    pub transaction_index: u32,
 }
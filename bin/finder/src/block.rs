@@ -1,9 +1,12 @@
 //! Generates test case JSON-RPC responses for addresses in a single block
 
 use serde::{Deserialize, Serialize};
-use unchained_utils::structure::AddressData;
+use unchained_utils::{chain::ChainConfig, structure::AddressData, Address};
 
-use crate::utils::unchained_index_to_location;
+use crate::{
+    cli::AddressCase,
+    utils::{format_address, unchained_index_to_location},
+};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -20,45 +23,53 @@ pub struct BlockAddresses {
     pub addresses: Vec<BlockAppearance>,
 }
 
-/// Block of the merge (Ethereum consensus/execution)
-const THE_MERGE: u32 = 15537393;
-
 impl AddressesInBlockResponse {
-    pub fn create(data: Vec<AddressData>, block_number: u32) -> Self {
+    pub fn create(
+        data: Vec<AddressData>,
+        block_number: u32,
+        case: &AddressCase,
+        chain: &ChainConfig,
+    ) -> Self {
         if block_number == 0 {
             // See also: <https://github.com/ethereum/execution-apis/pull/456>
             todo!("Every appearance will be 'alloc'")
         }
-        let mut addresses: Vec<BlockAppearance> = data
+        let mut addresses: Vec<(Address, Vec<String>)> = data
             .into_iter()
             .map(|x| {
-                let address = format!("0x{}", hex::encode(x.address));
                 let locations = x
                     .appearances
                     .into_iter()
-                    .filter_map(|y| unchained_index_to_location(y.index))
+                    .filter_map(|y| unchained_index_to_location(y.index, chain))
                     .collect();
-
-                BlockAppearance { address, locations }
+                (x.address, locations)
             })
             .collect();
 
-        if block_number > THE_MERGE {
+        // Withdrawals are only provided for mainnet. Responses for other chains
+        // hold the appearances in the index alone.
+        if chain.name == "mainnet" && chain.is_post_merge(block_number) {
             // UnchainedIndex did not store withdrawals
             // We can provide them manually until the index is integrated
             // See also: <https://github.com/TrueBlocks/trueblocks-core/issues/3122>
             if block_number == 17190873 {
                 // Used as a test case.
-                let withdrawal = BlockAppearance {
-                    address: "0x1cedc0f3af8f9841b0a1f5c1a4ddc6e1a1629074".to_string(),
-                    locations: vec!["withdrawals".to_string()],
-                };
-                addresses.push(withdrawal);
+                let withdrawal = Address::from_hex("0x1cedc0f3af8f9841b0a1f5c1a4ddc6e1a1629074")
+                    .expect("Hard coded address is valid");
+                addresses.push((withdrawal, vec!["withdrawals".to_string()]));
             } else {
                 todo!("Post merge block warning. Check if UnchainedIndex includes withdrawals, or provide withdrawal addresses manually in codebase")
             }
         }
-        addresses.sort_by(|a1, a2| a1.address.cmp(&a2.address));
+        // Sort by address bytes so the order does not depend on output case.
+        addresses.sort_by_key(|a| a.0);
+        let addresses = addresses
+            .into_iter()
+            .map(|(address, locations)| BlockAppearance {
+                address: format_address(&address, case),
+                locations,
+            })
+            .collect();
         AddressesInBlockResponse {
             id: 1,
             jsonrpc: "2.0".to_string(),
@@ -79,3 +90,49 @@ pub struct BlockAppearance {
     /// The transaction index where the address appeared.
     pub locations: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unchained_utils::structure::TransactionId;
+
+    const WITHDRAWAL: &str = "0x1cedc0f3af8f9841b0a1f5c1a4ddc6e1a1629074";
+
+    fn block_data(block: u32) -> Vec<AddressData> {
+        vec![AddressData {
+            address: Address::from_hex("0x30a4639850b3ddeaaca4f06280aa751682f11382").unwrap(),
+            appearances: vec![TransactionId { block, index: 5 }],
+        }]
+    }
+
+    fn addresses(response: &AddressesInBlockResponse) -> Vec<&str> {
+        response
+            .result
+            .addresses
+            .iter()
+            .map(|a| a.address.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn withdrawals_only_added_on_mainnet() {
+        let chain = ChainConfig::mainnet();
+        let response = AddressesInBlockResponse::create(
+            block_data(17190873),
+            17190873,
+            &AddressCase::Lower,
+            &chain,
+        );
+        assert!(addresses(&response).contains(&WITHDRAWAL));
+
+        // Holesky is post-merge from block 0, with no known withdrawals.
+        let chain = ChainConfig::holesky();
+        let response =
+            AddressesInBlockResponse::create(block_data(100), 100, &AddressCase::Lower, &chain);
+        assert_eq!(
+            addresses(&response),
+            vec!["0x30a4639850b3ddeaaca4f06280aa751682f11382"]
+        );
+        assert_eq!(response.result.addresses[0].locations, vec!["0x5"]);
+    }
+}
@@ -1,5 +1,16 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
+use unchained_utils::{
+    appearance_index::DEFAULT_VOLUME_SPAN,
+    chain::{ChainConfig, DEFAULT_CHAIN},
+    manifest::DEFAULT_MANIFEST_VERSION,
+    query::Order,
+    structure::TransactionId,
+    Address,
+};
+
+#[cfg(feature = "http")]
+use unchained_utils::{contract::DEFAULT_PUBLISHER, download::DEFAULT_GATEWAY};
 
 /// Test case generator for address_* endpoints
 #[derive(Parser, Debug)]
@@ -8,6 +19,26 @@ pub struct AppArgs {
     /// eth_getAddressesInBlock
     #[clap(subcommand)]
     pub endpoint: AddressEndpoint,
+    /// Letter case used for addresses in the output.
+    #[clap(long, global = true, value_enum, default_value_t = AddressCase::Lower)]
+    pub address_case: AddressCase,
+    /// Chain the index is for: mainnet, sepolia, holesky, gnosis, or the name
+    /// of another chain (common sentinels, no known merge block).
+    #[clap(long, global = true, default_value = DEFAULT_CHAIN)]
+    pub chain: String,
+    /// JSON file describing the chain, used instead of a preset.
+    #[clap(long, global = true, conflicts_with = "chain")]
+    pub chain_config: Option<PathBuf>,
+}
+
+impl AppArgs {
+    /// Settings for the selected chain.
+    pub fn chain_config(&self) -> ChainConfig {
+        match &self.chain_config {
+            Some(path) => ChainConfig::from_file(path).expect("Couldn't read chain config"),
+            None => ChainConfig::named(&self.chain),
+        }
+    }
 }
 
 #[derive(Subcommand, Clone, Debug)]
@@ -15,21 +46,29 @@ pub enum AddressEndpoint {
     /// address_getAddressesInBlock
     ///
     /// Gets all appearances for all addresses in a single block.
+    #[clap(group(ArgGroup::new("chunk_source").required(true)))]
     GetAddressesInBlock {
         /// Block that appearances are to be found for.
         #[clap(short, long)]
         block: u32,
         /// UnchaineIndex file to search
-        #[clap(short, long)]
-        chunk_file: PathBuf,
+        #[clap(short, long, group = "chunk_source")]
+        chunk_file: Option<PathBuf>,
+        /// SQLite database (from sqlite-load), used instead of a chunk file.
+        #[cfg(feature = "sqlite")]
+        #[clap(short, long, group = "chunk_source")]
+        database: Option<PathBuf>,
     },
     /// address_getAppearances
     ///
     /// Gets all appearances for one addresses across multiple blocks.
+    #[clap(group(ArgGroup::new("source").required(true)))]
     GetAppearances {
         /// Address to get appearances for
+        ///
+        /// The 0x prefix is optional. Mixed case must be a valid EIP-55 checksum.
         #[clap(short, long)]
-        address: String,
+        address: Address,
         /// Block range kind to use for the test vector.
         #[clap(short, long)]
         range: RangeParam,
@@ -39,9 +78,369 @@ pub enum AddressEndpoint {
         /// Optional end block (inclusive, may be equal to start block for narrow range)
         #[clap(short, long)]
         end_block: Option<u32>,
-        /// File containing a JSON formatted response from 'chifra <address> list --fmt json'
+        /// File containing a response from 'chifra list <address> --fmt <json|csv|txt>'
+        ///
+        /// The format is detected from the content. NDJSON and multi-address exports are accepted.
+        #[clap(short, long, group = "source")]
+        file: Option<PathBuf>,
+        /// Directory containing UnchainedIndex chunk files, used instead of a file.
+        #[clap(short, long, group = "source")]
+        chunks_dir: Option<PathBuf>,
+        /// Index file (from chunk-index) used to only open chunks containing the address.
+        #[clap(long, requires = "chunks_dir")]
+        chunk_index: Option<PathBuf>,
+        /// SQLite database (from sqlite-load), used instead of a file.
+        #[cfg(feature = "sqlite")]
+        #[clap(short, long, group = "source")]
+        database: Option<PathBuf>,
+        /// Order of appearances in the response.
+        #[clap(short, long, value_enum, default_value_t = OrderParam::Asc)]
+        order: OrderParam,
+        /// Maximum number of appearances per page. Enables a paged response.
+        #[clap(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+        limit: Option<u64>,
+        /// Continue after this appearance, as '<block>:<index>' (decimal or 0x hex).
+        ///
+        /// Use the continuation of a previous page. Enables a paged response.
+        #[clap(long, value_parser = parse_cursor)]
+        cursor: Option<TransactionId>,
+    },
+    /// Compares appearances in a chifra export against local chunk files.
+    ///
+    /// Reports (block, index) pairs that are missing from or extra in the chunks,
+    /// treating the export as the trusted source. Only blocks covered by the
+    /// chunk files present are compared.
+    Diff {
+        /// Address to compare appearances for
+        #[clap(short, long)]
+        address: Address,
+        /// Block range kind to compare.
+        #[clap(short, long)]
+        range: RangeParam,
+        /// Optional start block. If no range is given, all blocks are used.
+        #[clap(short, long)]
+        start_block: Option<u32>,
+        /// Optional end block (inclusive, may be equal to start block for narrow range)
+        #[clap(short, long)]
+        end_block: Option<u32>,
+        /// File containing a response from 'chifra list <address> --fmt <json|csv|txt>'
         #[clap(short, long)]
         file: PathBuf,
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long)]
+        chunks_dir: PathBuf,
+    },
+    /// Merges adjacent chunk files into a single chunk.
+    ///
+    /// All chunks that intersect the block range are merged. The new chunk is
+    /// named for the combined range. Source chunks are left in place.
+    Consolidate {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long)]
+        chunks_dir: PathBuf,
+        /// Earliest block of the chunks to merge.
+        #[clap(short, long)]
+        start_block: u32,
+        /// Latest block of the chunks to merge.
+        #[clap(short, long)]
+        end_block: u32,
+        /// Directory to write the merged chunk to.
+        #[clap(short, long)]
+        output_dir: PathBuf,
+        /// Optional directory to write a bloom filter file for the new chunk to.
+        #[clap(short, long)]
+        blooms_dir: Option<PathBuf>,
+    },
+    /// Writes the part of a chunk within a block range as a new chunk.
+    ///
+    /// Only addresses with appearances in the range are kept. The new chunk is
+    /// named for the range (limited to the blocks the chunk covers).
+    Split {
+        /// UnchainedIndex file to read
+        #[clap(short, long)]
+        chunk_file: PathBuf,
+        /// Earliest block to keep.
+        #[clap(short, long)]
+        start_block: u32,
+        /// Latest block to keep (inclusive).
+        #[clap(short, long)]
+        end_block: u32,
+        /// Directory to write the new chunk to.
+        #[clap(short, long)]
+        output_dir: PathBuf,
+        /// Optional directory to write a bloom filter file for the new chunk to.
+        #[clap(short, long)]
+        blooms_dir: Option<PathBuf>,
+    },
+    /// Writes a chunk containing only the entries of selected addresses.
+    ///
+    /// Produces small deterministic fixtures. The new chunk is named for the chunk
+    /// range, or the block range if one is given.
+    Extract {
+        /// UnchainedIndex file to read
+        #[clap(short, long)]
+        chunk_file: PathBuf,
+        /// Addresses to keep (repeat the flag or separate with commas)
+        #[clap(short, long, required = true, value_delimiter = ',')]
+        address: Vec<Address>,
+        /// Optional earliest block to keep.
+        #[clap(short, long, requires = "end_block")]
+        start_block: Option<u32>,
+        /// Optional latest block to keep (inclusive).
+        #[clap(short, long, requires = "start_block")]
+        end_block: Option<u32>,
+        /// Directory to write the new chunk to.
+        #[clap(short, long)]
+        output_dir: PathBuf,
+        /// Optional directory to write a bloom filter file for the new chunk to.
+        #[clap(short, long)]
+        blooms_dir: Option<PathBuf>,
+    },
+    /// Writes a trueblocks-core compatible bloom filter file for chunk files.
+    ///
+    /// Each bloom is named for the chunk range, e.g. "017190314-017193246.bloom".
+    #[clap(group(ArgGroup::new("chunks").required(true)))]
+    WriteBlooms {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long, group = "chunks")]
+        chunks_dir: Option<PathBuf>,
+        /// UnchainedIndex file to write a bloom for.
+        #[clap(long, group = "chunks")]
+        chunk_file: Option<PathBuf>,
+        /// Directory to write the bloom filter files to.
+        #[clap(short, long)]
+        output_dir: PathBuf,
+    },
+    /// Checks bloom filter files against their chunks.
+    ///
+    /// Every address in a chunk must be reported present by its bloom. The false
+    /// positive rate is estimated by probing pseudo-random addresses.
+    CheckBlooms {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long)]
+        chunks_dir: PathBuf,
+        /// Directory containing a bloom filter file for each chunk.
+        #[clap(short, long)]
+        blooms_dir: PathBuf,
+        /// Number of addresses to probe per chunk.
+        #[clap(short, long, default_value_t = 10_000)]
+        probes: u32,
+        /// Seed for the probe addresses.
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Creates or updates an index of the chunks each address appears in.
+    ///
+    /// Only chunks that are not yet in the index are read. Chunks that have
+    /// been removed or changed since they were indexed are dropped.
+    ChunkIndex {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long)]
+        chunks_dir: PathBuf,
+        /// Index file to create or update.
+        #[clap(short, long)]
+        index: PathBuf,
+    },
+    /// Converts chunk files into an address-appearance-index.
+    ///
+    /// Appearances are grouped by address prefix (chapters) and fixed block spans
+    /// (volumes), SSZ encoded and snappy compressed. A manifest.json is written
+    /// alongside the files.
+    ExportAppearanceIndex {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long)]
+        chunks_dir: PathBuf,
+        /// Optional earliest block to export.
+        #[clap(short, long, requires = "end_block")]
+        start_block: Option<u32>,
+        /// Optional latest block to export (inclusive).
+        #[clap(short, long, requires = "start_block")]
+        end_block: Option<u32>,
+        /// Number of blocks in each volume.
+        #[clap(long, default_value_t = DEFAULT_VOLUME_SPAN, value_parser = clap::value_parser!(u32).range(1..))]
+        volume_span: u32,
+        /// Number of leading address bytes per chapter (1 or 2, min-know uses 1).
+        #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+        prefix_bytes: u8,
+        /// Directory to write the index to.
+        #[clap(short, long)]
+        output_dir: PathBuf,
+    },
+    /// Encodes appearances as portal network content (key, id and SSZ value).
+    ///
+    /// Either the appearances of an address in a block range (from a chunks
+    /// directory), or the whole contents of a chunk file.
+    #[clap(group(ArgGroup::new("content").required(true)))]
+    PortalContent {
+        /// Address to encode appearances for (requires chunks_dir and a block range).
+        #[clap(short, long, requires_all = ["chunks_dir", "start_block"])]
+        address: Option<Address>,
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long, group = "content", requires = "address")]
+        chunks_dir: Option<PathBuf>,
+        /// Earliest block of the address content.
+        #[clap(short, long, requires_all = ["end_block", "address"])]
+        start_block: Option<u32>,
+        /// Latest block of the address content (inclusive).
+        #[clap(short, long, requires = "start_block")]
+        end_block: Option<u32>,
+        /// UnchainedIndex file to encode in full, used instead of an address.
+        #[clap(long, group = "content")]
+        chunk_file: Option<PathBuf>,
+    },
+    /// Checks local chunk files against the CIDs in a manifest (offline).
+    ///
+    /// Computes the CID that 'ipfs add' would give each file and compares it
+    /// and the file size with the manifest entry for the same block range.
+    VerifyCids {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long)]
+        chunks_dir: PathBuf,
+        /// Manifest JSON file.
+        #[clap(short, long)]
+        manifest: PathBuf,
+        /// Optional directory containing bloom filter files to check as well.
+        #[clap(short, long)]
+        blooms_dir: Option<PathBuf>,
+    },
+    /// Creates a manifest for local chunk files and bloom filters.
+    ///
+    /// Computes the CID and size that 'ipfs add' would give each file.
+    GenerateManifest {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long)]
+        chunks_dir: PathBuf,
+        /// Directory containing a bloom filter file for each chunk.
+        #[clap(short, long)]
+        blooms_dir: PathBuf,
+        /// CID of the specification document.
+        #[clap(short, long)]
+        specification: String,
+        /// Version recorded in the manifest.
+        #[clap(long, default_value = DEFAULT_MANIFEST_VERSION)]
+        manifest_version: String,
+    },
+    /// Compares a manifest with an older manifest or a local chunks directory.
+    ///
+    /// Reports added, removed, re-hashed and re-ranged chunks, and the
+    /// downloads, re-verifications and deletions needed to update.
+    #[clap(group(ArgGroup::new("previous").required(true)))]
+    ManifestDiff {
+        /// Newer manifest JSON file.
+        #[clap(short, long)]
+        manifest: PathBuf,
+        /// Older manifest JSON file.
+        #[clap(short, long, group = "previous")]
+        old_manifest: Option<PathBuf>,
+        /// Directory containing UnchainedIndex chunk files, used instead of an older manifest.
+        #[clap(short, long, group = "previous")]
+        chunks_dir: Option<PathBuf>,
+    },
+    /// Writes the chunks listed in a manifest from a CAR archive.
+    ///
+    /// Files are named for their block range. Manifest entries not in the
+    /// archive are skipped. Blocks are checked against their CIDs.
+    UnpackCar {
+        /// CAR (v1 or v2) archive to read.
+        #[clap(long)]
+        car: PathBuf,
+        /// Manifest JSON file listing the chunk CIDs.
+        #[clap(short, long)]
+        manifest: PathBuf,
+        /// Directory to write chunk files to.
+        #[clap(short, long)]
+        output_dir: PathBuf,
+        /// Optional directory to write bloom filter files to.
+        #[clap(short, long)]
+        blooms_dir: Option<PathBuf>,
+    },
+    /// Reads the latest manifest CID from the Unchained Index contract (requires the `http` feature).
+    ///
+    /// Performs an eth_call of manifestHashMap(publisher, chain).
+    #[cfg(feature = "http")]
+    ManifestCid {
+        /// Ethereum mainnet JSON-RPC endpoint.
+        #[clap(long)]
+        rpc_url: String,
+        /// Publisher of the manifest.
+        #[clap(long, default_value_t = DEFAULT_PUBLISHER)]
+        publisher: Address,
+    },
+    /// Downloads the chunks in a block range from an IPFS gateway (requires the `http` feature).
+    ///
+    /// Chunks are named for their block range. Interrupted downloads are
+    /// resumed, and files are only kept once their size and CID match the
+    /// manifest. Chunks already present are not fetched again.
+    #[cfg(feature = "http")]
+    #[clap(group(ArgGroup::new("manifest_source").required(true)))]
+    #[clap(group(ArgGroup::new("destination").required(true)))]
+    Download {
+        /// Manifest JSON file listing the chunk CIDs.
+        #[clap(short, long, group = "manifest_source")]
+        manifest: Option<PathBuf>,
+        /// CID of the manifest, fetched from the gateway instead of a file.
+        #[clap(long, group = "manifest_source")]
+        manifest_cid: Option<String>,
+        /// Ethereum mainnet JSON-RPC endpoint used to read the manifest CID from
+        /// the Unchained Index contract.
+        #[clap(long, group = "manifest_source")]
+        rpc_url: Option<String>,
+        /// Publisher of the manifest (used with rpc_url).
+        #[clap(long, default_value_t = DEFAULT_PUBLISHER)]
+        publisher: Address,
+        /// Base URL of the IPFS gateway.
+        #[clap(short, long, default_value = DEFAULT_GATEWAY)]
+        gateway: String,
+        /// Optional earliest block to download.
+        #[clap(short, long, requires = "end_block")]
+        start_block: Option<u32>,
+        /// Optional latest block to download (inclusive).
+        #[clap(short, long, requires = "start_block")]
+        end_block: Option<u32>,
+        /// Directory to write chunk files to.
+        #[clap(short, long, group = "destination")]
+        output_dir: Option<PathBuf>,
+        /// Optional directory to write bloom filter files to.
+        #[clap(short, long, conflicts_with = "unchained_dir")]
+        blooms_dir: Option<PathBuf>,
+        /// trueblocks "unchained" directory. Chunks and blooms are written to the
+        /// directories of the chain within it (e.g., "mainnet/finalized").
+        #[clap(short, long, group = "destination")]
+        unchained_dir: Option<PathBuf>,
+    },
+    /// Loads chunk files into a SQLite database (requires the `sqlite` feature).
+    ///
+    /// Chunks already in the database are skipped, so the command can be
+    /// repeated as new chunks are added to the directory.
+    #[cfg(feature = "sqlite")]
+    SqliteLoad {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long)]
+        chunks_dir: PathBuf,
+        /// SQLite database to create or append to.
+        #[clap(short, long)]
+        database: PathBuf,
+    },
+    /// Exports appearances to a Parquet file (requires the `parquet` feature).
+    ///
+    /// Columns: address, block, tx_index, location_kind, chunk_range.
+    #[cfg(feature = "parquet")]
+    ExportParquet {
+        /// Directory containing UnchainedIndex chunk files
+        #[clap(short, long, required_unless_present = "chunk_file")]
+        chunks_dir: Option<PathBuf>,
+        /// Single UnchainedIndex file, used instead of a directory.
+        #[clap(long, conflicts_with = "chunks_dir")]
+        chunk_file: Option<PathBuf>,
+        /// Optional earliest block to export.
+        #[clap(short, long, requires = "end_block")]
+        start_block: Option<u32>,
+        /// Optional latest block to export (inclusive).
+        #[clap(short, long, requires = "start_block")]
+        end_block: Option<u32>,
+        /// Parquet file to write.
+        #[clap(short, long)]
+        output: PathBuf,
     },
 }
 
@@ -51,3 +450,41 @@ pub enum RangeParam {
     Single,
     Custom,
 }
+
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+pub enum OrderParam {
+    Asc,
+    Desc,
+}
+
+impl From<OrderParam> for Order {
+    fn from(value: OrderParam) -> Self {
+        match value {
+            OrderParam::Asc => Order::Ascending,
+            OrderParam::Desc => Order::Descending,
+        }
+    }
+}
+
+/// Parses a '<block>:<index>' cursor. Each value may be decimal or 0x hex.
+fn parse_cursor(cursor: &str) -> Result<TransactionId, String> {
+    let parse = |value: &str| match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse::<u32>(),
+    };
+    let Some((block, index)) = cursor.split_once(':') else {
+        return Err(format!("Expected '<block>:<index>', got '{}'", cursor));
+    };
+    Ok(TransactionId {
+        block: parse(block).map_err(|e| format!("Invalid block '{}': {}", block, e))?,
+        index: parse(index).map_err(|e| format!("Invalid index '{}': {}", index, e))?,
+    })
+}
+
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+pub enum AddressCase {
+    /// All lowercase (as used by trueblocks-core).
+    Lower,
+    /// Mixed case EIP-55 checksum.
+    Checksum,
+}
@@ -0,0 +1,69 @@
+//! Compares appearances from a chifra export with those in local chunk files
+
+use std::{collections::BTreeSet, path::PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use unchained_utils::{files::ChunksDir, structure::TransactionId, Address, BlockRange};
+
+use crate::{appearances::AppearanceSource, cli::AddressCase, utils::format_address};
+
+/// Differences between a trusted export and the chunk files.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppearancesDiff {
+    pub address: String,
+    /// Blocks compared (the desired range, limited to the blocks the chunks cover).
+    pub compared: Vec<BlockRange>,
+    pub source_count: usize,
+    pub chunks_count: usize,
+    /// Present in the export but absent from the chunks.
+    pub missing: Vec<TransactionId>,
+    /// Present in the chunks but absent from the export.
+    pub extra: Vec<TransactionId>,
+}
+
+impl AppearancesDiff {
+    pub fn create(
+        source: &PathBuf,
+        chunks_dir: &PathBuf,
+        address: Address,
+        desired: BlockRange,
+        case: &AddressCase,
+    ) -> anyhow::Result<Self> {
+        let source = AppearanceSource::from_file(source)?;
+        let dir = ChunksDir::new(chunks_dir)
+            .with_context(|| format!("Could not read chunks in {:?}", chunks_dir))?;
+
+        // Restrict the comparison to blocks that the local chunks cover.
+        let compared: Vec<BlockRange> = dir
+            .for_range(&desired)
+            .unwrap_or_default()
+            .iter()
+            .map(|chunk| BlockRange {
+                old: chunk.range.old.max(desired.old),
+                new: chunk.range.new.min(desired.new),
+            })
+            .collect();
+        let covered = |tx: &TransactionId| compared.iter().any(|r| r.contains(tx));
+
+        let from_source: BTreeSet<TransactionId> = source
+            .appearances_of(&address, &desired)
+            .into_iter()
+            .filter(covered)
+            .collect();
+        let from_chunks: BTreeSet<TransactionId> = dir
+            .appearances_of(&address, &desired)?
+            .into_iter()
+            .collect();
+
+        Ok(AppearancesDiff {
+            address: format_address(&address, case),
+            source_count: from_source.len(),
+            chunks_count: from_chunks.len(),
+            missing: from_source.difference(&from_chunks).cloned().collect(),
+            extra: from_chunks.difference(&from_source).cloned().collect(),
+            compared,
+        })
+    }
+}
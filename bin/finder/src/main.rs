@@ -1,56 +1,700 @@
 mod appearances;
 mod block;
 pub mod cli;
+mod diff;
+mod source;
 mod utils;
 
 use appearances::{AppearanceSource, AppearancesResponse};
 use block::AddressesInBlockResponse;
 use clap::Parser;
 use cli::{AddressEndpoint, AppArgs};
-use unchained_utils::{BlockRange, UnchainedFile};
+use diff::AppearancesDiff;
+use std::path::PathBuf;
+use unchained_utils::{
+    appearance_index::{write_appearance_index, IndexLayout},
+    bloom::{check_blooms, write_bloom, WrittenBloom},
+    car::{unpack_chunks, CarArchive},
+    chunk_index::{ChunkIndex, IndexFile},
+    files::{ChunkFile, ChunksDir},
+    manifest::Manifest,
+    portal::{address_appearances_content, chunk_contents, EncodedContent},
+    query::Pagination,
+    write::{consolidate, extract_addresses, extract_range, WrittenChunk},
+    BlockRange, UnchainedFile,
+};
+use utils::{block_range, hidden_sentinels};
+
+#[cfg(feature = "sqlite")]
+use unchained_utils::sqlite::AppearanceDb;
+#[cfg(feature = "http")]
+use unchained_utils::{
+    contract::manifest_cid,
+    download::{download_chunks, fetch_manifest, HttpGateway},
+};
 
 fn main() {
     let args = AppArgs::parse();
     match args.endpoint {
         AddressEndpoint::GetAddressesInBlock { .. } => generate_addresses_in_block(args),
         AddressEndpoint::GetAppearances { .. } => generate_appearances(args),
+        AddressEndpoint::Diff { .. } => diff_appearances(args),
+        AddressEndpoint::Consolidate { .. } => consolidate_chunks(args),
+        AddressEndpoint::Split { .. } => split_chunk(args),
+        AddressEndpoint::Extract { .. } => extract_chunk(args),
+        AddressEndpoint::WriteBlooms { .. } => write_blooms(args),
+        AddressEndpoint::CheckBlooms { .. } => check_chunk_blooms(args),
+        AddressEndpoint::ChunkIndex { .. } => update_chunk_index(args),
+        AddressEndpoint::ExportAppearanceIndex { .. } => export_appearance_index(args),
+        AddressEndpoint::PortalContent { .. } => portal_content(args),
+        AddressEndpoint::VerifyCids { .. } => verify_cids(args),
+        AddressEndpoint::GenerateManifest { .. } => generate_manifest(args),
+        AddressEndpoint::ManifestDiff { .. } => manifest_diff(args),
+        AddressEndpoint::UnpackCar { .. } => unpack_car(args),
+        #[cfg(feature = "http")]
+        AddressEndpoint::ManifestCid { .. } => read_manifest_cid(args),
+        #[cfg(feature = "http")]
+        AddressEndpoint::Download { .. } => download(args),
+        #[cfg(feature = "sqlite")]
+        AddressEndpoint::SqliteLoad { .. } => sqlite_load(args),
+        #[cfg(feature = "parquet")]
+        AddressEndpoint::ExportParquet { .. } => export_parquet(args),
     }
 }
 /// For address_getAppearances
 fn generate_appearances(args: AppArgs) {
-    let (address, range, start_block, end_block, file) = match args.endpoint {
+    let chain = args.chain_config();
+    #[cfg(feature = "sqlite")]
+    let database = match &args.endpoint {
+        AddressEndpoint::GetAppearances { database, .. } => database.clone(),
+        _ => return,
+    };
+    let (
+        address,
+        range,
+        start_block,
+        end_block,
+        file,
+        chunks_dir,
+        chunk_index,
+        order,
+        limit,
+        cursor,
+    ) = match args.endpoint {
         AddressEndpoint::GetAppearances {
             address,
             range,
             start_block,
             end_block,
             file,
-        } => (address, range, start_block, end_block, file),
+            chunks_dir,
+            chunk_index,
+            order,
+            limit,
+            cursor,
+            ..
+        } => (
+            address,
+            range,
+            start_block,
+            end_block,
+            file,
+            chunks_dir,
+            chunk_index,
+            order,
+            limit,
+            cursor,
+        ),
         _ => return,
     };
-    // Read file, parse, return formatted.
-    let source = AppearanceSource::from_file(&file).expect("Couldn't read file");
-    let response_test_vector =
-        AppearancesResponse::create(source, address, range, start_block, end_block)
-            .expect("Could not generate test from data");
+    let desired = block_range(&range, start_block, end_block).expect("Bad range");
+    let paged = limit.is_some() || cursor.is_some();
+    let pagination = Pagination {
+        order: order.into(),
+        limit: limit.map(|l| l as usize),
+        after: cursor,
+        hidden: hidden_sentinels(&chain),
+    };
+    #[cfg(feature = "sqlite")]
+    if let Some(database) = database {
+        let db = AppearanceDb::open(&database).expect("Couldn't open database");
+        let page = db
+            .appearances_page(&address, &desired, &pagination)
+            .expect("Could not query database");
+        let response_test_vector = AppearancesResponse::from_page(page, paged, &chain);
+        println!(
+            "{}",
+            serde_json::to_string(&response_test_vector).expect("Could not create JSON response")
+        );
+        return;
+    }
+    let response_test_vector = match (file, chunks_dir) {
+        (_, Some(dir)) => {
+            let mut chunks = ChunksDir::new(&dir).expect("Couldn't read chunks directory");
+            if let Some(index_path) = chunk_index {
+                let mut index = IndexFile::open(&index_path).expect("Couldn't read chunk index");
+                chunks = index
+                    .filter_dir(&chunks, &address)
+                    .expect("Couldn't read chunk index");
+            }
+            AppearancesResponse::create_from_chunks(
+                &chunks,
+                address,
+                desired,
+                &pagination,
+                paged,
+                &chain,
+            )
+        }
+        (Some(file), None) => {
+            // Read file, parse, return formatted.
+            let source = AppearanceSource::from_file(&file).expect("Couldn't read file");
+            AppearancesResponse::create(source, address, desired, &pagination, paged, &chain)
+        }
+        (None, None) => unreachable!("Clap requires a source"),
+    }
+    .expect("Could not generate test from data");
     println!(
         "{}",
         serde_json::to_string(&response_test_vector).expect("Could not create JSON response")
     );
 }
 
+/// Compares a chifra export with local chunk files.
+fn diff_appearances(args: AppArgs) {
+    let (address, range, start_block, end_block, file, chunks_dir) = match args.endpoint {
+        AddressEndpoint::Diff {
+            address,
+            range,
+            start_block,
+            end_block,
+            file,
+            chunks_dir,
+        } => (address, range, start_block, end_block, file, chunks_dir),
+        _ => return,
+    };
+    let desired = block_range(&range, start_block, end_block).expect("Bad range");
+    let diff = AppearancesDiff::create(&file, &chunks_dir, address, desired, &args.address_case)
+        .expect("Could not compare appearances");
+    println!(
+        "{}",
+        serde_json::to_string(&diff).expect("Could not create JSON response")
+    );
+}
+
+/// Merges adjacent chunk files.
+fn consolidate_chunks(args: AppArgs) {
+    let (chunks_dir, start_block, end_block, output_dir, blooms_dir) = match args.endpoint {
+        AddressEndpoint::Consolidate {
+            chunks_dir,
+            start_block,
+            end_block,
+            output_dir,
+            blooms_dir,
+        } => (chunks_dir, start_block, end_block, output_dir, blooms_dir),
+        _ => return,
+    };
+    let range = BlockRange::new(start_block, end_block).expect("Bad range");
+    let chunks = ChunksDir::new(&chunks_dir).expect("Couldn't read chunks directory");
+    let relevant = chunks.for_range(&range).expect("No chunks in range");
+    let written = consolidate(&relevant, &output_dir).expect("Could not consolidate chunks");
+    write_new_bloom(&written, blooms_dir);
+    println!(
+        "{}",
+        serde_json::to_string(&written).expect("Could not create JSON response")
+    );
+}
+
+/// Writes a block sub-range of a chunk as a new chunk.
+fn split_chunk(args: AppArgs) {
+    let (chunk_file, start_block, end_block, output_dir, blooms_dir) = match args.endpoint {
+        AddressEndpoint::Split {
+            chunk_file,
+            start_block,
+            end_block,
+            output_dir,
+            blooms_dir,
+        } => (chunk_file, start_block, end_block, output_dir, blooms_dir),
+        _ => return,
+    };
+    let range = BlockRange::new(start_block, end_block).expect("Bad range");
+    let chunk = ChunkFile::new(&chunk_file).expect("Couldn't read chunk file name");
+    let written = extract_range(&chunk, range, &output_dir).expect("Could not split chunk");
+    write_new_bloom(&written, blooms_dir);
+    println!(
+        "{}",
+        serde_json::to_string(&written).expect("Could not create JSON response")
+    );
+}
+
+/// Writes a chunk holding only selected addresses.
+fn extract_chunk(args: AppArgs) {
+    let (chunk_file, addresses, start_block, end_block, output_dir, blooms_dir) =
+        match args.endpoint {
+            AddressEndpoint::Extract {
+                chunk_file,
+                address,
+                start_block,
+                end_block,
+                output_dir,
+                blooms_dir,
+            } => (
+                chunk_file,
+                address,
+                start_block,
+                end_block,
+                output_dir,
+                blooms_dir,
+            ),
+            _ => return,
+        };
+    let range = match (start_block, end_block) {
+        (Some(start), Some(end)) => Some(BlockRange::new(start, end).expect("Bad range")),
+        _ => None,
+    };
+    let chunk = ChunkFile::new(&chunk_file).expect("Couldn't read chunk file name");
+    let written = extract_addresses(&chunk, &addresses, range, &output_dir)
+        .expect("Could not extract addresses");
+    write_new_bloom(&written, blooms_dir);
+    println!(
+        "{}",
+        serde_json::to_string(&written).expect("Could not create JSON response")
+    );
+}
+
+/// Writes the bloom filter for a newly written chunk, if a directory is given.
+fn write_new_bloom(written: &WrittenChunk, blooms_dir: Option<PathBuf>) {
+    if let Some(dir) = blooms_dir {
+        let chunk = ChunkFile::new(&written.path).expect("Couldn't read chunk file name");
+        write_bloom(&chunk, &dir).expect("Could not write bloom");
+    }
+}
+
+/// Writes bloom filters for chunk files.
+fn write_blooms(args: AppArgs) {
+    let (chunks_dir, chunk_file, output_dir) = match args.endpoint {
+        AddressEndpoint::WriteBlooms {
+            chunks_dir,
+            chunk_file,
+            output_dir,
+        } => (chunks_dir, chunk_file, output_dir),
+        _ => return,
+    };
+    let chunks: Vec<ChunkFile> = match (chunks_dir, chunk_file) {
+        (Some(dir), _) => {
+            ChunksDir::new(&dir)
+                .expect("Couldn't read chunks directory")
+                .paths
+        }
+        (None, Some(file)) => vec![ChunkFile::new(&file).expect("Couldn't read chunk file name")],
+        (None, None) => unreachable!("Clap requires a file or chunks directory"),
+    };
+    let written: Vec<WrittenBloom> = chunks
+        .iter()
+        .map(|chunk| write_bloom(chunk, &output_dir).expect("Could not write bloom"))
+        .collect();
+    println!(
+        "{}",
+        serde_json::to_string(&written).expect("Could not create JSON response")
+    );
+}
+
+/// Checks bloom filters against their chunks.
+fn check_chunk_blooms(args: AppArgs) {
+    let (chunks_dir, blooms_dir, probes, seed) = match args.endpoint {
+        AddressEndpoint::CheckBlooms {
+            chunks_dir,
+            blooms_dir,
+            probes,
+            seed,
+        } => (chunks_dir, blooms_dir, probes, seed),
+        _ => return,
+    };
+    let chunks = ChunksDir::new(&chunks_dir).expect("Couldn't read chunks directory");
+    let checks = check_blooms(&chunks, &blooms_dir, probes, seed).expect("Could not check blooms");
+    println!(
+        "{}",
+        serde_json::to_string(&checks).expect("Could not create JSON response")
+    );
+}
+
+/// Creates or updates the address to chunk index.
+fn update_chunk_index(args: AppArgs) {
+    let (chunks_dir, index) = match args.endpoint {
+        AddressEndpoint::ChunkIndex { chunks_dir, index } => (chunks_dir, index),
+        _ => return,
+    };
+    let chunks = ChunksDir::new(&chunks_dir).expect("Couldn't read chunks directory");
+    let mut chunk_index = ChunkIndex::from_file_or_new(&index).expect("Couldn't read chunk index");
+    let update = chunk_index.update(&chunks).expect("Could not index chunks");
+    chunk_index
+        .write(&index)
+        .expect("Could not write chunk index");
+    println!(
+        "{}",
+        serde_json::to_string(&update).expect("Could not create JSON response")
+    );
+}
+
+/// Converts chunks into the address-appearance-index format.
+fn export_appearance_index(args: AppArgs) {
+    let (chunks_dir, start_block, end_block, volume_span, prefix_bytes, output_dir) =
+        match args.endpoint {
+            AddressEndpoint::ExportAppearanceIndex {
+                chunks_dir,
+                start_block,
+                end_block,
+                volume_span,
+                prefix_bytes,
+                output_dir,
+            } => (
+                chunks_dir,
+                start_block,
+                end_block,
+                volume_span,
+                prefix_bytes,
+                output_dir,
+            ),
+            _ => return,
+        };
+    let range = match (start_block, end_block) {
+        (Some(start), Some(end)) => BlockRange::new(start, end).expect("Bad range"),
+        _ => BlockRange::new(0, u32::MAX).expect("Bad range"),
+    };
+    let layout = IndexLayout {
+        volume_span,
+        prefix_bytes: prefix_bytes as usize,
+    };
+    let chunks = ChunksDir::new(&chunks_dir).expect("Couldn't read chunks directory");
+    let manifest = write_appearance_index(&chunks, &range, &layout, &output_dir)
+        .expect("Could not export appearance index");
+    println!(
+        "{}",
+        serde_json::to_string(&manifest).expect("Could not create JSON response")
+    );
+}
+
+/// Encodes portal network content for an address or a chunk.
+fn portal_content(args: AppArgs) {
+    let (address, chunks_dir, start_block, end_block, chunk_file) = match args.endpoint {
+        AddressEndpoint::PortalContent {
+            address,
+            chunks_dir,
+            start_block,
+            end_block,
+            chunk_file,
+        } => (address, chunks_dir, start_block, end_block, chunk_file),
+        _ => return,
+    };
+    let encoded = match (address, chunks_dir, start_block, end_block, chunk_file) {
+        (Some(address), Some(dir), Some(start), Some(end), None) => {
+            let range = BlockRange::new(start, end).expect("Bad range");
+            let chunks = ChunksDir::new(&dir).expect("Couldn't read chunks directory");
+            let (key, value) = address_appearances_content(&chunks, &address, &range)
+                .expect("Could not encode appearances");
+            EncodedContent::new(&key, &value)
+        }
+        (None, None, None, None, Some(file)) => {
+            let chunk = ChunkFile::new(&file).expect("Couldn't read chunk file name");
+            let (key, value) = chunk_contents(&chunk).expect("Could not encode chunk");
+            EncodedContent::new(&key, &value)
+        }
+        _ => unreachable!(
+            "Clap requires an address with a chunks directory and range, or a chunk file"
+        ),
+    };
+    println!(
+        "{}",
+        serde_json::to_string(&encoded).expect("Could not create JSON response")
+    );
+}
+
+/// Checks chunk (and bloom) files against the manifest CIDs.
+fn verify_cids(args: AppArgs) {
+    let (chunks_dir, manifest, blooms_dir) = match args.endpoint {
+        AddressEndpoint::VerifyCids {
+            chunks_dir,
+            manifest,
+            blooms_dir,
+        } => (chunks_dir, manifest, blooms_dir),
+        _ => return,
+    };
+    let manifest = Manifest::from_file(&manifest).expect("Couldn't read manifest");
+    let chunks = ChunksDir::new(&chunks_dir).expect("Couldn't read chunks directory");
+    let mut report = manifest
+        .verify_index_files(&chunks)
+        .expect("Could not verify chunks");
+    if let Some(blooms_dir) = blooms_dir {
+        report.extend(
+            manifest
+                .verify_bloom_files(&blooms_dir)
+                .expect("Could not verify blooms"),
+        );
+    }
+    println!(
+        "{}",
+        serde_json::to_string(&report).expect("Could not create JSON response")
+    );
+}
+
+/// Creates a manifest for local chunks and blooms.
+fn generate_manifest(args: AppArgs) {
+    let chain = args.chain_config();
+    let (chunks_dir, blooms_dir, specification, manifest_version) = match args.endpoint {
+        AddressEndpoint::GenerateManifest {
+            chunks_dir,
+            blooms_dir,
+            specification,
+            manifest_version,
+        } => (chunks_dir, blooms_dir, specification, manifest_version),
+        _ => return,
+    };
+    let chunks = ChunksDir::new(&chunks_dir).expect("Couldn't read chunks directory");
+    let manifest = Manifest::from_chunks(
+        &chunks,
+        &blooms_dir,
+        &manifest_version,
+        &chain.name,
+        &specification,
+    )
+    .expect("Could not generate manifest");
+    println!(
+        "{}",
+        serde_json::to_string(&manifest).expect("Could not create JSON response")
+    );
+}
+
+/// Compares a manifest with an older manifest or local chunks.
+fn manifest_diff(args: AppArgs) {
+    let (manifest, old_manifest, chunks_dir) = match args.endpoint {
+        AddressEndpoint::ManifestDiff {
+            manifest,
+            old_manifest,
+            chunks_dir,
+        } => (manifest, old_manifest, chunks_dir),
+        _ => return,
+    };
+    let manifest = Manifest::from_file(&manifest).expect("Couldn't read manifest");
+    let diff = match (old_manifest, chunks_dir) {
+        (Some(path), _) => {
+            let older = Manifest::from_file(&path).expect("Couldn't read old manifest");
+            manifest.diff_from(&older)
+        }
+        (None, Some(dir)) => {
+            let chunks = ChunksDir::new(&dir).expect("Couldn't read chunks directory");
+            manifest.diff_from_dir(&chunks)
+        }
+        (None, None) => unreachable!("Clap requires an old manifest or chunks directory"),
+    }
+    .expect("Could not compare manifests");
+    println!(
+        "{}",
+        serde_json::to_string(&diff).expect("Could not create JSON response")
+    );
+}
+
+/// Writes manifest chunks from a CAR archive.
+fn unpack_car(args: AppArgs) {
+    let (car, manifest, output_dir, blooms_dir) = match args.endpoint {
+        AddressEndpoint::UnpackCar {
+            car,
+            manifest,
+            output_dir,
+            blooms_dir,
+        } => (car, manifest, output_dir, blooms_dir),
+        _ => return,
+    };
+    let manifest = Manifest::from_file(&manifest).expect("Couldn't read manifest");
+    let mut archive = CarArchive::from_file(&car).expect("Couldn't read CAR file");
+    let unpacked = unpack_chunks(&mut archive, &manifest, &output_dir, blooms_dir.as_deref())
+        .expect("Could not unpack chunks");
+    println!(
+        "{}",
+        serde_json::to_string(&unpacked).expect("Could not create JSON response")
+    );
+}
+
+/// Reads the manifest CID from the Unchained Index contract.
+#[cfg(feature = "http")]
+fn read_manifest_cid(args: AppArgs) {
+    let chain = args.chain_config();
+    let (rpc_url, publisher) = match args.endpoint {
+        AddressEndpoint::ManifestCid { rpc_url, publisher } => (rpc_url, publisher),
+        _ => return,
+    };
+    let cid = manifest_cid(&rpc_url, &publisher, &chain.name).expect("Couldn't read manifest CID");
+    println!(
+        "{}",
+        serde_json::to_string(&cid).expect("Could not create JSON response")
+    );
+}
+
+/// Downloads manifest chunks from an IPFS gateway.
+#[cfg(feature = "http")]
+fn download(args: AppArgs) {
+    let chain = args.chain_config();
+    let (
+        manifest,
+        cid,
+        rpc_url,
+        publisher,
+        gateway,
+        start_block,
+        end_block,
+        output_dir,
+        blooms_dir,
+        unchained_dir,
+    ) = match args.endpoint {
+        AddressEndpoint::Download {
+            manifest,
+            manifest_cid: cid,
+            rpc_url,
+            publisher,
+            gateway,
+            start_block,
+            end_block,
+            output_dir,
+            blooms_dir,
+            unchained_dir,
+        } => (
+            manifest,
+            cid,
+            rpc_url,
+            publisher,
+            gateway,
+            start_block,
+            end_block,
+            output_dir,
+            blooms_dir,
+            unchained_dir,
+        ),
+        _ => return,
+    };
+    let range = match (start_block, end_block) {
+        (Some(start), Some(end)) => BlockRange::new(start, end).expect("Bad range"),
+        _ => BlockRange::new(0, u32::MAX).expect("Bad range"),
+    };
+    let gateway = HttpGateway::new(&gateway);
+    let manifest = match (manifest, cid, rpc_url) {
+        (Some(path), _, _) => Manifest::from_file(&path).expect("Couldn't read manifest"),
+        (None, Some(cid), _) => fetch_manifest(&gateway, &cid).expect("Couldn't fetch manifest"),
+        (None, None, Some(url)) => {
+            let cid =
+                manifest_cid(&url, &publisher, &chain.name).expect("Couldn't read manifest CID");
+            fetch_manifest(&gateway, &cid).expect("Couldn't fetch manifest")
+        }
+        (None, None, None) => unreachable!("Clap requires a manifest source"),
+    };
+    let (output_dir, blooms_dir) = match (output_dir, unchained_dir) {
+        (Some(dir), _) => (dir, blooms_dir),
+        (None, Some(dir)) => (chain.chunks_dir(&dir), Some(chain.blooms_dir(&dir))),
+        (None, None) => unreachable!("Clap requires a destination"),
+    };
+    let downloaded = download_chunks(
+        &gateway,
+        &manifest,
+        &range,
+        &output_dir,
+        blooms_dir.as_deref(),
+    )
+    .expect("Could not download chunks");
+    println!(
+        "{}",
+        serde_json::to_string(&downloaded).expect("Could not create JSON response")
+    );
+}
+
+/// Loads chunks into a SQLite database.
+#[cfg(feature = "sqlite")]
+fn sqlite_load(args: AppArgs) {
+    let (chunks_dir, database) = match args.endpoint {
+        AddressEndpoint::SqliteLoad {
+            chunks_dir,
+            database,
+        } => (chunks_dir, database),
+        _ => return,
+    };
+    let chunks = ChunksDir::new(&chunks_dir).expect("Couldn't read chunks directory");
+    let mut db = AppearanceDb::open(&database).expect("Couldn't open database");
+    let summary = db.load_chunks(&chunks).expect("Could not load chunks");
+    println!(
+        "{}",
+        serde_json::to_string(&summary).expect("Could not create JSON response")
+    );
+}
+
+/// Exports appearances to Parquet.
+#[cfg(feature = "parquet")]
+fn export_parquet(args: AppArgs) {
+    let chain = args.chain_config();
+    let (chunks_dir, chunk_file, start_block, end_block, output) = match args.endpoint {
+        AddressEndpoint::ExportParquet {
+            chunks_dir,
+            chunk_file,
+            start_block,
+            end_block,
+            output,
+        } => (chunks_dir, chunk_file, start_block, end_block, output),
+        _ => return,
+    };
+    let range = match (start_block, end_block) {
+        (Some(start), Some(end)) => BlockRange::new(start, end).expect("Bad range"),
+        _ => BlockRange::new(0, u32::MAX).expect("Bad range"),
+    };
+    let chunks: Vec<ChunkFile> = match (chunks_dir, chunk_file) {
+        (Some(dir), _) => ChunksDir::new(&dir)
+            .expect("Couldn't read chunks directory")
+            .for_range(&range)
+            .expect("No chunks in range")
+            .into_iter()
+            .cloned()
+            .collect(),
+        (None, Some(file)) => vec![ChunkFile::new(&file).expect("Couldn't read chunk file name")],
+        (None, None) => unreachable!("Clap requires a file or chunks directory"),
+    };
+    let relevant: Vec<&ChunkFile> = chunks.iter().collect();
+    let summary = unchained_utils::export::write_parquet(&relevant, &range, &chain, &output)
+        .expect("Could not export to parquet");
+    println!(
+        "{}",
+        serde_json::to_string(&summary).expect("Could not create JSON response")
+    );
+}
+
 /// For address_getAddressesInBlock
 fn generate_addresses_in_block(args: AppArgs) {
+    let chain = args.chain_config();
+    #[cfg(feature = "sqlite")]
+    let database = match &args.endpoint {
+        AddressEndpoint::GetAddressesInBlock { database, .. } => database.clone(),
+        _ => return,
+    };
     let (block, chunk_file) = match args.endpoint {
-        AddressEndpoint::GetAddressesInBlock { block, chunk_file } => (block, chunk_file),
+        AddressEndpoint::GetAddressesInBlock {
+            block, chunk_file, ..
+        } => (block, chunk_file),
         _ => return,
     };
+    #[cfg(feature = "sqlite")]
+    if let Some(database) = database {
+        let db = AppearanceDb::open(&database).expect("Couldn't open database");
+        let data = db
+            .addresses_in_block(block)
+            .expect("Could not query database");
+        let response = AddressesInBlockResponse::create(data, block, &args.address_case, &chain);
+        println!(
+            "{}",
+            serde_json::to_string(&response).expect("Could not create JSON response")
+        );
+        return;
+    }
+    let chunk_file = chunk_file.expect("Clap requires a source");
 
     let range = BlockRange::new(block, block).expect("Bad range");
     let mut file = UnchainedFile::from_file(chunk_file, range).expect("Couldn't read file");
     file.with_parsed(None)
         .expect("Could not add appearance data");
-    let response = AddressesInBlockResponse::create(file.parsed, block);
+    let response = AddressesInBlockResponse::create(file.parsed, block, &args.address_case, &chain);
     println!(
         "{}",
         serde_json::to_string(&response).expect("Could not create JSON response")
@@ -0,0 +1,265 @@
+//! Reads appearances exported by trueblocks-core in the various `chifra list --fmt` formats.
+//!
+//! The format is detected from the content:
+//! - JSON: `{"data": [...]}`, a bare array, newline delimited objects (NDJSON),
+//!   or several concatenated exports.
+//! - CSV: comma separated, optionally quoted, with a header row.
+//! - TXT: tab separated with a header row.
+
+use anyhow::{anyhow, bail, Context};
+use serde::{de::IgnoredAny, Deserialize};
+use serde_json::Value;
+use unchained_utils::Address;
+
+use crate::appearances::AppearanceData;
+
+/// Columns that hold appearance data.
+const ADDRESS: &str = "address";
+const BLOCK_NUMBER: &str = "blockNumber";
+const TRANSACTION_INDEX: &str = "transactionIndex";
+
+/// Columns that chifra may add to an export that are not needed.
+const IGNORED_COLUMNS: [&str; 2] = ["date", "timestamp"];
+
+/// An appearance in a JSON export.
+///
+/// Unknown fields are rejected, as they are for CSV and TXT columns.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct JsonAppearance {
+    address: Address,
+    block_number: u32,
+    transaction_index: u32,
+    #[serde(default, rename = "date")]
+    _date: Option<IgnoredAny>,
+    #[serde(default, rename = "timestamp")]
+    _timestamp: Option<IgnoredAny>,
+}
+
+impl From<JsonAppearance> for AppearanceData {
+    fn from(value: JsonAppearance) -> Self {
+        AppearanceData {
+            address: value.address,
+            block_number: value.block_number,
+            transaction_index: value.transaction_index,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SourceFormat {
+    Json,
+    Csv,
+    Txt,
+}
+
+impl SourceFormat {
+    /// Determines the export format from the file content.
+    pub fn detect(content: &str) -> anyhow::Result<Self> {
+        let trimmed = content.trim_start();
+        if trimmed.starts_with('{') || trimmed.starts_with('[') {
+            return Ok(SourceFormat::Json);
+        }
+        let Some(header) = trimmed.lines().next() else {
+            bail!("Source is empty")
+        };
+        if header.contains('\t') {
+            return Ok(SourceFormat::Txt);
+        }
+        if header.contains(',') {
+            return Ok(SourceFormat::Csv);
+        }
+        bail!("Unrecognised source format, header: {}", header)
+    }
+}
+
+/// Parses appearances from the content of a chifra export.
+pub fn parse_source(content: &str) -> anyhow::Result<Vec<AppearanceData>> {
+    match SourceFormat::detect(content)? {
+        SourceFormat::Json => parse_json(content),
+        SourceFormat::Csv => parse_delimited(content, ','),
+        SourceFormat::Txt => parse_delimited(content, '\t'),
+    }
+}
+
+/// Parses one or more JSON values from the content.
+///
+/// Each value may be a `{"data": [...]}` export, an array of appearances or
+/// a single appearance.
+fn parse_json(content: &str) -> anyhow::Result<Vec<AppearanceData>> {
+    let mut data: Vec<AppearanceData> = vec![];
+    for (n, value) in serde_json::Deserializer::from_str(content)
+        .into_iter::<Value>()
+        .enumerate()
+    {
+        let value = value.with_context(|| format!("Invalid JSON in value {}", n))?;
+        data.extend(json_value_appearances(value).with_context(|| format!("In JSON value {}", n))?);
+    }
+    Ok(data)
+}
+
+fn json_value_appearances(value: Value) -> anyhow::Result<Vec<AppearanceData>> {
+    let appearances: Vec<JsonAppearance> = match value {
+        Value::Object(mut map) => match map.remove("data") {
+            Some(inner) => serde_json::from_value(inner)?,
+            None => vec![serde_json::from_value(Value::Object(map))?],
+        },
+        Value::Array(_) => serde_json::from_value(value)?,
+        _ => bail!("Expected an object or array, found {}", value),
+    };
+    Ok(appearances.into_iter().map(AppearanceData::from).collect())
+}
+
+/// Parses a table with a header row, as produced by `--fmt csv` or `--fmt txt`.
+///
+/// Rows identical to the header (from concatenated exports) are skipped.
+fn parse_delimited(content: &str, delimiter: char) -> anyhow::Result<Vec<AppearanceData>> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = lines.next().ok_or_else(|| anyhow!("Source is empty"))?;
+    let columns: Vec<String> = split_row(header, delimiter);
+    for column in &columns {
+        if ![ADDRESS, BLOCK_NUMBER, TRANSACTION_INDEX].contains(&column.as_str())
+            && !IGNORED_COLUMNS.contains(&column.as_str())
+        {
+            bail!(
+                "Unknown column '{}'. Expected {}, {} and {} (optionally {})",
+                column,
+                ADDRESS,
+                BLOCK_NUMBER,
+                TRANSACTION_INDEX,
+                IGNORED_COLUMNS.join(", ")
+            )
+        }
+    }
+    let position = |name: &str| {
+        columns
+            .iter()
+            .position(|c| c == name)
+            .ok_or_else(|| anyhow!("Missing column '{}' in header: {}", name, header))
+    };
+    let address_col = position(ADDRESS)?;
+    let block_col = position(BLOCK_NUMBER)?;
+    let index_col = position(TRANSACTION_INDEX)?;
+
+    let mut data: Vec<AppearanceData> = vec![];
+    for (n, line) in lines {
+        let fields = split_row(line, delimiter);
+        if fields == columns {
+            continue;
+        }
+        if fields.len() != columns.len() {
+            bail!(
+                "Line {} has {} fields, but header has {}",
+                n + 1,
+                fields.len(),
+                columns.len()
+            )
+        }
+        let address = Address::from_hex(&fields[address_col])
+            .with_context(|| format!("Line {} has an invalid address", n + 1))?;
+        let block_number = fields[block_col]
+            .parse::<u32>()
+            .with_context(|| format!("Line {} has an invalid block number", n + 1))?;
+        let transaction_index = fields[index_col]
+            .parse::<u32>()
+            .with_context(|| format!("Line {} has an invalid transaction index", n + 1))?;
+        data.push(AppearanceData {
+            address,
+            block_number,
+            transaction_index,
+        });
+    }
+    Ok(data)
+}
+
+/// Splits a row into fields, removing whitespace and surrounding quotes.
+fn split_row(line: &str, delimiter: char) -> Vec<String> {
+    line.split(delimiter)
+        .map(|field| field.trim().trim_matches('"').to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS_A: &str = "0x30a4639850b3ddeaaca4f06280aa751682f11382";
+    const ADDRESS_B: &str = "0x0000000000000000000000000000000000000001";
+
+    fn appearance(address: &str, block_number: u32, transaction_index: u32) -> AppearanceData {
+        AppearanceData {
+            address: Address::from_hex(address).unwrap(),
+            block_number,
+            transaction_index,
+        }
+    }
+
+    #[test]
+    fn detects_formats() {
+        let cases = [
+            ("{\"data\": []}", SourceFormat::Json),
+            ("  [{}]", SourceFormat::Json),
+            ("{}\n{}\n", SourceFormat::Json),
+            ("\"address\",\"blockNumber\"\n", SourceFormat::Csv),
+            ("address\tblockNumber\n", SourceFormat::Txt),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(
+                SourceFormat::detect(content).unwrap(),
+                expected,
+                "{content}"
+            );
+        }
+        assert!(SourceFormat::detect("  \n").is_err());
+        assert!(SourceFormat::detect("address blockNumber\n").is_err());
+    }
+
+    #[test]
+    fn parses_each_format() {
+        let expected = vec![
+            appearance(ADDRESS_A, 17190873, 5),
+            appearance(ADDRESS_B, 1, 99999),
+        ];
+        let json = format!(
+            "{{\"data\": [{{\"address\": \"{ADDRESS_A}\", \"blockNumber\": 17190873, \"transactionIndex\": 5, \"date\": \"2023-05-04\"}}, {{\"address\": \"{ADDRESS_B}\", \"blockNumber\": 1, \"transactionIndex\": 99999, \"timestamp\": 1}}]}}"
+        );
+        let ndjson = format!(
+            "{{\"address\": \"{ADDRESS_A}\", \"blockNumber\": 17190873, \"transactionIndex\": 5}}\n{{\"address\": \"{ADDRESS_B}\", \"blockNumber\": 1, \"transactionIndex\": 99999}}\n"
+        );
+        let csv = format!(
+            "\"blockNumber\",\"transactionIndex\",\"address\",\"date\"\n17190873,5,\"{ADDRESS_A}\",\"2023-05-04\"\n1,99999,\"{ADDRESS_B}\",\"2015-07-30\"\n"
+        );
+        let txt = format!(
+            "blockNumber\ttransactionIndex\taddress\n17190873\t5\t{ADDRESS_A}\n1\t99999\t{ADDRESS_B}\n"
+        );
+        for content in [json, ndjson, csv, txt] {
+            assert_eq!(parse_source(&content).unwrap(), expected, "{content}");
+        }
+    }
+
+    #[test]
+    fn skips_repeated_headers() {
+        let first = format!("blockNumber,transactionIndex,address\n17190873,5,{ADDRESS_A}\n");
+        let second = format!("blockNumber,transactionIndex,address\n1,99999,{ADDRESS_B}\n");
+        let expected = vec![
+            appearance(ADDRESS_A, 17190873, 5),
+            appearance(ADDRESS_B, 1, 99999),
+        ];
+        assert_eq!(parse_source(&(first.clone() + &second)).unwrap(), expected);
+        let tabbed = (first + &second).replace(',', "\t");
+        assert_eq!(parse_source(&tabbed).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_unknown_columns() {
+        let csv = format!("blockNumber,transactionIndex,address,value\n1,2,{ADDRESS_A},3\n");
+        assert!(parse_source(&csv).is_err());
+        let json = format!(
+            "[{{\"address\": \"{ADDRESS_A}\", \"blockNumber\": 1, \"transactionIndex\": 2, \"value\": 3}}]"
+        );
+        assert!(parse_source(&json).is_err());
+    }
+}
@@ -1,15 +1,56 @@
+use anyhow::bail;
+use unchained_utils::{chain::ChainConfig, structure::LocationKind, Address, BlockRange};
+
+use crate::cli::{AddressCase, RangeParam};
+
 /// Converts a transaction id in UnchainedIndex format to one compatible with Appearances
-/// specification.
+/// specification, using the sentinels of the chain.
 ///
 /// See also: <https://github.com/ethereum/execution-apis/pull/456>
-pub fn unchained_index_to_location(tx: u32) -> Option<String> {
-    match tx {
-        99999 => Some("miner".to_string()),
-        99998 => Some("uncle".to_string()),
-        99997 => None, // stores address 0xdeaddead..., can ignore
-        99996 => None, // External (To be confirmed: used for gnosis chain somehow)
+pub fn unchained_index_to_location(tx: u32, chain: &ChainConfig) -> Option<String> {
+    match chain.location_kind(tx) {
+        LocationKind::Miner => Some("miner".to_string()),
+        LocationKind::Uncle => Some("uncle".to_string()),
+        LocationKind::NullRecipient => None, // stores address 0xdeaddead..., can ignore
+        LocationKind::External => None, // External (To be confirmed: used for gnosis chain somehow)
         // 99995 => Some("withdrawals".to_string()), // TBC: Future inclusion in Unchained index
         // https://github.com/TrueBlocks/trueblocks-core/issues/3122
-        _ => Some(format!("{:#x}", tx)),
+        LocationKind::Transaction => Some(format!("{:#x}", tx)),
     }
 }
+
+/// Sentinel transaction indices of the chain that have no location.
+///
+/// These are left out of responses, so they are excluded before pagination.
+pub fn hidden_sentinels(chain: &ChainConfig) -> Vec<u32> {
+    chain
+        .sentinels
+        .iter()
+        .map(|s| s.index)
+        .filter(|index| unchained_index_to_location(*index, chain).is_none())
+        .collect()
+}
+
+/// Formats an address as a 0x-prefixed string in the requested case.
+pub fn format_address(address: &Address, case: &AddressCase) -> String {
+    match case {
+        AddressCase::Lower => address.to_string(),
+        AddressCase::Checksum => address.to_checksum(),
+    }
+}
+
+/// Converts block range CLI parameters into a range of blocks.
+pub fn block_range(
+    range: &RangeParam,
+    start_block: Option<u32>,
+    end_block: Option<u32>,
+) -> anyhow::Result<BlockRange> {
+    let desired = match (range, start_block, end_block) {
+        (RangeParam::All, _, _) => BlockRange::new(0, u32::MAX)?,
+        (RangeParam::Single, Some(start), _) => BlockRange::new(start, start)?,
+        (RangeParam::Custom, Some(start), Some(end)) => BlockRange::new(start, end)?,
+        (_, None, _) => bail!("Must provide start block for specific blocks"),
+        (_, _, None) => bail!("Must provide end block for custom block range"),
+    };
+    Ok(desired)
+}
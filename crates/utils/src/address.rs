@@ -0,0 +1,253 @@
+//! Fixed-size Ethereum address used in the Unchained Index.
+
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tiny_keccak::{Hasher, Keccak};
+
+use super::constants::ADDR;
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum AddressError {
+    #[error("Address {address} has {length} bytes, but expected {ADDR}")]
+    InvalidLength { address: String, length: usize },
+    #[error("Address {address} is not valid hex {source}")]
+    InvalidHex {
+        address: String,
+        source: hex::FromHexError,
+    },
+    #[error(
+        "Address {address} has mixed case but an invalid EIP-55 checksum (expected {expected})"
+    )]
+    InvalidChecksum { address: String, expected: String },
+}
+
+/// An Ethereum address (20 bytes).
+///
+/// Displayed and serialized as a lowercase, 0x-prefixed hex string.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address(pub [u8; ADDR]);
+
+impl Address {
+    /// Creates an address from a slice that must be exactly 20 bytes.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, AddressError> {
+        let inner: [u8; ADDR] = bytes.try_into().map_err(|_| AddressError::InvalidLength {
+            address: hex::encode(bytes),
+            length: bytes.len(),
+        })?;
+        Ok(Address(inner))
+    }
+    /// Parses a hex address, with or without the 0x prefix.
+    ///
+    /// All-lowercase and all-uppercase addresses are accepted as is. Mixed case
+    /// is treated as an EIP-55 checksum and must be valid.
+    pub fn from_hex(address: &str) -> Result<Self, AddressError> {
+        let stripped = address
+            .strip_prefix("0x")
+            .or_else(|| address.strip_prefix("0X"))
+            .unwrap_or(address);
+        let bytes = hex::decode(stripped).map_err(|e| AddressError::InvalidHex {
+            address: address.to_owned(),
+            source: e,
+        })?;
+        let parsed = Address::from_slice(&bytes).map_err(|_| AddressError::InvalidLength {
+            address: address.to_owned(),
+            length: bytes.len(),
+        })?;
+        let has_lower = stripped.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = stripped.chars().any(|c| c.is_ascii_uppercase());
+        if has_lower && has_upper {
+            let expected = parsed.to_checksum();
+            if expected[2..] != *stripped {
+                return Err(AddressError::InvalidChecksum {
+                    address: address.to_owned(),
+                    expected,
+                });
+            }
+        }
+        Ok(parsed)
+    }
+    /// The raw address bytes.
+    pub fn as_bytes(&self) -> &[u8; ADDR] {
+        &self.0
+    }
+    /// True if the address begins with the given bytes.
+    pub fn starts_with(&self, leading: &[u8]) -> bool {
+        self.0.starts_with(leading)
+    }
+    /// Lowercase hex without the 0x prefix.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+    /// Mixed-case 0x-prefixed hex with an EIP-55 checksum.
+    ///
+    /// See also: <https://eips.ethereum.org/EIPS/eip-55>
+    pub fn to_checksum(&self) -> String {
+        let lower = self.to_hex();
+        let mut hash = [0u8; 32];
+        let mut hasher = Keccak::v256();
+        hasher.update(lower.as_bytes());
+        hasher.finalize(&mut hash);
+
+        let mut checksummed = String::with_capacity(2 + 2 * ADDR);
+        checksummed.push_str("0x");
+        for (i, c) in lower.chars().enumerate() {
+            // Each hex char is paired with a nibble of the hash.
+            let nibble = match i % 2 {
+                0 => hash[i / 2] >> 4,
+                _ => hash[i / 2] & 0x0f,
+            };
+            if nibble >= 8 {
+                checksummed.push(c.to_ascii_uppercase());
+            } else {
+                checksummed.push(c);
+            }
+        }
+        checksummed
+    }
+}
+
+impl From<[u8; ADDR]> for Address {
+    fn from(value: [u8; ADDR]) -> Self {
+        Address(value)
+    }
+}
+
+impl AsRef<[u8]> for Address {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Address::from_hex(s)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", self.to_hex())
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Address(0x{})", self.to_hex())
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Address::from_hex(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test vectors from EIP-55.
+    const CHECKSUMMED: [&str; 8] = [
+        "0x52908400098527886E0F7030069857D2E4169EE7",
+        "0x8617E340B3D01FA5F11F306F4090FD50E238070D",
+        "0xde709f2102306220921060314715629080e2fb77",
+        "0x27b1fdb04752bbc536007a920d24acb045561c26",
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ];
+
+    #[test]
+    fn checksum_matches_eip55_vectors() {
+        for expected in CHECKSUMMED {
+            let address = Address::from_hex(&expected.to_lowercase()).unwrap();
+            assert_eq!(address.to_checksum(), expected);
+            assert_eq!(Address::from_hex(expected).unwrap(), address);
+        }
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        // Case of the final letter flipped.
+        let address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD";
+        assert!(matches!(
+            Address::from_hex(address),
+            Err(AddressError::InvalidChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn prefixless_and_uppercase_are_accepted() {
+        let expected = Address::from_hex("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+        for address in [
+            "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED",
+            "0X5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED",
+            "5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED",
+        ] {
+            assert_eq!(Address::from_hex(address).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn wrong_length_is_rejected() {
+        assert!(matches!(
+            Address::from_hex("0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea"),
+            Err(AddressError::InvalidLength { length: 19, .. })
+        ));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let bytes = [0xab; ADDR];
+        let address = Address::from_slice(&bytes).unwrap();
+        assert_eq!(address, Address::from(bytes));
+        assert_eq!(address.as_bytes(), &bytes);
+        assert!(address.starts_with(&[0xab, 0xab]));
+        assert!(!address.starts_with(&[0xab, 0xcd]));
+        assert!(matches!(
+            Address::from_slice(&bytes[..ADDR - 1]),
+            Err(AddressError::InvalidLength { length: 19, .. })
+        ));
+    }
+
+    #[test]
+    fn displays_and_serializes_lowercase() {
+        let address: Address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+            .parse()
+            .unwrap();
+        let lower = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+        assert_eq!(address.to_string(), lower);
+        assert_eq!(address.to_hex(), &lower[2..]);
+        assert_eq!(format!("{address:?}"), format!("Address({lower})"));
+
+        let json = serde_json::to_string(&address).unwrap();
+        assert_eq!(json, format!("\"{lower}\""));
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), address);
+        assert!(serde_json::from_str::<Address>("\"0x5aaeb6\"").is_err());
+    }
+
+    #[test]
+    fn orders_by_bytes() {
+        let mut addresses = [[0x02; ADDR], [0x00; ADDR], [0x01; ADDR]].map(Address::from);
+        addresses.sort();
+        assert_eq!(
+            addresses,
+            [[0x00; ADDR], [0x01; ADDR], [0x02; ADDR]].map(Address::from)
+        );
+        assert_eq!(Address::default(), Address([0; ADDR]));
+    }
+}
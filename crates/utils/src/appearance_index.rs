@@ -0,0 +1,525 @@
+//! Converts chunk files into a sharded address-appearance-index.
+//!
+//! The chunk files group appearances by block range. The address-appearance-index
+//! (as used by min-know) regroups them so that a user only needs the data for
+//! their own addresses:
+//! - Chapters: addresses sharing a leading prefix (one byte by default, "0x4e").
+//! - Volumes: fixed spans of blocks (100_000 by default).
+//!
+//! Each chapter of each volume that has appearances is one file, SSZ encoded
+//! then snappy compressed (frame format):
+//! ```text
+//! chapter_0x4e/chapter_0x4e_volume_017_100_000.ssz_snappy
+//! ```
+//! A `manifest.json` file lists the volumes and files. A chapter missing from
+//! a listed volume has no appearances.
+//!
+//! SSZ schema of a file (min-know `AddressIndexVolumeChapter`), where N is the
+//! number of prefix bytes (min-know uses 1, 2 is an extension):
+//! ```text
+//! ChapterVolume {
+//!     address_prefix: Vector[uint8, N],
+//!     identifier: VolumeIdentifier,
+//!     addresses: List[AddressAppearances, MAX_ADDRESSES_PER_VOLUME],
+//! }
+//! VolumeIdentifier {
+//!     oldest_block: uint32,
+//! }
+//! AddressAppearances {
+//!     address: Vector[uint8, 20],
+//!     appearances: List[AppearanceTx, MAX_APPEARANCES_PER_ADDRESS],
+//! }
+//! AppearanceTx {
+//!     block: uint32,
+//!     index: uint32,
+//! }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    address::Address,
+    constants::ADDR,
+    files::{ChunkFile, ChunksDir},
+    parse::{BlockRange, ParseError, UnchainedFile},
+    ssz::{check_max, decode_container, ContainerEncoder, Ssz, SszError, OFFSET},
+    structure::{AddressData, TransactionId},
+    write::merge_address_data,
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum AppearanceIndexError {
+    #[error("File {filename} could not be created {source}")]
+    FileCreator {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("File {filename} could not be opened {source}")]
+    FileOpener {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Unable to read {filename} {source}")]
+    Read {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Unable to write to {filename} {source}")]
+    Write {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Volume span must be at least 1 block")]
+    InvalidVolumeSpan,
+    #[error("Address prefix must be 1 to {max} bytes, found {found}")]
+    InvalidPrefixLength { found: usize, max: usize },
+    #[error("No chunks found in range {0:?}")]
+    NoChunks(BlockRange),
+    #[error("Unable to serialize manifest {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+    #[error("SszError {0}")]
+    SszError(#[from] SszError),
+}
+
+/// Maximum number of addresses in one chapter of a volume.
+pub const MAX_ADDRESSES_PER_VOLUME: usize = 1 << 30;
+
+/// Maximum number of appearances of one address in one volume.
+pub const MAX_APPEARANCES_PER_ADDRESS: usize = 1 << 30;
+
+/// Default number of blocks in a volume.
+pub const DEFAULT_VOLUME_SPAN: u32 = 100_000;
+
+/// Version of the file layout, recorded in the manifest.
+pub const SPEC_VERSION: &str = "0.1.0";
+
+/// Name of the manifest file in the output directory.
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// How appearances are divided into files.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexLayout {
+    /// Number of blocks in each volume. Volume n covers blocks n*span to (n+1)*span - 1.
+    pub volume_span: u32,
+    /// Number of leading address bytes that define a chapter.
+    pub prefix_bytes: usize,
+}
+
+impl Default for IndexLayout {
+    fn default() -> Self {
+        IndexLayout {
+            volume_span: DEFAULT_VOLUME_SPAN,
+            prefix_bytes: 1,
+        }
+    }
+}
+
+impl IndexLayout {
+    fn check(&self) -> Result<(), AppearanceIndexError> {
+        if self.volume_span == 0 {
+            return Err(AppearanceIndexError::InvalidVolumeSpan);
+        }
+        // Two bytes already gives 65_536 files per volume.
+        let max = 2;
+        if self.prefix_bytes == 0 || self.prefix_bytes > max {
+            return Err(AppearanceIndexError::InvalidPrefixLength {
+                found: self.prefix_bytes,
+                max,
+            });
+        }
+        Ok(())
+    }
+    /// Block range of the volume containing the block.
+    pub fn volume_of(&self, block: u32) -> BlockRange {
+        let old = block - block % self.volume_span;
+        BlockRange {
+            old,
+            new: old.saturating_add(self.volume_span - 1),
+        }
+    }
+}
+
+/// Name of the directory holding a chapter, e.g. "chapter_0x4e".
+pub fn chapter_dirname(prefix: &[u8]) -> String {
+    format!("chapter_0x{}", hex::encode(prefix))
+}
+
+/// Path of a chapter file relative to the index directory.
+///
+/// E.g. "chapter_0x4e/chapter_0x4e_volume_017_100_000.ssz_snappy"
+pub fn chapter_file_path(prefix: &[u8], volume: &BlockRange) -> PathBuf {
+    let chapter = chapter_dirname(prefix);
+    let old = format!("{:09}", volume.old);
+    let filename = format!(
+        "{}_volume_{}_{}_{}.ssz_snappy",
+        chapter,
+        &old[0..3],
+        &old[3..6],
+        &old[6..9]
+    );
+    PathBuf::from(chapter).join(filename)
+}
+
+/// Identifies a volume by its first block.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VolumeIdentifier {
+    pub oldest_block: u32,
+}
+
+/// Container { oldest_block: uint32 }
+impl Ssz for VolumeIdentifier {
+    const FIXED_LEN: Option<usize> = Some(4);
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        self.oldest_block.ssz_append(buf);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        Ok(VolumeIdentifier {
+            oldest_block: u32::from_ssz_bytes(bytes)?,
+        })
+    }
+}
+
+/// Appearances of the addresses in one chapter during one volume.
+///
+/// The prefix is a fixed size vector whose length depends on the layout, so
+/// decoding needs the prefix length (see [ChapterVolume::from_ssz_bytes]).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChapterVolume {
+    pub address_prefix: Vec<u8>,
+    pub identifier: VolumeIdentifier,
+    /// Sorted by address, with sorted appearances.
+    pub addresses: Vec<AddressData>,
+}
+
+/// Container { address: Vector[uint8, 20], appearances: List[AppearanceTx, N] }
+impl Ssz for AddressData {
+    const FIXED_LEN: Option<usize> = None;
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        let mut encoder = ContainerEncoder::new(Address::fixed_part_len() + 4);
+        encoder.append(&self.address);
+        encoder.append(&self.appearances);
+        encoder.finish(buf);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        let fields = decode_container(bytes, &[Some(ADDR), None])?;
+        let appearances = Vec::<TransactionId>::from_ssz_bytes(fields[1])?;
+        check_max(&appearances, MAX_APPEARANCES_PER_ADDRESS)?;
+        Ok(AddressData {
+            address: Address::from_ssz_bytes(fields[0])?,
+            appearances,
+        })
+    }
+}
+
+impl ChapterVolume {
+    /// SSZ encoding, with the prefix as a fixed size vector.
+    pub fn to_ssz_bytes(&self) -> Vec<u8> {
+        let fixed_len = self.address_prefix.len() + VolumeIdentifier::fixed_part_len() + OFFSET;
+        let mut encoder = ContainerEncoder::new(fixed_len);
+        for byte in &self.address_prefix {
+            encoder.append(byte);
+        }
+        encoder.append(&self.identifier);
+        encoder.append(&self.addresses);
+        let mut buf = vec![];
+        encoder.finish(&mut buf);
+        buf
+    }
+    /// Decodes a chapter whose prefix has the given number of bytes.
+    pub fn from_ssz_bytes(bytes: &[u8], prefix_bytes: usize) -> Result<Self, SszError> {
+        let fields = decode_container(
+            bytes,
+            &[
+                Some(prefix_bytes),
+                VolumeIdentifier::FIXED_LEN,
+                <Vec<AddressData>>::FIXED_LEN,
+            ],
+        )?;
+        let addresses = Vec::<AddressData>::from_ssz_bytes(fields[2])?;
+        check_max(&addresses, MAX_ADDRESSES_PER_VOLUME)?;
+        Ok(ChapterVolume {
+            address_prefix: fields[0].to_vec(),
+            identifier: VolumeIdentifier::from_ssz_bytes(fields[1])?,
+            addresses,
+        })
+    }
+    /// Reads a snappy compressed SSZ chapter file.
+    pub fn from_file(path: &Path, prefix_bytes: usize) -> Result<Self, AppearanceIndexError> {
+        let file = File::open(path).map_err(|e| AppearanceIndexError::FileOpener {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        let mut bytes = vec![];
+        snap::read::FrameDecoder::new(BufReader::new(file))
+            .read_to_end(&mut bytes)
+            .map_err(|e| AppearanceIndexError::Read {
+                source: e,
+                filename: path.to_path_buf(),
+            })?;
+        Ok(ChapterVolume::from_ssz_bytes(&bytes, prefix_bytes)?)
+    }
+    /// Writes the chapter as a snappy compressed SSZ file.
+    pub fn write(&self, path: &Path) -> Result<u64, AppearanceIndexError> {
+        let write_err = |e| AppearanceIndexError::Write {
+            source: e,
+            filename: path.to_path_buf(),
+        };
+        let file = File::create(path).map_err(|e| AppearanceIndexError::FileCreator {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        let mut encoder = snap::write::FrameEncoder::new(BufWriter::new(file));
+        encoder.write_all(&self.to_ssz_bytes()).map_err(write_err)?;
+        let mut inner = encoder
+            .into_inner()
+            .map_err(|e| write_err(e.into_error()))?;
+        inner.flush().map_err(write_err)?;
+        Ok(fs::metadata(path).map_err(write_err)?.len())
+    }
+}
+
+/// Details of the index files, written as `manifest.json`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexManifest {
+    pub spec_version: String,
+    pub layout: IndexLayout,
+    pub volumes: Vec<VolumeEntry>,
+}
+
+/// A volume and its chapter files.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeEntry {
+    pub range: BlockRange,
+    /// True if chunks covered every block of the volume.
+    ///
+    /// Incomplete volumes (e.g. at the chain tip) are rewritten by a later export.
+    pub complete: bool,
+    pub chapters: Vec<ChapterEntry>,
+}
+
+/// A single chapter file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChapterEntry {
+    /// Address prefix, e.g. "0x4e".
+    pub prefix: String,
+    /// Path relative to the index directory.
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub n_addresses: usize,
+    pub n_appearances: usize,
+}
+
+/// Converts the chunks in a directory into address-appearance-index files.
+///
+/// Only blocks in the desired range are included. Chapters without appearances
+/// are not written: the manifest lists every volume that intersects the chunks,
+/// so a missing address is still distinguishable from missing data. The
+/// manifest is written to the output directory and returned.
+pub fn write_appearance_index(
+    chunks: &ChunksDir,
+    desired: &BlockRange,
+    layout: &IndexLayout,
+    out: &Path,
+) -> Result<IndexManifest, AppearanceIndexError> {
+    layout.check()?;
+    let relevant = chunks
+        .for_range(desired)
+        .ok_or(AppearanceIndexError::NoChunks(*desired))?;
+    let first = desired.old.max(relevant[0].range.old);
+    let last = desired.new.min(relevant[relevant.len() - 1].range.new);
+
+    let mut volumes: Vec<VolumeEntry> = vec![];
+    let mut volume = layout.volume_of(first);
+    loop {
+        let included = BlockRange {
+            old: volume.old.max(desired.old),
+            new: volume.new.min(desired.new),
+        };
+        if let Some(volume_chunks) = chunks.for_range(&included) {
+            let complete = included == volume && is_covered(&volume, &volume_chunks);
+            let mut data: Vec<AddressData> = vec![];
+            for chunk in volume_chunks {
+                let mut file = UnchainedFile::from_file(chunk.path.to_owned(), included)?;
+                data.extend(file.read_all()?);
+            }
+            let chapters = write_volume(merge_address_data(data), &volume, layout, out)?;
+            volumes.push(VolumeEntry {
+                range: volume,
+                complete,
+                chapters,
+            });
+        }
+        if volume.new >= last {
+            break;
+        }
+        volume = layout.volume_of(volume.new + 1);
+    }
+
+    let manifest = IndexManifest {
+        spec_version: SPEC_VERSION.to_string(),
+        layout: *layout,
+        volumes,
+    };
+    let path = out.join(MANIFEST_FILENAME);
+    let file = File::create(&path).map_err(|e| AppearanceIndexError::FileCreator {
+        source: e,
+        filename: path.to_path_buf(),
+    })?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, &manifest)?;
+    writer.flush().map_err(|e| AppearanceIndexError::Write {
+        source: e,
+        filename: path.to_path_buf(),
+    })?;
+    Ok(manifest)
+}
+
+/// True if the chunks (sorted by block) have no gaps across the volume.
+fn is_covered(volume: &BlockRange, chunks: &[&ChunkFile]) -> bool {
+    let mut next = volume.old;
+    for chunk in chunks {
+        if chunk.range.old > next {
+            return false;
+        }
+        if chunk.range.new >= volume.new {
+            return true;
+        }
+        next = next.max(chunk.range.new + 1);
+    }
+    false
+}
+
+/// Splits sorted address data into chapters and writes one file per chapter.
+fn write_volume(
+    data: Vec<AddressData>,
+    volume: &BlockRange,
+    layout: &IndexLayout,
+    out: &Path,
+) -> Result<Vec<ChapterEntry>, AppearanceIndexError> {
+    let mut grouped: BTreeMap<Vec<u8>, Vec<AddressData>> = BTreeMap::new();
+    for entry in data {
+        let prefix = entry.address.as_bytes()[..layout.prefix_bytes].to_vec();
+        grouped.entry(prefix).or_default().push(entry);
+    }
+    let mut chapters = vec![];
+    for (prefix, addresses) in grouped {
+        let relative = chapter_file_path(&prefix, volume);
+        let dir = out.join(chapter_dirname(&prefix));
+        fs::create_dir_all(&dir).map_err(|e| AppearanceIndexError::FileCreator {
+            source: e,
+            filename: dir,
+        })?;
+        let n_addresses = addresses.len();
+        let n_appearances = addresses.iter().map(|a| a.appearances.len()).sum();
+        let chapter = ChapterVolume {
+            address_prefix: prefix.clone(),
+            identifier: VolumeIdentifier {
+                oldest_block: volume.old,
+            },
+            addresses,
+        };
+        let bytes = chapter.write(&out.join(&relative))?;
+        chapters.push(ChapterEntry {
+            prefix: format!("0x{}", hex::encode(&prefix)),
+            path: relative,
+            bytes,
+            n_addresses,
+            n_appearances,
+        });
+    }
+    Ok(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::write_chunk;
+
+    fn tx(block: u32, index: u32) -> TransactionId {
+        TransactionId { block, index }
+    }
+
+    #[test]
+    fn chapter_matches_min_know_layout() {
+        let address = Address([0x4e; ADDR]);
+        let chapter = ChapterVolume {
+            address_prefix: vec![0x4e],
+            identifier: VolumeIdentifier {
+                oldest_block: 17_100_000,
+            },
+            addresses: vec![AddressData {
+                address,
+                appearances: vec![tx(17_100_001, 2)],
+            }],
+        };
+        let mut expected = vec![0x4e];
+        expected.extend_from_slice(&17_100_000u32.to_le_bytes());
+        // Offset of the addresses list, after the fixed part.
+        expected.extend_from_slice(&9u32.to_le_bytes());
+        // List of one variable size item: its offset, then the item.
+        expected.extend_from_slice(&4u32.to_le_bytes());
+        expected.extend_from_slice(address.as_bytes());
+        expected.extend_from_slice(&24u32.to_le_bytes());
+        expected.extend_from_slice(&17_100_001u32.to_le_bytes());
+        expected.extend_from_slice(&2u32.to_le_bytes());
+
+        let bytes = chapter.to_ssz_bytes();
+        assert_eq!(bytes, expected);
+        assert_eq!(ChapterVolume::from_ssz_bytes(&bytes, 1).unwrap(), chapter);
+        assert!(ChapterVolume::from_ssz_bytes(&bytes, 2).is_err());
+    }
+
+    #[test]
+    fn export_skips_empty_chapters() {
+        let root = std::env::temp_dir().join(format!("unchained-aai-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (chunks_dir, out) = (root.join("chunks"), root.join("index"));
+        fs::create_dir_all(&chunks_dir).unwrap();
+        let data = vec![
+            AddressData {
+                address: Address([0x4e; ADDR]),
+                appearances: vec![tx(5, 1), tx(150, 2)],
+            },
+            AddressData {
+                address: Address([0xa1; ADDR]),
+                appearances: vec![tx(7, 0)],
+            },
+        ];
+        write_chunk(&chunks_dir, BlockRange::new(0, 199).unwrap(), [0; 32], data).unwrap();
+        let chunks = ChunksDir::new(&chunks_dir).unwrap();
+        let layout = IndexLayout {
+            volume_span: 100,
+            prefix_bytes: 2,
+        };
+        let manifest =
+            write_appearance_index(&chunks, &BlockRange::new(0, 199).unwrap(), &layout, &out)
+                .unwrap();
+
+        assert_eq!(manifest.volumes.len(), 2);
+        assert_eq!(manifest.volumes[0].chapters.len(), 2);
+        assert_eq!(manifest.volumes[1].chapters.len(), 1);
+        let chapter_dirs = fs::read_dir(&out).unwrap().count();
+        // Two chapters and the manifest.
+        assert_eq!(chapter_dirs, 3);
+        let entry = &manifest.volumes[1].chapters[0];
+        assert_eq!(entry.prefix, "0x4e4e");
+        let chapter = ChapterVolume::from_file(&out.join(&entry.path), 2).unwrap();
+        assert_eq!(chapter.identifier.oldest_block, 100);
+        assert_eq!(chapter.addresses[0].appearances, vec![tx(150, 2)]);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,557 @@
+//! Reads and writes the bloom filter files that accompany chunks.
+//!
+//! Each chunk "{range}.bin" has a "{range}.bloom" file that tells whether an
+//! address may be in the chunk without opening it. The format is that of
+//! trueblocks-core (all integers little endian):
+//!
+//! ```text
+//! - magic 0xdead (2 bytes)
+//! - version_hash (32 bytes, as in the chunk header)
+//! - nblooms (4 bytes)
+//! - blooms (nblooms * 131076 bytes)
+//!     - ninserted (4 bytes)
+//!     - bits (131072 bytes)
+//! ```
+//! An address sets five bits in a bloom: each 4 byte slice of the address, read
+//! big endian, modulo the bloom width. Bytes are stored last first. Addresses
+//! are inserted in address order, and a new bloom is started once a bloom holds
+//! more than 50000 (so full blooms hold 50001, and the last may be empty).
+//!
+//! A bloom can be checked against its chunk: every address in the chunk must be
+//! reported present, and the false positive rate is estimated by probing
+//! addresses that are not in the chunk.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    address::Address,
+    constants::{ADDR, VER},
+    files::{bloom_filename, ChunkFile, ChunksDir},
+    parse::{BlockRange, ParseError, UnchainedFile},
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum BloomError {
+    #[error("File {filename} could not be opened {source}")]
+    FileOpener {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("File {filename} could not be created {source}")]
+    FileCreator {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Unable to read {filename} {source}")]
+    Read {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Unable to write to {filename} {source}")]
+    Write {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("File {filename} has magic {found:#06x}, expected {BLOOM_MAGIC:#06x}")]
+    InvalidMagic { filename: PathBuf, found: u16 },
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+}
+
+/// Magic number at the start of a bloom file.
+pub const BLOOM_MAGIC: u16 = 0xdead;
+
+/// Bits in a single bloom.
+pub const BLOOM_WIDTH_IN_BITS: u32 = 1_048_576;
+
+/// Bytes in a single bloom (131072).
+pub const BLOOM_WIDTH_IN_BYTES: usize = (BLOOM_WIDTH_IN_BITS / 8) as usize;
+
+/// A new bloom is started once a bloom holds more than this many addresses.
+pub const MAX_ADDRS_IN_BLOOM: u32 = 50_000;
+
+/// Bits set for each address.
+pub const BITS_PER_ADDRESS: usize = ADDR / 4;
+
+/// Bit positions an address sets in a bloom.
+pub fn address_bits(address: &Address) -> [u32; BITS_PER_ADDRESS] {
+    let mut bits = [0u32; BITS_PER_ADDRESS];
+    for (bit, slice) in bits.iter_mut().zip(address.as_bytes().chunks(4)) {
+        let value = u32::from_be_bytes([slice[0], slice[1], slice[2], slice[3]]);
+        *bit = value % BLOOM_WIDTH_IN_BITS;
+    }
+    bits
+}
+
+/// Byte index and mask of a bit position.
+fn locate(bit: u32) -> (usize, u8) {
+    let index = BLOOM_WIDTH_IN_BYTES - (bit / 8) as usize - 1;
+    (index, 1 << (bit % 8))
+}
+
+/// A single fixed width bloom.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BloomBytes {
+    /// Number of addresses inserted.
+    pub n_inserted: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Default for BloomBytes {
+    fn default() -> Self {
+        BloomBytes {
+            n_inserted: 0,
+            bytes: vec![0u8; BLOOM_WIDTH_IN_BYTES],
+        }
+    }
+}
+
+impl BloomBytes {
+    fn insert(&mut self, address: &Address) {
+        for bit in address_bits(address) {
+            let (index, mask) = locate(bit);
+            self.bytes[index] |= mask;
+        }
+        self.n_inserted += 1;
+    }
+    /// Whether the address may have been inserted.
+    pub fn contains(&self, address: &Address) -> bool {
+        address_bits(address).into_iter().all(|bit| {
+            let (index, mask) = locate(bit);
+            self.bytes[index] & mask != 0
+        })
+    }
+    /// Number of bits that are set.
+    pub fn bits_set(&self) -> u32 {
+        self.bytes.iter().map(|b| b.count_ones()).sum()
+    }
+}
+
+/// Bloom filter for the addresses of a chunk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bloom {
+    /// Hash identifying the specification version (as in the chunk header).
+    pub version: [u8; VER],
+    pub blooms: Vec<BloomBytes>,
+}
+
+impl Bloom {
+    /// Empty filter.
+    pub fn new(version: [u8; VER]) -> Self {
+        Bloom {
+            version,
+            blooms: vec![],
+        }
+    }
+    /// Adds an address to the last bloom, then starts a new bloom if the last
+    /// one is full (as trueblocks-core does).
+    pub fn insert(&mut self, address: &Address) {
+        if self.blooms.is_empty() {
+            self.blooms.push(BloomBytes::default());
+        }
+        let last = self.blooms.last_mut().expect("A bloom was just added");
+        last.insert(address);
+        if last.n_inserted > MAX_ADDRS_IN_BLOOM {
+            self.blooms.push(BloomBytes::default());
+        }
+    }
+    /// Whether the address may be in the chunk.
+    ///
+    /// False positives are possible, false negatives are not.
+    pub fn contains(&self, address: &Address) -> bool {
+        self.blooms.iter().any(|b| b.contains(address))
+    }
+    /// Total number of addresses inserted.
+    pub fn n_inserted(&self) -> u32 {
+        self.blooms.iter().map(|b| b.n_inserted).sum()
+    }
+    /// Builds the filter for the addresses of a chunk.
+    pub fn from_chunk(chunk: &ChunkFile) -> Result<Self, BloomError> {
+        let mut file = UnchainedFile::from_file(chunk.path.to_owned(), chunk.range)?;
+        let mut bloom = Bloom::new(file.version());
+        for address in file.addresses()? {
+            bloom.insert(&address);
+        }
+        Ok(bloom)
+    }
+    /// Reads a bloom file.
+    pub fn from_file(path: &Path) -> Result<Self, BloomError> {
+        let file = File::open(path).map_err(|e| BloomError::FileOpener {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        let read_error = |e| BloomError::Read {
+            source: e,
+            filename: path.to_path_buf(),
+        };
+        let mut rdr = BufReader::new(file);
+        let magic = rdr.read_u16::<LittleEndian>().map_err(read_error)?;
+        if magic != BLOOM_MAGIC {
+            return Err(BloomError::InvalidMagic {
+                filename: path.to_path_buf(),
+                found: magic,
+            });
+        }
+        let mut version = [0u8; VER];
+        rdr.read_exact(&mut version).map_err(read_error)?;
+        let count = rdr.read_u32::<LittleEndian>().map_err(read_error)?;
+        let mut blooms = vec![];
+        for _ in 0..count {
+            let n_inserted = rdr.read_u32::<LittleEndian>().map_err(read_error)?;
+            let mut bytes = vec![0u8; BLOOM_WIDTH_IN_BYTES];
+            rdr.read_exact(&mut bytes).map_err(read_error)?;
+            blooms.push(BloomBytes { n_inserted, bytes });
+        }
+        Ok(Bloom { version, blooms })
+    }
+    /// Writes the filter to a file.
+    pub fn write(&self, path: &Path) -> Result<(), BloomError> {
+        let file = File::create(path).map_err(|e| BloomError::FileCreator {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        let mut wtr = BufWriter::new(file);
+        self.write_contents(&mut wtr)
+            .and_then(|_| wtr.flush())
+            .map_err(|e| BloomError::Write {
+                source: e,
+                filename: path.to_path_buf(),
+            })
+    }
+    fn write_contents(&self, mut wtr: impl Write) -> Result<(), std::io::Error> {
+        wtr.write_u16::<LittleEndian>(BLOOM_MAGIC)?;
+        wtr.write_all(&self.version)?;
+        wtr.write_u32::<LittleEndian>(self.blooms.len() as u32)?;
+        for bloom in &self.blooms {
+            wtr.write_u32::<LittleEndian>(bloom.n_inserted)?;
+            wtr.write_all(&bloom.bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Details of a bloom file that has been written.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WrittenBloom {
+    pub path: PathBuf,
+    pub range: BlockRange,
+    pub n_inserted: u32,
+    pub n_blooms: u32,
+}
+
+/// Writes the bloom file for a chunk into a directory.
+pub fn write_bloom(chunk: &ChunkFile, dir: &Path) -> Result<WrittenBloom, BloomError> {
+    let bloom = Bloom::from_chunk(chunk)?;
+    let path = dir.join(bloom_filename(&chunk.range));
+    bloom.write(&path)?;
+    Ok(WrittenBloom {
+        path,
+        range: chunk.range,
+        n_inserted: bloom.n_inserted(),
+        n_blooms: bloom.blooms.len() as u32,
+    })
+}
+
+/// Result of checking a bloom file against its chunk.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BloomCheck {
+    pub chunk_path: PathBuf,
+    pub bloom_path: PathBuf,
+    pub range: BlockRange,
+    /// The bloom file does not exist. The bloom fields are then empty.
+    pub missing: bool,
+    /// Whether the bloom has the version hash of the chunk.
+    pub version_matches: bool,
+    /// Addresses in the chunk.
+    pub n_addresses: u32,
+    /// Addresses the bloom records as inserted.
+    pub n_inserted: u32,
+    pub n_blooms: u32,
+    /// Fraction of bits set in each bloom.
+    pub fill: Vec<f64>,
+    /// Chunk addresses the bloom reports as absent.
+    pub false_negatives: Vec<Address>,
+    /// Addresses not in the chunk that were tested.
+    pub probes: u32,
+    /// Probes the bloom reports as present.
+    pub false_positives: u32,
+    /// false_positives / probes.
+    pub false_positive_rate: f64,
+    /// Rate expected from the fill of the blooms.
+    pub expected_false_positive_rate: f64,
+    /// No false negatives, and version and address count match the chunk.
+    pub consistent: bool,
+}
+
+/// Checks a bloom file against its chunk.
+///
+/// Probe addresses are derived from the seed, so results are repeatable. A
+/// missing bloom file is reported as such (and not consistent).
+pub fn check_bloom(
+    chunk: &ChunkFile,
+    bloom_path: &Path,
+    probes: u32,
+    seed: u64,
+) -> Result<BloomCheck, BloomError> {
+    let mut file = UnchainedFile::from_file(chunk.path.to_owned(), chunk.range)?;
+    let addresses = file.addresses()?;
+    if !bloom_path.exists() {
+        return Ok(BloomCheck {
+            chunk_path: chunk.path.to_path_buf(),
+            bloom_path: bloom_path.to_path_buf(),
+            range: chunk.range,
+            missing: true,
+            version_matches: false,
+            n_addresses: addresses.len() as u32,
+            n_inserted: 0,
+            n_blooms: 0,
+            fill: vec![],
+            false_negatives: vec![],
+            probes: 0,
+            false_positives: 0,
+            false_positive_rate: 0.0,
+            expected_false_positive_rate: 0.0,
+            consistent: false,
+        });
+    }
+    let bloom = Bloom::from_file(bloom_path)?;
+    let false_negatives: Vec<Address> = addresses
+        .iter()
+        .filter(|address| !bloom.contains(address))
+        .copied()
+        .collect();
+    let mut tested = 0;
+    let mut false_positives = 0;
+    for n in 0..u64::from(probes) {
+        let address = probe_address(seed, n);
+        // Chunk addresses are sorted.
+        if addresses.binary_search(&address).is_ok() {
+            continue;
+        }
+        tested += 1;
+        if bloom.contains(&address) {
+            false_positives += 1;
+        }
+    }
+    let fill: Vec<f64> = bloom
+        .blooms
+        .iter()
+        .map(|b| f64::from(b.bits_set()) / f64::from(BLOOM_WIDTH_IN_BITS))
+        .collect();
+    let all_absent: f64 = fill
+        .iter()
+        .map(|f| 1.0 - f.powi(BITS_PER_ADDRESS as i32))
+        .product();
+    let n_addresses = addresses.len() as u32;
+    let version_matches = bloom.version == file.version();
+    Ok(BloomCheck {
+        chunk_path: chunk.path.to_path_buf(),
+        bloom_path: bloom_path.to_path_buf(),
+        range: chunk.range,
+        missing: false,
+        version_matches,
+        n_addresses,
+        n_inserted: bloom.n_inserted(),
+        n_blooms: bloom.blooms.len() as u32,
+        fill,
+        consistent: false_negatives.is_empty()
+            && version_matches
+            && bloom.n_inserted() == n_addresses,
+        false_negatives,
+        probes: tested,
+        false_positives,
+        false_positive_rate: match tested {
+            0 => 0.0,
+            _ => f64::from(false_positives) / f64::from(tested),
+        },
+        expected_false_positive_rate: 1.0 - all_absent,
+    })
+}
+
+/// Checks the bloom file ("{range}.bloom" in `blooms_dir`) of every chunk.
+pub fn check_blooms(
+    chunks: &ChunksDir,
+    blooms_dir: &Path,
+    probes: u32,
+    seed: u64,
+) -> Result<Vec<BloomCheck>, BloomError> {
+    chunks
+        .paths
+        .iter()
+        .map(|chunk| {
+            let bloom_path = blooms_dir.join(bloom_filename(&chunk.range));
+            check_bloom(chunk, &bloom_path, probes, seed)
+        })
+        .collect()
+}
+
+/// Pseudo-random address: the first 20 bytes of sha256(seed, n).
+fn probe_address(seed: u64, n: u64) -> Address {
+    let mut hasher = Sha256::new();
+    hasher.update(seed.to_le_bytes());
+    hasher.update(n.to_le_bytes());
+    let hash = hasher.finalize();
+    let mut address = [0u8; ADDR];
+    address.copy_from_slice(&hash[..ADDR]);
+    Address(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures,
+        structure::{AddressData, TransactionId},
+        write::write_chunk,
+    };
+    use std::fs;
+
+    /// Address with the given big endian number in its last four bytes.
+    fn numbered(n: u32) -> Address {
+        let mut address = [0u8; ADDR];
+        address[ADDR - 4..].copy_from_slice(&n.to_be_bytes());
+        Address(address)
+    }
+
+    #[test]
+    fn starts_new_bloom_after_limit() {
+        let mut bloom = Bloom::new([0; VER]);
+        for n in 0..MAX_ADDRS_IN_BLOOM {
+            bloom.insert(&numbered(n));
+        }
+        assert_eq!(bloom.blooms.len(), 1);
+        bloom.insert(&numbered(MAX_ADDRS_IN_BLOOM));
+        let counts: Vec<u32> = bloom.blooms.iter().map(|b| b.n_inserted).collect();
+        assert_eq!(counts, vec![MAX_ADDRS_IN_BLOOM + 1, 0]);
+        bloom.insert(&numbered(MAX_ADDRS_IN_BLOOM + 1));
+        let counts: Vec<u32> = bloom.blooms.iter().map(|b| b.n_inserted).collect();
+        assert_eq!(counts, vec![MAX_ADDRS_IN_BLOOM + 1, 1]);
+        assert!(bloom.contains(&numbered(0)));
+        assert!(bloom.contains(&numbered(MAX_ADDRS_IN_BLOOM + 1)));
+    }
+
+    #[test]
+    fn bloom_file_from_chunk_matches_expected_bytes() {
+        let dir = std::env::temp_dir().join(format!("unchained-bloom-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let range = BlockRange::new(17_190_873, 17_190_873).unwrap();
+        let version = [0xab; VER];
+        let data = [
+            "0xd2090025857b9c7b24387741f120538e928a3a59",
+            "0x30a4639850b3ddeaaca4f06280aa751682f11382",
+        ]
+        .into_iter()
+        .map(|a| AddressData {
+            address: Address::from_hex(a).unwrap(),
+            appearances: vec![TransactionId {
+                block: 17_190_873,
+                index: 1,
+            }],
+        })
+        .collect();
+        let written = write_chunk(&dir, range, version, data).unwrap();
+        let chunk = ChunkFile::new(&written.path).unwrap();
+        let bloom = write_bloom(&chunk, &dir).unwrap();
+        assert_eq!(bloom.n_inserted, 2);
+        assert_eq!(bloom.n_blooms, 1);
+
+        // Bytes set by the two addresses, computed independently of this module.
+        let set_bytes = [
+            (35994, 8),
+            (45447, 64),
+            (47326, 2),
+            (57381, 32),
+            (61761, 2),
+            (90653, 4),
+            (95158, 1),
+            (99436, 4),
+            (122297, 4),
+            (128440, 64),
+        ];
+        let mut expected = vec![0xad, 0xde];
+        expected.extend_from_slice(&version);
+        expected.extend_from_slice(&1u32.to_le_bytes());
+        expected.extend_from_slice(&2u32.to_le_bytes());
+        expected.resize(expected.len() + BLOOM_WIDTH_IN_BYTES, 0);
+        for (position, value) in set_bytes {
+            expected[position] = value;
+        }
+        assert_eq!(fs::read(&bloom.path).unwrap(), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checks_chunks_and_reports_missing_blooms() {
+        let dir = fixtures::test_dir("bloom-check");
+        let with_bloom = fixtures::write_sample(&dir);
+        let without_bloom = fixtures::write_fixture(
+            &dir,
+            BlockRange::new(17_190_876, 17_190_880).unwrap(),
+            vec![AddressData {
+                address: fixtures::address(fixtures::ADDRESS_A),
+                appearances: vec![fixtures::tx(17_190_880, 1)],
+            }],
+        );
+        write_bloom(&with_bloom, &dir).unwrap();
+        let chunks = ChunksDir::new(&dir).unwrap();
+        let checks = check_blooms(&chunks, &dir, 1000, 1).unwrap();
+        assert_eq!(checks.len(), 2);
+
+        assert!(!checks[0].missing);
+        assert!(checks[0].consistent);
+        assert!(checks[0].version_matches);
+        assert!(checks[0].false_negatives.is_empty());
+        assert_eq!(checks[0].n_addresses, 3);
+        assert_eq!(checks[0].n_inserted, 3);
+
+        assert_eq!(checks[1].chunk_path, without_bloom.path);
+        assert!(checks[1].missing);
+        assert!(!checks[1].consistent);
+        assert_eq!(checks[1].n_addresses, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn seeded_false_positive_rate() {
+        let dir = fixtures::test_dir("bloom-rate");
+        // Two blooms of about 50000 addresses, each about a fifth full.
+        let data: Vec<AddressData> = (0..100_000)
+            .map(|n| AddressData {
+                address: probe_address(u64::MAX, n),
+                appearances: vec![fixtures::tx(1, 0)],
+            })
+            .collect();
+        let chunk = fixtures::write_fixture(&dir, BlockRange::new(0, 1).unwrap(), data);
+        let written = write_bloom(&chunk, &dir).unwrap();
+        assert_eq!(written.n_blooms, 2);
+
+        let check = check_bloom(&chunk, &written.path, 50_000, 3).unwrap();
+        assert!(check.consistent);
+        assert_eq!(check.probes, 50_000);
+        assert!(check.false_positives > 0);
+        let expected = check.expected_false_positive_rate;
+        assert!(
+            (check.false_positive_rate - expected).abs() < expected / 2.0,
+            "rate {} expected {}",
+            check.false_positive_rate,
+            expected
+        );
+        // The same seed probes the same addresses.
+        assert_eq!(
+            check_bloom(&chunk, &written.path, 50_000, 3).unwrap(),
+            check
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,743 @@
+//! Reads files from IPFS CAR (content addressable archive) files.
+//!
+//! Supports CARv1 and CARv2 (the inner CARv1 payload is used, the index is
+//! not needed). On opening, the block sections are scanned to record where
+//! each block is. Blocks are only read when a file is requested.
+//!
+//! Files are reassembled from their UnixFS DAG (dag-pb nodes with dag-pb or
+//! raw leaves). Blocks with sha2-256 multihashes are checked against their CID.
+//!
+//! See also: <https://ipld.io/specs/transport/car/>
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    cid::{take_varint, Cid, CidError, DAG_PB, RAW, SHA2_256},
+    files::{bloom_filename, chunk_filename, FilesError},
+    manifest::Manifest,
+    parse::{BlockRange, ParseError, UnchainedFile},
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum CarError {
+    #[error("File {filename} could not be opened {source}")]
+    FileOpener {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Unable to read CAR data {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Invalid CAR header: {0}")]
+    InvalidHeader(String),
+    #[error("Unsupported CAR version {0}")]
+    UnsupportedVersion(u64),
+    #[error("CidError {0}")]
+    CidError(#[from] CidError),
+    #[error("Block {0} is not in the archive")]
+    MissingBlock(Cid),
+    #[error("Block {0} does not match its hash")]
+    HashMismatch(Cid),
+    #[error("Block {cid} has unsupported codec {codec:#x}")]
+    UnsupportedCodec { cid: Cid, codec: u64 },
+    #[error("Block {cid} is not a valid dag-pb node: {reason}")]
+    InvalidDagPb { cid: Cid, reason: String },
+    #[error("Block {cid} has UnixFS type {found}, expected a file")]
+    NotAFile { cid: Cid, found: u64 },
+    #[error("Unable to write to {filename} {source}")]
+    Write {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("FilesError {0}")]
+    FilesError(#[from] FilesError),
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+}
+
+/// First bytes of a CARv2 file.
+const CARV2_PRAGMA: [u8; 11] = [
+    0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+];
+
+/// Largest CARv1 header that is read. Headers only hold the version and
+/// roots, so anything larger is not a valid archive.
+const MAX_HEADER_LENGTH: u64 = 32 * 1024 * 1024;
+
+/// UnixFS data types that hold file bytes.
+const UNIXFS_RAW: u64 = 0;
+const UNIXFS_FILE: u64 = 2;
+
+/// Position of a block's data in the archive.
+#[derive(Clone, Copy, Debug)]
+struct BlockLocation {
+    offset: u64,
+    length: usize,
+}
+
+/// An opened CAR file.
+pub struct CarArchive<R> {
+    reader: R,
+    /// CAR format version (1 or 2).
+    pub version: u64,
+    /// Root CIDs from the header.
+    pub roots: Vec<Cid>,
+    blocks: HashMap<Cid, BlockLocation>,
+}
+
+impl CarArchive<BufReader<File>> {
+    /// Opens a CAR file and indexes its blocks.
+    pub fn from_file(path: &Path) -> Result<Self, CarError> {
+        let file = File::open(path).map_err(|e| CarError::FileOpener {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        CarArchive::from_reader(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> CarArchive<R> {
+    /// Reads the header and indexes the blocks of CAR data from any seekable source.
+    pub fn from_reader(mut reader: R) -> Result<Self, CarError> {
+        let mut pragma = [0u8; 11];
+        reader.read_exact(&mut pragma)?;
+        let (payload_start, payload_end) = if pragma == CARV2_PRAGMA {
+            // Characteristics (16 bytes), data offset, data size, index offset.
+            let mut header = [0u8; 40];
+            reader.read_exact(&mut header)?;
+            let offset = u64::from_le_bytes(header[16..24].try_into().expect("8 bytes"));
+            let size = u64::from_le_bytes(header[24..32].try_into().expect("8 bytes"));
+            let end = offset.checked_add(size).ok_or_else(|| {
+                CarError::InvalidHeader(format!(
+                    "Data offset {} and size {} overflow",
+                    offset, size
+                ))
+            })?;
+            (offset, Some(end))
+        } else {
+            (0, None)
+        };
+        reader.seek(SeekFrom::Start(payload_start))?;
+        let (version, roots) = read_v1_header(&mut reader)?;
+        if version != 1 {
+            return Err(CarError::UnsupportedVersion(version));
+        }
+        let blocks = index_blocks(&mut reader, payload_end)?;
+        Ok(CarArchive {
+            reader,
+            version: if payload_end.is_some() { 2 } else { 1 },
+            roots,
+            blocks,
+        })
+    }
+
+    /// True if the archive holds the block.
+    pub fn contains(&self, cid: &Cid) -> bool {
+        self.blocks.contains_key(cid)
+    }
+
+    /// Number of blocks in the archive.
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Reads a single block, checking it against the CID.
+    pub fn block(&mut self, cid: &Cid) -> Result<Vec<u8>, CarError> {
+        let location = *self
+            .blocks
+            .get(cid)
+            .ok_or_else(|| CarError::MissingBlock(cid.clone()))?;
+        self.reader.seek(SeekFrom::Start(location.offset))?;
+        let mut data = vec![0u8; location.length];
+        self.reader.read_exact(&mut data)?;
+        let (code, digest) = cid.digest()?;
+        if code == SHA2_256 && Sha256::digest(&data).as_slice() != digest {
+            return Err(CarError::HashMismatch(cid.clone()));
+        }
+        Ok(data)
+    }
+
+    /// Reassembles the bytes of a UnixFS file.
+    pub fn read_file(&mut self, cid: &Cid) -> Result<Vec<u8>, CarError> {
+        let mut contents = vec![];
+        self.append_file(cid, &mut contents)?;
+        Ok(contents)
+    }
+
+    /// Prepares a chunk stored in the archive for reading.
+    ///
+    /// The block range of the chunk (e.g., from the manifest) must be supplied.
+    pub fn unchained_file(
+        &mut self,
+        cid: &Cid,
+        present: BlockRange,
+        desired: BlockRange,
+    ) -> Result<UnchainedFile, CarError> {
+        let bytes = self.read_file(cid)?;
+        Ok(UnchainedFile::from_bytes(bytes, present, desired)?)
+    }
+
+    fn append_file(&mut self, cid: &Cid, contents: &mut Vec<u8>) -> Result<(), CarError> {
+        let block = self.block(cid)?;
+        match cid.codec {
+            RAW => contents.extend_from_slice(&block),
+            DAG_PB => {
+                let node = PbNode::decode(&block).map_err(|reason| CarError::InvalidDagPb {
+                    cid: cid.clone(),
+                    reason,
+                })?;
+                let data =
+                    UnixFsData::decode(&node.data).map_err(|reason| CarError::InvalidDagPb {
+                        cid: cid.clone(),
+                        reason,
+                    })?;
+                if data.kind != UNIXFS_FILE && data.kind != UNIXFS_RAW {
+                    return Err(CarError::NotAFile {
+                        cid: cid.clone(),
+                        found: data.kind,
+                    });
+                }
+                contents.extend_from_slice(&data.data);
+                for link in node.links {
+                    self.append_file(&link, contents)?;
+                }
+            }
+            codec => {
+                return Err(CarError::UnsupportedCodec {
+                    cid: cid.clone(),
+                    codec,
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Details of a file written from an archive.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnpackedFile {
+    pub path: PathBuf,
+    pub range: BlockRange,
+    pub cid: String,
+    pub size: usize,
+}
+
+/// Writes the manifest chunks (and optionally blooms) that are in the archive.
+///
+/// Files are named for their range as in a trueblocks-core index directory,
+/// e.g. "017190314-017193246.bin" and "017190314-017193246.bloom". Manifest
+/// entries whose CIDs are not in the archive are skipped.
+pub fn unpack_chunks<R: Read + Seek>(
+    car: &mut CarArchive<R>,
+    manifest: &Manifest,
+    chunks_dir: &Path,
+    blooms_dir: Option<&Path>,
+) -> Result<Vec<UnpackedFile>, CarError> {
+    let mut unpacked = vec![];
+    for chunk in &manifest.chunks {
+        let range = chunk.block_range()?;
+        let index_name = chunk_filename(&range);
+        let mut targets = vec![(&chunk.index_hash, chunks_dir.join(&index_name))];
+        if let Some(dir) = blooms_dir {
+            targets.push((&chunk.bloom_hash, dir.join(bloom_filename(&range))));
+        }
+        for (cid, path) in targets {
+            let cid: Cid = cid.parse()?;
+            if !car.contains(&cid) {
+                continue;
+            }
+            let bytes = car.read_file(&cid)?;
+            fs::write(&path, &bytes).map_err(|e| CarError::Write {
+                source: e,
+                filename: path.to_path_buf(),
+            })?;
+            unpacked.push(UnpackedFile {
+                path,
+                range,
+                cid: cid.to_string(),
+                size: bytes.len(),
+            });
+        }
+    }
+    Ok(unpacked)
+}
+
+/// Reads a varint from a stream. Returns None at the end of the stream.
+fn read_varint(rdr: &mut impl Read) -> Result<Option<u64>, CarError> {
+    let mut value: u64 = 0;
+    for (n, shift) in (0..64).step_by(7).enumerate() {
+        let mut byte = [0u8; 1];
+        if rdr.read(&mut byte)? == 0 {
+            if n == 0 {
+                return Ok(None);
+            }
+            return Err(CarError::InvalidHeader("Truncated varint".to_string()));
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(CarError::InvalidHeader("Varint too long".to_string()))
+}
+
+/// Reads the CARv1 header: a DAG-CBOR map with "version" and "roots".
+fn read_v1_header(rdr: &mut impl Read) -> Result<(u64, Vec<Cid>), CarError> {
+    let length =
+        read_varint(rdr)?.ok_or_else(|| CarError::InvalidHeader("Missing header".to_string()))?;
+    if length > MAX_HEADER_LENGTH {
+        return Err(CarError::InvalidHeader(format!(
+            "Header length {} exceeds {}",
+            length, MAX_HEADER_LENGTH
+        )));
+    }
+    let mut header = vec![0u8; length as usize];
+    rdr.read_exact(&mut header)?;
+    let mut cbor = Cbor {
+        bytes: &header,
+        position: 0,
+    };
+    let mut version = None;
+    let mut roots = vec![];
+    let (major, entries) = cbor.item()?;
+    if major != 5 {
+        return Err(CarError::InvalidHeader("Header is not a map".to_string()));
+    }
+    for _ in 0..entries {
+        let key = cbor.text()?;
+        match key.as_str() {
+            "version" => {
+                let (major, value) = cbor.item()?;
+                if major != 0 {
+                    return Err(CarError::InvalidHeader(
+                        "Version is not an integer".to_string(),
+                    ));
+                }
+                version = Some(value);
+            }
+            "roots" => {
+                let (major, count) = cbor.item()?;
+                if major != 4 {
+                    return Err(CarError::InvalidHeader("Roots is not an array".to_string()));
+                }
+                for _ in 0..count {
+                    roots.push(cbor.cid()?);
+                }
+            }
+            other => {
+                return Err(CarError::InvalidHeader(format!("Unexpected key {}", other)));
+            }
+        }
+    }
+    let version = version.ok_or_else(|| CarError::InvalidHeader("Missing version".to_string()))?;
+    Ok((version, roots))
+}
+
+/// Records the location of each block section: varint length, CID, data.
+fn index_blocks(
+    rdr: &mut (impl Read + Seek),
+    end: Option<u64>,
+) -> Result<HashMap<Cid, BlockLocation>, CarError> {
+    let mut blocks = HashMap::new();
+    loop {
+        let start = rdr.stream_position()?;
+        if end.is_some_and(|end| start >= end) {
+            break;
+        }
+        let Some(length) = read_varint(rdr)? else {
+            break;
+        };
+        if length == 0 {
+            // Zero length sections are padding in some writers.
+            continue;
+        }
+        let section = rdr.stream_position()?;
+        // A CID is at most a few varints and a 64 byte digest.
+        let mut prefix = vec![0u8; (length as usize).min(128)];
+        rdr.read_exact(&mut prefix)?;
+        let (cid, cid_length) = Cid::from_bytes(&prefix)?;
+        let location = BlockLocation {
+            offset: section + cid_length as u64,
+            length: length as usize - cid_length,
+        };
+        blocks.entry(cid).or_insert(location);
+        let next = section.checked_add(length).ok_or_else(|| {
+            CarError::InvalidHeader(format!("Section length {} overflows", length))
+        })?;
+        rdr.seek(SeekFrom::Start(next))?;
+    }
+    Ok(blocks)
+}
+
+/// Minimal DAG-CBOR reader for the CAR header.
+struct Cbor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Cbor<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], CarError> {
+        let taken = self
+            .bytes
+            .get(self.position..self.position + n)
+            .ok_or_else(|| CarError::InvalidHeader("Truncated".to_string()))?;
+        self.position += n;
+        Ok(taken)
+    }
+    /// Major type and argument of the next item.
+    fn item(&mut self) -> Result<(u8, u64), CarError> {
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let argument = match initial & 0x1f {
+            n @ 0..=23 => u64::from(n),
+            24 => u64::from(self.take(1)?[0]),
+            25 => u64::from(u16::from_be_bytes(
+                self.take(2)?.try_into().expect("2 bytes"),
+            )),
+            26 => u64::from(u32::from_be_bytes(
+                self.take(4)?.try_into().expect("4 bytes"),
+            )),
+            27 => u64::from_be_bytes(self.take(8)?.try_into().expect("8 bytes")),
+            _ => {
+                return Err(CarError::InvalidHeader(
+                    "Indefinite length item".to_string(),
+                ))
+            }
+        };
+        Ok((major, argument))
+    }
+    fn text(&mut self) -> Result<String, CarError> {
+        let (major, length) = self.item()?;
+        if major != 3 {
+            return Err(CarError::InvalidHeader("Expected a text key".to_string()));
+        }
+        let text = self.take(length as usize)?;
+        String::from_utf8(text.to_vec()).map_err(|e| CarError::InvalidHeader(e.to_string()))
+    }
+    /// CID: tag 42 around a byte string with a leading zero byte.
+    fn cid(&mut self) -> Result<Cid, CarError> {
+        let (major, tag) = self.item()?;
+        let (bytes_major, length) = self.item()?;
+        if major != 6 || tag != 42 || bytes_major != 2 || length == 0 {
+            return Err(CarError::InvalidHeader("Expected a CID".to_string()));
+        }
+        let bytes = self.take(length as usize)?;
+        let (cid, _) = Cid::from_bytes(&bytes[1..])?;
+        Ok(cid)
+    }
+}
+
+/// Reads protobuf fields as (field number, varint or bytes).
+fn protobuf_fields(bytes: &[u8]) -> Result<Vec<(u64, ProtobufValue<'_>)>, String> {
+    let mut fields = vec![];
+    let mut position = 0;
+    while position < bytes.len() {
+        let key = take_varint(bytes, &mut position).map_err(|e| e.to_string())?;
+        let value = match key & 0x7 {
+            0 => {
+                ProtobufValue::Varint(take_varint(bytes, &mut position).map_err(|e| e.to_string())?)
+            }
+            2 => {
+                let length = take_varint(bytes, &mut position).map_err(|e| e.to_string())? as usize;
+                let value = bytes
+                    .get(position..position + length)
+                    .ok_or("Truncated field")?;
+                position += length;
+                ProtobufValue::Bytes(value)
+            }
+            wire => return Err(format!("Unsupported wire type {}", wire)),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+enum ProtobufValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// dag-pb node: links (field 2) and data (field 1).
+struct PbNode {
+    links: Vec<Cid>,
+    data: Vec<u8>,
+}
+
+impl PbNode {
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut node = PbNode {
+            links: vec![],
+            data: vec![],
+        };
+        for (field, value) in protobuf_fields(bytes)? {
+            match (field, value) {
+                (1, ProtobufValue::Bytes(data)) => node.data = data.to_vec(),
+                (2, ProtobufValue::Bytes(link)) => {
+                    for (field, value) in protobuf_fields(link)? {
+                        if let (1, ProtobufValue::Bytes(hash)) = (field, value) {
+                            let (cid, _) = Cid::from_bytes(hash).map_err(|e| e.to_string())?;
+                            node.links.push(cid);
+                        }
+                    }
+                }
+                _ => return Err(format!("Unexpected field {}", field)),
+            }
+        }
+        Ok(node)
+    }
+}
+
+/// UnixFS data: type (field 1) and file bytes (field 2).
+struct UnixFsData {
+    kind: u64,
+    data: Vec<u8>,
+}
+
+impl UnixFsData {
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut unixfs = UnixFsData {
+            kind: u64::MAX,
+            data: vec![],
+        };
+        for (field, value) in protobuf_fields(bytes)? {
+            match (field, value) {
+                (1, ProtobufValue::Varint(kind)) => unixfs.kind = kind,
+                (2, ProtobufValue::Bytes(data)) => unixfs.data = data.to_vec(),
+                // Sizes and metadata are not needed to reassemble the file.
+                _ => {}
+            }
+        }
+        Ok(unixfs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, manifest::ManifestChunk};
+    use std::io::Cursor;
+
+    /// CARv1 with root QmUnqnX9u6eTLMNwyjoTyoksXgJxP5SFHHwiWPoE9vdgcJ: a dag-pb
+    /// file node linking to a raw leaf ("hello ") and a dag-pb leaf ("world\n").
+    const CAR_V1: &str = "38a265726f6f747381d82a58230012205fdbbf131cc3250c1ccdafd86480d8acb38929879acff837c012f8a72b2da7ab6776657273696f6e01820112205fdbbf131cc3250c1ccdafd86480d8acb38929879acff837c012f8a72b2da7ab122a0a24015512205e3235a8346e5a4585f8c58562f5052b8fe26a3bb122e1e96c76784964dfc4611200180612280a221220b37b31a091a4df434f9bb91bd76d291c081ba2b55a1a583084738d564f1d68391200180e0a080802180c200620062a015512205e3235a8346e5a4585f8c58562f5052b8fe26a3bb122e1e96c76784964dfc46168656c6c6f20301220b37b31a091a4df434f9bb91bd76d291c081ba2b55a1a583084738d564f1d68390a0c08021206776f726c640a1806";
+    const ROOT: &str = "QmUnqnX9u6eTLMNwyjoTyoksXgJxP5SFHHwiWPoE9vdgcJ";
+    const RAW_LEAF: &str = "bafkreic6gi22qndoljcyl6gfqvrpkbjlr7rguo5relq6s3dwpbewjx6eme";
+    const PB_LEAF: &str = "QmaRGe7bVmVaLmxbrMiVNXqW4pRNNp3xq7hFtyRKA3mtJL";
+    /// Position of the raw leaf data in the archive.
+    const RAW_LEAF_DATA: usize = 226;
+
+    fn archive(bytes: Vec<u8>) -> CarArchive<Cursor<Vec<u8>>> {
+        CarArchive::from_reader(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn reads_files() {
+        let mut car = archive(hex::decode(CAR_V1).unwrap());
+        let root: Cid = ROOT.parse().unwrap();
+        assert_eq!(car.version, 1);
+        assert_eq!(car.roots, vec![root.clone()]);
+        assert_eq!(car.block_count(), 3);
+        assert_eq!(car.read_file(&root).unwrap(), b"hello world\n");
+        assert_eq!(
+            car.read_file(&RAW_LEAF.parse().unwrap()).unwrap(),
+            b"hello "
+        );
+        assert_eq!(
+            car.read_file(&PB_LEAF.parse().unwrap()).unwrap(),
+            b"world\n"
+        );
+    }
+
+    #[test]
+    fn rejects_modified_block() {
+        let mut bytes = hex::decode(CAR_V1).unwrap();
+        assert_eq!(&bytes[RAW_LEAF_DATA..RAW_LEAF_DATA + 6], b"hello ");
+        bytes[RAW_LEAF_DATA] = b'j';
+        let mut car = archive(bytes);
+        let raw_leaf: Cid = RAW_LEAF.parse().unwrap();
+        assert!(matches!(
+            car.read_file(&ROOT.parse().unwrap()),
+            Err(CarError::HashMismatch(cid)) if cid == raw_leaf
+        ));
+    }
+
+    #[test]
+    fn rejects_overflowing_v2_header() {
+        let mut bytes = CARV2_PRAGMA.to_vec();
+        let mut header = [0u8; 40];
+        header[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        header[24..32].copy_from_slice(&1u64.to_le_bytes());
+        bytes.extend_from_slice(&header);
+        assert!(matches!(
+            CarArchive::from_reader(Cursor::new(bytes)),
+            Err(CarError::InvalidHeader(_))
+        ));
+    }
+
+    /// Wraps CARv1 data as a CARv2 payload, with padding before the payload and
+    /// bytes after it standing in for an index.
+    fn car_v2(v1: &[u8]) -> Vec<u8> {
+        let padding = 5;
+        let offset = (CARV2_PRAGMA.len() + 40 + padding) as u64;
+        let mut bytes = CARV2_PRAGMA.to_vec();
+        let mut header = [0u8; 40];
+        header[16..24].copy_from_slice(&offset.to_le_bytes());
+        header[24..32].copy_from_slice(&(v1.len() as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(offset + v1.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(&vec![0; padding]);
+        bytes.extend_from_slice(v1);
+        bytes.extend_from_slice(&[0xff; 16]);
+        bytes
+    }
+
+    /// Protobuf style varint, as used for CAR section lengths.
+    fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    /// CIDv1 of raw data.
+    fn raw_cid(data: &[u8]) -> Cid {
+        let mut multihash = vec![0x12, 0x20];
+        multihash.extend_from_slice(&Sha256::digest(data));
+        Cid {
+            version: 1,
+            codec: RAW,
+            multihash,
+        }
+    }
+
+    /// CARv1 holding each file as a single raw block. The first file is the root.
+    fn car_v1(files: &[&[u8]]) -> Vec<u8> {
+        let root = raw_cid(files[0]).to_bytes();
+        // {"roots": [CID], "version": 1} in DAG-CBOR.
+        let mut header = vec![0xa2, 0x65];
+        header.extend_from_slice(b"roots");
+        header.extend_from_slice(&[0x81, 0xd8, 0x2a, 0x58, root.len() as u8 + 1, 0x00]);
+        header.extend_from_slice(&root);
+        header.push(0x67);
+        header.extend_from_slice(b"version");
+        header.push(0x01);
+        let mut bytes = vec![];
+        put_varint(&mut bytes, header.len() as u64);
+        bytes.extend_from_slice(&header);
+        for data in files {
+            let cid = raw_cid(data).to_bytes();
+            put_varint(&mut bytes, (cid.len() + data.len()) as u64);
+            bytes.extend_from_slice(&cid);
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_v2_payload() {
+        let mut car = archive(car_v2(&hex::decode(CAR_V1).unwrap()));
+        let root: Cid = ROOT.parse().unwrap();
+        assert_eq!(car.version, 2);
+        assert_eq!(car.roots, vec![root.clone()]);
+        // Bytes after the payload are not read as blocks.
+        assert_eq!(car.block_count(), 3);
+        assert_eq!(car.read_file(&root).unwrap(), b"hello world\n");
+        assert_eq!(
+            car.read_file(&PB_LEAF.parse().unwrap()).unwrap(),
+            b"world\n"
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        let mut bytes = vec![];
+        put_varint(&mut bytes, u64::MAX >> 1);
+        // Too short for the claimed header, which must not be allocated.
+        bytes.extend_from_slice(&[0; 16]);
+        assert!(matches!(
+            CarArchive::from_reader(Cursor::new(bytes)),
+            Err(CarError::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn unpacks_manifest_chunks() {
+        let dir = fixtures::test_dir("car-unpack");
+        let chunk = fixtures::write_sample(&dir);
+        let chunk_bytes = fs::read(&chunk.path).unwrap();
+        let bloom_bytes = b"bloom bytes".to_vec();
+        let mut car = archive(car_v1(&[&chunk_bytes, &bloom_bytes]));
+        assert_eq!(car.block_count(), 2);
+
+        let range = fixtures::sample_range();
+        let present = ManifestChunk {
+            range: "017190873-017190875".to_string(),
+            bloom_hash: raw_cid(&bloom_bytes).to_string(),
+            bloom_size: bloom_bytes.len() as u64,
+            index_hash: raw_cid(&chunk_bytes).to_string(),
+            index_size: chunk_bytes.len() as u64,
+        };
+        // Not in the archive.
+        let absent = ManifestChunk {
+            range: "017190876-017190880".to_string(),
+            bloom_hash: raw_cid(b"other bloom").to_string(),
+            bloom_size: 11,
+            index_hash: raw_cid(b"other chunk").to_string(),
+            index_size: 11,
+        };
+        let manifest = Manifest {
+            version: "test".to_string(),
+            chain: "mainnet".to_string(),
+            specification: String::new(),
+            chunks: vec![present.clone(), absent],
+        };
+        let chunks_dir = dir.join("chunks");
+        let blooms_dir = dir.join("blooms");
+        fs::create_dir_all(&chunks_dir).unwrap();
+        fs::create_dir_all(&blooms_dir).unwrap();
+
+        let unpacked = unpack_chunks(&mut car, &manifest, &chunks_dir, Some(&blooms_dir)).unwrap();
+        assert_eq!(
+            unpacked,
+            vec![
+                UnpackedFile {
+                    path: chunks_dir.join("017190873-017190875.bin"),
+                    range,
+                    cid: present.index_hash.clone(),
+                    size: chunk_bytes.len(),
+                },
+                UnpackedFile {
+                    path: blooms_dir.join("017190873-017190875.bloom"),
+                    range,
+                    cid: present.bloom_hash.clone(),
+                    size: bloom_bytes.len(),
+                },
+            ]
+        );
+        assert_eq!(fs::read(&unpacked[0].path).unwrap(), chunk_bytes);
+        assert_eq!(fs::read(&unpacked[1].path).unwrap(), bloom_bytes);
+        let mut names: Vec<_> = fs::read_dir(&chunks_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["017190873-017190875.bin"]);
+        let mut file = UnchainedFile::from_file(unpacked[0].path.clone(), range).unwrap();
+        assert_eq!(file.read_all().unwrap(), fixtures::sample_data());
+
+        // Without a blooms directory only chunks are written.
+        let unpacked = unpack_chunks(&mut car, &manifest, &chunks_dir, None).unwrap();
+        assert_eq!(unpacked.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+//! Per-chain settings for reading the Unchained Index.
+//!
+//! trueblocks-core publishes an index for several chains. They share the chunk
+//! format but differ in:
+//! - Fork blocks (e.g., the merge, after which withdrawals exist).
+//! - Which sentinel transaction indices are used for extra-block appearances.
+//! - Directory names ("{unchained}/{chain}/finalized" and ".../blooms").
+//!
+//! Known chains have presets (see [ChainConfig::named]). Other chains can be
+//! described with a JSON file (see [ChainConfig::from_file]).
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::structure::LocationKind;
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum ChainError {
+    #[error("File {filename} could not be opened {source}")]
+    FileOpener {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Chain config {filename} is not valid JSON {source}")]
+    InvalidJson {
+        source: serde_json::Error,
+        filename: PathBuf,
+    },
+}
+
+/// Chain used when none is specified.
+pub const DEFAULT_CHAIN: &str = "mainnet";
+
+/// Meaning of a sentinel transaction index.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sentinel {
+    pub index: u32,
+    pub kind: LocationKind,
+}
+
+/// Sentinels used on every chain.
+const COMMON_SENTINELS: [Sentinel; 3] = [
+    Sentinel {
+        index: 99999,
+        kind: LocationKind::Miner,
+    },
+    Sentinel {
+        index: 99998,
+        kind: LocationKind::Uncle,
+    },
+    Sentinel {
+        index: 99997,
+        kind: LocationKind::NullRecipient,
+    },
+];
+
+/// Settings for the index of a single chain.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainConfig {
+    /// Name used in manifests, the Unchained Index contract and directories.
+    pub name: String,
+    pub chain_id: Option<u64>,
+    /// Last block before proof of stake (0 if the chain started with proof of
+    /// stake, None if unknown or never merged).
+    pub merge_block: Option<u32>,
+    /// Sentinel transaction indices and what they mean.
+    pub sentinels: Vec<Sentinel>,
+    /// Directory holding the chunks, within the chain directory.
+    pub chunks_subdir: String,
+    /// Directory holding the blooms, within the chain directory.
+    pub blooms_subdir: String,
+}
+
+impl ChainConfig {
+    /// Settings for a chain that has no preset: common sentinels only and no
+    /// known merge block.
+    pub fn custom(name: &str) -> Self {
+        ChainConfig {
+            name: name.to_string(),
+            chain_id: None,
+            merge_block: None,
+            sentinels: COMMON_SENTINELS.to_vec(),
+            chunks_subdir: "finalized".to_string(),
+            blooms_subdir: "blooms".to_string(),
+        }
+    }
+    pub fn mainnet() -> Self {
+        ChainConfig {
+            chain_id: Some(1),
+            merge_block: Some(15_537_393),
+            ..ChainConfig::custom("mainnet")
+        }
+    }
+    pub fn sepolia() -> Self {
+        ChainConfig {
+            chain_id: Some(11_155_111),
+            merge_block: Some(1_450_408),
+            ..ChainConfig::custom("sepolia")
+        }
+    }
+    pub fn holesky() -> Self {
+        ChainConfig {
+            chain_id: Some(17_000),
+            merge_block: Some(0),
+            ..ChainConfig::custom("holesky")
+        }
+    }
+    /// Gnosis also records external (block reward contract) appearances.
+    pub fn gnosis() -> Self {
+        let mut sentinels = COMMON_SENTINELS.to_vec();
+        sentinels.push(Sentinel {
+            index: 99996,
+            kind: LocationKind::External,
+        });
+        ChainConfig {
+            chain_id: Some(100),
+            merge_block: Some(25_349_536),
+            sentinels,
+            ..ChainConfig::custom("gnosis")
+        }
+    }
+    /// Preset for a known chain, otherwise [ChainConfig::custom].
+    pub fn named(name: &str) -> Self {
+        match name {
+            "mainnet" => ChainConfig::mainnet(),
+            "sepolia" => ChainConfig::sepolia(),
+            "holesky" => ChainConfig::holesky(),
+            "gnosis" => ChainConfig::gnosis(),
+            other => ChainConfig::custom(other),
+        }
+    }
+    /// Reads settings from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self, ChainError> {
+        let file = File::open(path).map_err(|e| ChainError::FileOpener {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| ChainError::InvalidJson {
+            source: e,
+            filename: path.to_path_buf(),
+        })
+    }
+    /// Interprets a transaction index using the sentinels of the chain.
+    ///
+    /// An index is only a sentinel if the chain uses it (e.g., 99996 is a
+    /// transaction index on mainnet).
+    pub fn location_kind(&self, index: u32) -> LocationKind {
+        self.sentinels
+            .iter()
+            .find(|s| s.index == index)
+            .map_or(LocationKind::Transaction, |s| s.kind)
+    }
+    /// Whether a block is after the merge. False if the merge block is unknown.
+    pub fn is_post_merge(&self, block: u32) -> bool {
+        self.merge_block.is_some_and(|merge| block > merge)
+    }
+    /// Chunks directory under an "unchained" directory.
+    ///
+    /// E.g., "xyz/trueblocks/unchained" -> "xyz/trueblocks/unchained/mainnet/finalized"
+    pub fn chunks_dir(&self, unchained: &Path) -> PathBuf {
+        unchained.join(&self.name).join(&self.chunks_subdir)
+    }
+    /// Blooms directory under an "unchained" directory.
+    ///
+    /// E.g., "xyz/trueblocks/unchained" -> "xyz/trueblocks/unchained/mainnet/blooms"
+    pub fn blooms_dir(&self, unchained: &Path) -> PathBuf {
+        unchained.join(&self.name).join(&self.blooms_subdir)
+    }
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig::named(DEFAULT_CHAIN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_by_name() {
+        for (name, chain_id) in [
+            ("mainnet", Some(1)),
+            ("sepolia", Some(11_155_111)),
+            ("holesky", Some(17_000)),
+            ("gnosis", Some(100)),
+            ("mychain", None),
+        ] {
+            let chain = ChainConfig::named(name);
+            assert_eq!(chain.name, name);
+            assert_eq!(chain.chain_id, chain_id);
+        }
+        assert_eq!(ChainConfig::default(), ChainConfig::mainnet());
+
+        let custom = ChainConfig::custom("mychain");
+        assert_eq!(custom.merge_block, None);
+        assert_eq!(custom.sentinels, COMMON_SENTINELS.to_vec());
+        assert_eq!(
+            custom.chunks_dir(Path::new("unchained")),
+            Path::new("unchained/mychain/finalized")
+        );
+        assert_eq!(
+            custom.blooms_dir(Path::new("unchained")),
+            Path::new("unchained/mychain/blooms")
+        );
+    }
+
+    #[test]
+    fn sentinels_depend_on_chain() {
+        let mainnet = ChainConfig::mainnet();
+        let gnosis = ChainConfig::gnosis();
+        for chain in [&mainnet, &gnosis] {
+            assert_eq!(chain.location_kind(99999), LocationKind::Miner);
+            assert_eq!(chain.location_kind(99998), LocationKind::Uncle);
+            assert_eq!(chain.location_kind(99997), LocationKind::NullRecipient);
+            assert_eq!(chain.location_kind(5), LocationKind::Transaction);
+        }
+        assert_eq!(gnosis.location_kind(99996), LocationKind::External);
+        assert_eq!(mainnet.location_kind(99996), LocationKind::Transaction);
+    }
+
+    #[test]
+    fn post_merge_blocks() {
+        let mainnet = ChainConfig::mainnet();
+        assert!(!mainnet.is_post_merge(15_537_393));
+        assert!(mainnet.is_post_merge(15_537_394));
+        let holesky = ChainConfig::holesky();
+        assert!(!holesky.is_post_merge(0));
+        assert!(holesky.is_post_merge(1));
+        // Unknown merge block.
+        assert!(!ChainConfig::custom("mychain").is_post_merge(u32::MAX));
+    }
+
+    #[test]
+    fn reads_config_file() {
+        let dir = std::env::temp_dir().join(format!("unchained-chain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mychain.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "name": "mychain",
+                "chainId": 1337,
+                "mergeBlock": 10,
+                "sentinels": [{ "index": 99999, "kind": "miner" }, { "index": 99995, "kind": "external" }],
+                "chunksSubdir": "chunks",
+                "bloomsSubdir": "filters"
+            }"#,
+        )
+        .unwrap();
+        let chain = ChainConfig::from_file(&path).unwrap();
+        assert_eq!(chain.chain_id, Some(1337));
+        assert!(chain.is_post_merge(11));
+        assert_eq!(chain.location_kind(99995), LocationKind::External);
+        assert_eq!(chain.location_kind(99998), LocationKind::Transaction);
+        assert_eq!(
+            chain.chunks_dir(Path::new("u")),
+            Path::new("u/mychain/chunks")
+        );
+
+        std::fs::write(&path, r#"{ "name": "mychain" }"#).unwrap();
+        assert!(matches!(
+            ChainConfig::from_file(&path),
+            Err(ChainError::InvalidJson { .. })
+        ));
+        assert!(matches!(
+            ChainConfig::from_file(&dir.join("missing.json")),
+            Err(ChainError::FileOpener { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
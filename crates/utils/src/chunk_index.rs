@@ -0,0 +1,705 @@
+//! Persistent inverted index mapping each address to the chunks it appears in.
+//!
+//! The index is a sidecar file for a chunks directory. Queries for an address
+//! only need to open the chunks the index lists, with no false positives.
+//! New chunks are added incrementally by reading only their address tables.
+//!
+//! The size and modification time of each chunk file are recorded. Chunks that
+//! are removed or replaced (e.g., re-hashed with the same range) are dropped
+//! from the index on the next update. Until then, queries treat a replaced
+//! chunk as not indexed.
+//!
+//! [ChunkIndex] holds the whole index in memory, for updates. Queries use
+//! [IndexFile], which binary searches the address table on disk, so the cost
+//! of a lookup does not grow with the number of addresses indexed.
+
+/*
+The index file has structure (all values little endian):
+- header (16 bytes)
+    - magic "UCIX" (4 bytes)
+    - format version (4 bytes)
+    - nchunks (4 bytes)
+    - naddresses (4 bytes)
+- chunks (nchunks * 24 bytes)
+    - oldest block (4 bytes)
+    - newest block (4 bytes)
+    - file size (8 bytes)
+    - file modification time, nanoseconds since the unix epoch (8 bytes)
+- addresses (naddresses * 28 bytes), sorted by address
+    - address (20 bytes)
+    - offset of the first position in the positions table (4 bytes)
+    - count (4 bytes)
+- positions (4 bytes each)
+    - position of a chunk in the chunks table
+*/
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    address::Address,
+    constants::ADDR,
+    files::{partial_path, ChunkFile, ChunksDir},
+    parse::{BlockRange, ParseError, UnchainedFile},
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum ChunkIndexError {
+    #[error("File {filename} could not be opened {source}")]
+    FileOpener {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Index file {path} has magic bytes {found}, but expected {expected}")]
+    InvalidMagicBytes {
+        path: PathBuf,
+        found: String,
+        expected: String,
+    },
+    #[error("Index file {path} has format version {found}, but expected {expected}")]
+    UnsupportedVersion {
+        path: PathBuf,
+        found: u32,
+        expected: u32,
+    },
+    #[error("Index file {path} refers to chunk {position}, but only has {n_chunks} chunks")]
+    InvalidChunkPosition {
+        path: PathBuf,
+        position: u32,
+        n_chunks: usize,
+    },
+    #[error("Unable to read index file {path} {source}")]
+    Read {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[error("Unable to write index file {path} {source}")]
+    Write {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[error("Too many entries ({count}) for an index file")]
+    TooManyEntries { count: usize },
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+}
+
+/// Magic bytes of an index file.
+const INDEX_MAGIC: [u8; 4] = *b"UCIX";
+
+/// Format version of the index file.
+const INDEX_VERSION: u32 = 1;
+
+/// Bytes in the header.
+const INDEX_HEADER: u64 = 16;
+
+/// Bytes in a chunks table entry.
+const CHUNK_ENTRY: u64 = 24;
+
+/// Bytes in an address table entry.
+const ADDRESS_ENTRY: u64 = ADDR as u64 + 8;
+
+/// Bytes in a positions table entry.
+const POSITION_ENTRY: u64 = 4;
+
+/// Details of an update to the index.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexUpdate {
+    pub chunks_added: usize,
+    /// Chunks dropped because their file was removed or has changed.
+    pub chunks_removed: usize,
+    pub chunks_total: usize,
+    pub addresses_total: usize,
+}
+
+/// A chunk in the index, with details of the file when it was indexed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndexedChunk {
+    pub range: BlockRange,
+    /// Bytes in the file.
+    pub size: u64,
+    /// Modification time of the file, in nanoseconds since the unix epoch.
+    pub modified: u64,
+}
+
+impl IndexedChunk {
+    /// Reads the details of a chunk file.
+    pub fn of_file(chunk: &ChunkFile) -> Result<Self, ChunkIndexError> {
+        let metadata_err = |e| ChunkIndexError::FileOpener {
+            source: e,
+            filename: chunk.path.to_path_buf(),
+        };
+        let metadata = fs::metadata(&chunk.path).map_err(metadata_err)?;
+        let modified = metadata
+            .modified()
+            .map_err(metadata_err)?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Ok(IndexedChunk {
+            range: chunk.range,
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Maps addresses to the chunks they appear in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkIndex {
+    /// The indexed chunks, in the order they were added.
+    pub chunks: Vec<IndexedChunk>,
+    /// Positions in the chunks table for each address.
+    pub entries: BTreeMap<Address, Vec<u32>>,
+}
+
+impl ChunkIndex {
+    /// Reads an index file.
+    pub fn from_file(path: &Path) -> Result<Self, ChunkIndexError> {
+        let file = File::open(path).map_err(|e| ChunkIndexError::FileOpener {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        let mut rdr = BufReader::new(file);
+        read_index(&mut rdr, path)
+    }
+
+    /// Reads an index file, or starts an empty index if the file does not exist.
+    pub fn from_file_or_new(path: &Path) -> Result<Self, ChunkIndexError> {
+        match path.exists() {
+            true => ChunkIndex::from_file(path),
+            false => Ok(ChunkIndex::default()),
+        }
+    }
+
+    /// Writes the index file.
+    ///
+    /// The file is written alongside and then renamed, so an interrupted write
+    /// leaves the previous index intact.
+    pub fn write(&self, path: &Path) -> Result<(), ChunkIndexError> {
+        let tables = IndexTables::new(self)?;
+        let partial = partial_path(path);
+        let file = File::create(&partial).map_err(|e| ChunkIndexError::FileOpener {
+            source: e,
+            filename: partial.to_path_buf(),
+        })?;
+        let mut wtr = BufWriter::new(file);
+        write_index(&mut wtr, self, &tables)
+            .and_then(|_| wtr.flush())
+            .and_then(|_| fs::rename(&partial, path))
+            .map_err(|e| ChunkIndexError::Write {
+                source: e,
+                path: path.to_path_buf(),
+            })
+    }
+
+    /// Brings the index in line with the chunks in the directory.
+    ///
+    /// Chunks whose file is missing or has changed are removed, then chunks
+    /// that are not yet indexed are added.
+    pub fn update(&mut self, dir: &ChunksDir) -> Result<IndexUpdate, ChunkIndexError> {
+        let current = dir
+            .paths
+            .iter()
+            .map(IndexedChunk::of_file)
+            .collect::<Result<Vec<IndexedChunk>, ChunkIndexError>>()?;
+        let chunks_removed = self.retain(&current);
+        let mut chunks_added = 0;
+        for (chunk, indexed) in dir.paths.iter().zip(current) {
+            if self.chunks.contains(&indexed) {
+                continue;
+            }
+            let mut file = UnchainedFile::from_file(chunk.path.to_owned(), chunk.range)?;
+            let position = to_u32(self.chunks.len())?;
+            for address in file.addresses()? {
+                self.entries.entry(address).or_default().push(position);
+            }
+            self.chunks.push(indexed);
+            chunks_added += 1;
+        }
+        Ok(IndexUpdate {
+            chunks_added,
+            chunks_removed,
+            chunks_total: self.chunks.len(),
+            addresses_total: self.entries.len(),
+        })
+    }
+
+    /// Removes indexed chunks that are not in the given list, returning how
+    /// many were removed.
+    ///
+    /// Positions of the remaining chunks are renumbered and addresses left
+    /// without chunks are dropped.
+    fn retain(&mut self, current: &[IndexedChunk]) -> usize {
+        let mut kept: Vec<IndexedChunk> = vec![];
+        let mut new_positions: Vec<Option<u32>> = Vec::with_capacity(self.chunks.len());
+        for chunk in &self.chunks {
+            match current.contains(chunk) {
+                true => {
+                    new_positions.push(Some(kept.len() as u32));
+                    kept.push(*chunk);
+                }
+                false => new_positions.push(None),
+            }
+        }
+        let removed = self.chunks.len() - kept.len();
+        if removed == 0 {
+            return 0;
+        }
+        self.chunks = kept;
+        self.entries.retain(|_, positions| {
+            *positions = positions
+                .iter()
+                .filter_map(|p| new_positions[*p as usize])
+                .collect();
+            !positions.is_empty()
+        });
+        removed
+    }
+
+    /// True if the chunk file is in the index and has not changed since.
+    pub fn is_indexed(&self, chunk: &ChunkFile) -> Result<bool, ChunkIndexError> {
+        Ok(self.chunks.contains(&IndexedChunk::of_file(chunk)?))
+    }
+
+    /// Ranges of the chunks that an address appears in, oldest first.
+    pub fn chunks_for(&self, address: &Address) -> Vec<BlockRange> {
+        let mut ranges: Vec<BlockRange> = match self.entries.get(address) {
+            Some(positions) => positions
+                .iter()
+                .map(|p| self.chunks[*p as usize].range)
+                .collect(),
+            None => vec![],
+        };
+        ranges.sort_by_key(|r| r.old);
+        ranges
+    }
+
+    /// Keeps only the chunks of a directory that may hold the address.
+    ///
+    /// Indexed chunks are kept if the address appears in them. Chunks that are
+    /// not yet indexed, or have changed since, are always kept.
+    pub fn filter_dir(
+        &self,
+        dir: &ChunksDir,
+        address: &Address,
+    ) -> Result<ChunksDir, ChunkIndexError> {
+        filter_chunks(dir, &self.chunks, &self.chunks_for(address))
+    }
+}
+
+/// An index file opened for lookups.
+///
+/// Only the header and chunks table are read when opening. Each lookup binary
+/// searches the address table on disk.
+pub struct IndexFile {
+    pub path: PathBuf,
+    /// The indexed chunks.
+    pub chunks: Vec<IndexedChunk>,
+    pub n_addresses: u32,
+    reader: BufReader<File>,
+}
+
+impl IndexFile {
+    /// Opens an index file and reads its chunks table.
+    pub fn open(path: &Path) -> Result<Self, ChunkIndexError> {
+        let file = File::open(path).map_err(|e| ChunkIndexError::FileOpener {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        let mut reader = BufReader::new(file);
+        let (chunks, n_addresses) = read_header(&mut reader, path)?;
+        Ok(IndexFile {
+            path: path.to_path_buf(),
+            chunks,
+            n_addresses,
+            reader,
+        })
+    }
+
+    /// Ranges of the chunks that an address appears in, oldest first.
+    pub fn chunks_for(&mut self, address: &Address) -> Result<Vec<BlockRange>, ChunkIndexError> {
+        let addresses_start = INDEX_HEADER + self.chunks.len() as u64 * CHUNK_ENTRY;
+        let positions_start = addresses_start + u64::from(self.n_addresses) * ADDRESS_ENTRY;
+        let (mut low, mut high) = (0u64, u64::from(self.n_addresses));
+        let mut found: Option<(u32, u32)> = None;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            self.seek(addresses_start + mid * ADDRESS_ENTRY)?;
+            let mut candidate = [0u8; ADDR];
+            self.reader
+                .read_exact(&mut candidate)
+                .map_err(|e| self.read_err(e))?;
+            match Address(candidate).cmp(address) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => {
+                    let offset = self.read_u32()?;
+                    let count = self.read_u32()?;
+                    found = Some((offset, count));
+                    break;
+                }
+            }
+        }
+        let Some((offset, count)) = found else {
+            return Ok(vec![]);
+        };
+        self.seek(positions_start + u64::from(offset) * POSITION_ENTRY)?;
+        let mut ranges = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let position = self.read_u32()?;
+            let chunk = self.chunks.get(position as usize).ok_or_else(|| {
+                ChunkIndexError::InvalidChunkPosition {
+                    path: self.path.to_path_buf(),
+                    position,
+                    n_chunks: self.chunks.len(),
+                }
+            })?;
+            ranges.push(chunk.range);
+        }
+        ranges.sort_by_key(|r| r.old);
+        Ok(ranges)
+    }
+
+    /// Keeps only the chunks of a directory that may hold the address.
+    ///
+    /// See [ChunkIndex::filter_dir].
+    pub fn filter_dir(
+        &mut self,
+        dir: &ChunksDir,
+        address: &Address,
+    ) -> Result<ChunksDir, ChunkIndexError> {
+        let relevant = self.chunks_for(address)?;
+        filter_chunks(dir, &self.chunks, &relevant)
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), ChunkIndexError> {
+        self.reader
+            .seek(SeekFrom::Start(position))
+            .map(|_| ())
+            .map_err(|e| self.read_err(e))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ChunkIndexError> {
+        self.reader
+            .read_u32::<LittleEndian>()
+            .map_err(|e| self.read_err(e))
+    }
+
+    fn read_err(&self, source: std::io::Error) -> ChunkIndexError {
+        ChunkIndexError::Read {
+            source,
+            path: self.path.to_path_buf(),
+        }
+    }
+}
+
+/// Keeps chunks that are relevant, or not indexed as they are now.
+fn filter_chunks(
+    dir: &ChunksDir,
+    indexed: &[IndexedChunk],
+    relevant: &[BlockRange],
+) -> Result<ChunksDir, ChunkIndexError> {
+    let mut paths = vec![];
+    for chunk in &dir.paths {
+        if relevant.contains(&chunk.range) || !indexed.contains(&IndexedChunk::of_file(chunk)?) {
+            paths.push(chunk.clone());
+        }
+    }
+    Ok(ChunksDir {
+        dir: dir.dir.to_path_buf(),
+        paths,
+    })
+}
+
+/// Reads the header and chunks table, returning the chunks and the number of addresses.
+fn read_header(
+    rdr: &mut impl Read,
+    path: &Path,
+) -> Result<(Vec<IndexedChunk>, u32), ChunkIndexError> {
+    let read_err = |e| ChunkIndexError::Read {
+        source: e,
+        path: path.to_path_buf(),
+    };
+    let mut magic = [0u8; 4];
+    rdr.read_exact(&mut magic).map_err(read_err)?;
+    if magic != INDEX_MAGIC {
+        return Err(ChunkIndexError::InvalidMagicBytes {
+            path: path.to_path_buf(),
+            found: hex::encode(magic),
+            expected: hex::encode(INDEX_MAGIC),
+        });
+    }
+    let version = rdr.read_u32::<LittleEndian>().map_err(read_err)?;
+    if version != INDEX_VERSION {
+        return Err(ChunkIndexError::UnsupportedVersion {
+            path: path.to_path_buf(),
+            found: version,
+            expected: INDEX_VERSION,
+        });
+    }
+    let n_chunks = rdr.read_u32::<LittleEndian>().map_err(read_err)?;
+    let n_addresses = rdr.read_u32::<LittleEndian>().map_err(read_err)?;
+    let mut chunks = Vec::with_capacity(n_chunks as usize);
+    for _ in 0..n_chunks {
+        let old = rdr.read_u32::<LittleEndian>().map_err(read_err)?;
+        let new = rdr.read_u32::<LittleEndian>().map_err(read_err)?;
+        let size = rdr.read_u64::<LittleEndian>().map_err(read_err)?;
+        let modified = rdr.read_u64::<LittleEndian>().map_err(read_err)?;
+        chunks.push(IndexedChunk {
+            range: BlockRange { old, new },
+            size,
+            modified,
+        });
+    }
+    Ok((chunks, n_addresses))
+}
+
+fn read_index(rdr: &mut impl Read, path: &Path) -> Result<ChunkIndex, ChunkIndexError> {
+    let read_err = |e| ChunkIndexError::Read {
+        source: e,
+        path: path.to_path_buf(),
+    };
+    let (chunks, n_addresses) = read_header(rdr, path)?;
+    let mut table = Vec::with_capacity(n_addresses as usize);
+    for _ in 0..n_addresses {
+        let mut address = [0u8; ADDR];
+        rdr.read_exact(&mut address).map_err(read_err)?;
+        let _offset = rdr.read_u32::<LittleEndian>().map_err(read_err)?;
+        let count = rdr.read_u32::<LittleEndian>().map_err(read_err)?;
+        table.push((Address(address), count));
+    }
+    // Positions are stored in address order.
+    let mut entries = BTreeMap::new();
+    for (address, count) in table {
+        let mut positions = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let position = rdr.read_u32::<LittleEndian>().map_err(read_err)?;
+            if position as usize >= chunks.len() {
+                return Err(ChunkIndexError::InvalidChunkPosition {
+                    path: path.to_path_buf(),
+                    position,
+                    n_chunks: chunks.len(),
+                });
+            }
+            positions.push(position);
+        }
+        entries.insert(address, positions);
+    }
+    Ok(ChunkIndex { chunks, entries })
+}
+
+/// Counts and offsets of an index, checked to fit the file format.
+struct IndexTables {
+    n_chunks: u32,
+    n_addresses: u32,
+    /// Offset and count of the positions of each address.
+    entries: Vec<(u32, u32)>,
+}
+
+impl IndexTables {
+    fn new(index: &ChunkIndex) -> Result<Self, ChunkIndexError> {
+        let mut entries = Vec::with_capacity(index.entries.len());
+        let mut offset: u32 = 0;
+        for positions in index.entries.values() {
+            let count = to_u32(positions.len())?;
+            entries.push((offset, count));
+            offset = offset
+                .checked_add(count)
+                .ok_or(ChunkIndexError::TooManyEntries {
+                    count: offset as usize + positions.len(),
+                })?;
+        }
+        Ok(IndexTables {
+            n_chunks: to_u32(index.chunks.len())?,
+            n_addresses: to_u32(index.entries.len())?,
+            entries,
+        })
+    }
+}
+
+fn write_index(
+    wtr: &mut impl Write,
+    index: &ChunkIndex,
+    tables: &IndexTables,
+) -> Result<(), std::io::Error> {
+    wtr.write_all(&INDEX_MAGIC)?;
+    wtr.write_u32::<LittleEndian>(INDEX_VERSION)?;
+    wtr.write_u32::<LittleEndian>(tables.n_chunks)?;
+    wtr.write_u32::<LittleEndian>(tables.n_addresses)?;
+    for chunk in &index.chunks {
+        wtr.write_u32::<LittleEndian>(chunk.range.old)?;
+        wtr.write_u32::<LittleEndian>(chunk.range.new)?;
+        wtr.write_u64::<LittleEndian>(chunk.size)?;
+        wtr.write_u64::<LittleEndian>(chunk.modified)?;
+    }
+    for (address, (offset, count)) in index.entries.keys().zip(&tables.entries) {
+        wtr.write_all(address.as_bytes())?;
+        wtr.write_u32::<LittleEndian>(*offset)?;
+        wtr.write_u32::<LittleEndian>(*count)?;
+    }
+    for position in index.entries.values().flatten() {
+        wtr.write_u32::<LittleEndian>(*position)?;
+    }
+    Ok(())
+}
+
+fn to_u32(count: usize) -> Result<u32, ChunkIndexError> {
+    u32::try_from(count).map_err(|_| ChunkIndexError::TooManyEntries { count })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{self, ADDRESS_A, ADDRESS_B, ADDRESS_C},
+        structure::AddressData,
+    };
+
+    fn range(old: u32, new: u32) -> BlockRange {
+        BlockRange { old, new }
+    }
+
+    /// An index of three chunks and many addresses.
+    fn sample_index() -> ChunkIndex {
+        let mut entries = BTreeMap::new();
+        for n in 0..1000u32 {
+            let mut address = [0u8; ADDR];
+            address[..4].copy_from_slice(&(n * 7919).to_be_bytes());
+            let positions = match n % 3 {
+                0 => vec![0],
+                1 => vec![2, 1],
+                _ => vec![0, 1, 2],
+            };
+            entries.insert(Address(address), positions);
+        }
+        let chunks = [range(0, 9), range(20, 29), range(10, 19)]
+            .into_iter()
+            .enumerate()
+            .map(|(n, range)| IndexedChunk {
+                range,
+                size: 1000 + n as u64,
+                modified: 2000 + n as u64,
+            })
+            .collect();
+        ChunkIndex { chunks, entries }
+    }
+
+    #[test]
+    fn index_file_lookups_match_memory() {
+        let path =
+            std::env::temp_dir().join(format!("unchained-index-{}.ucix", std::process::id()));
+        let index = sample_index();
+        index.write(&path).unwrap();
+        assert!(!partial_path(&path).exists());
+        assert_eq!(ChunkIndex::from_file(&path).unwrap(), index);
+
+        let mut file = IndexFile::open(&path).unwrap();
+        assert_eq!(file.chunks, index.chunks);
+        for address in index.entries.keys() {
+            assert_eq!(file.chunks_for(address).unwrap(), index.chunks_for(address));
+        }
+        // Before, between and after the indexed addresses.
+        let mut between = [0u8; ADDR];
+        between[ADDR - 1] = 1;
+        let missing = [Address([0xff; ADDR]), Address(between), Address([1; ADDR])];
+        for address in missing {
+            assert!(!index.entries.contains_key(&address));
+            assert!(file.chunks_for(&address).unwrap().is_empty());
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn empty_index_has_no_chunks_for_address() {
+        let path =
+            std::env::temp_dir().join(format!("unchained-index-empty-{}.ucix", std::process::id()));
+        ChunkIndex::default().write(&path).unwrap();
+        let mut file = IndexFile::open(&path).unwrap();
+        assert!(file.chunks_for(&Address([1; ADDR])).unwrap().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    fn ranges(dir: &ChunksDir) -> Vec<BlockRange> {
+        dir.paths.iter().map(|chunk| chunk.range).collect()
+    }
+
+    #[test]
+    fn update_drops_removed_and_changed_chunks() {
+        let dir = fixtures::test_dir("chunk-index-update");
+        let chunks_dir = dir.join("chunks");
+        fs::create_dir_all(&chunks_dir).unwrap();
+        let sample = fixtures::write_sample(&chunks_dir);
+        let later_range = BlockRange::new(17_190_876, 17_190_880).unwrap();
+        let b_later = AddressData {
+            address: fixtures::address(ADDRESS_B),
+            appearances: vec![fixtures::tx(17_190_876, 1)],
+        };
+        let later = fixtures::write_fixture(&chunks_dir, later_range, vec![b_later]);
+        let (a, b, c) = (
+            fixtures::address(ADDRESS_A),
+            fixtures::address(ADDRESS_B),
+            fixtures::address(ADDRESS_C),
+        );
+
+        let mut index = ChunkIndex::default();
+        let update = index.update(&ChunksDir::new(&chunks_dir).unwrap()).unwrap();
+        assert_eq!((update.chunks_added, update.chunks_removed), (2, 0));
+        assert_eq!(update.addresses_total, 3);
+        assert_eq!(index.chunks_for(&b), vec![sample.range, later_range]);
+        let update = index.update(&ChunksDir::new(&chunks_dir).unwrap()).unwrap();
+        assert_eq!((update.chunks_added, update.chunks_removed), (0, 0));
+
+        // Same range, same size, different addresses.
+        let c_later = AddressData {
+            address: c,
+            appearances: vec![fixtures::tx(17_190_876, 1)],
+        };
+        fixtures::write_fixture(&chunks_dir, later_range, vec![c_later]);
+        let file = File::options().write(true).open(&later.path).unwrap();
+        file.set_modified(UNIX_EPOCH).unwrap();
+        assert!(!index.is_indexed(&later).unwrap());
+        // Before the update, the changed chunk is kept for any address.
+        let chunks = ChunksDir::new(&chunks_dir).unwrap();
+        assert_eq!(
+            ranges(&index.filter_dir(&chunks, &a).unwrap()),
+            ranges(&chunks)
+        );
+        let path = dir.join("index.ucix");
+        index.write(&path).unwrap();
+        let mut file = IndexFile::open(&path).unwrap();
+        assert_eq!(
+            ranges(&file.filter_dir(&chunks, &a).unwrap()),
+            ranges(&chunks)
+        );
+
+        let update = index.update(&chunks).unwrap();
+        assert_eq!((update.chunks_added, update.chunks_removed), (1, 1));
+        assert_eq!(index.chunks_for(&b), vec![sample.range]);
+        assert_eq!(index.chunks_for(&c), vec![sample.range, later_range]);
+        assert_eq!(
+            ranges(&index.filter_dir(&chunks, &a).unwrap()),
+            vec![sample.range]
+        );
+
+        // A removed chunk is dropped, along with addresses only in that chunk.
+        fs::remove_file(&sample.path).unwrap();
+        let update = index.update(&ChunksDir::new(&chunks_dir).unwrap()).unwrap();
+        assert_eq!((update.chunks_added, update.chunks_removed), (0, 1));
+        assert_eq!(update.addresses_total, 1);
+        assert!(index.chunks_for(&a).is_empty());
+        assert_eq!(index.chunks_for(&c), vec![later_range]);
+        index.write(&path).unwrap();
+        assert_eq!(ChunkIndex::from_file(&path).unwrap(), index);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
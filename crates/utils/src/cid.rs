@@ -0,0 +1,402 @@
+//! Offline computation of IPFS CIDv0 identifiers.
+//!
+//! Produces the same CID as `ipfs add` with default settings, so that local
+//! files can be checked against the CIDs published in the manifest:
+//! - Fixed size chunker of 256 KiB.
+//! - Balanced DAG layout with at most 174 links per node.
+//! - UnixFS "File" nodes in dag-pb, including leaves (no raw leaves).
+//! - sha2-256 multihash, base58btc encoded.
+//!
+//! CIDs can also be parsed and formatted (see [Cid]).
+//!
+//! See also: <https://github.com/ipfs/specs/blob/main/UNIXFS.md>
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum CidError {
+    #[error("File {filename} could not be opened {source}")]
+    FileOpener {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Unable to read {filename} {source}")]
+    Read {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Invalid CID {0}")]
+    InvalidCid(String),
+}
+
+/// Bytes of file data in each leaf node.
+pub const CHUNK_SIZE: usize = 262_144;
+
+/// Maximum number of links from a node.
+pub const MAX_LINKS: usize = 174;
+
+/// Multihash prefix for sha2-256: code 0x12, length 32.
+const SHA2_256_PREFIX: [u8; 2] = [0x12, 0x20];
+
+/// UnixFS data type "File".
+const UNIXFS_FILE: u64 = 2;
+
+/// Summary of a DAG node, as needed by its parent.
+#[derive(Clone, Debug)]
+struct NodeSummary {
+    multihash: Vec<u8>,
+    /// Size of the node and all nodes below it.
+    cumulative_size: u64,
+    /// Bytes of file data below the node.
+    file_size: u64,
+}
+
+/// CID and size of a file as added to IPFS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileCid {
+    /// CIDv0, e.g. "QmVuHWrW2DtRZcoosk7i13gUfyHMRSzugNvHvhFcq2Wk5Z"
+    pub cid: String,
+    /// Bytes of file data.
+    pub size: u64,
+}
+
+/// Computes the CIDv0 of data read from a source.
+///
+/// The data is read in chunks, so memory use does not depend on the file size.
+pub fn cid_v0(mut rdr: impl Read) -> Result<FileCid, std::io::Error> {
+    let mut leaves: Vec<NodeSummary> = vec![];
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let filled = read_chunk(&mut rdr, &mut buf)?;
+        if filled == 0 && !leaves.is_empty() {
+            break;
+        }
+        leaves.push(leaf(&buf[..filled]));
+        if filled < CHUNK_SIZE {
+            break;
+        }
+    }
+    let mut level = leaves;
+    while level.len() > 1 {
+        level = level.chunks(MAX_LINKS).map(parent).collect();
+    }
+    let root = level.remove(0);
+    Ok(FileCid {
+        cid: bs58::encode(&root.multihash).into_string(),
+        size: root.file_size,
+    })
+}
+
+/// Computes the CIDv0 of a file.
+pub fn cid_v0_of_file(path: &Path) -> Result<FileCid, CidError> {
+    let file = File::open(path).map_err(|e| CidError::FileOpener {
+        source: e,
+        filename: path.to_path_buf(),
+    })?;
+    cid_v0(BufReader::new(file)).map_err(|e| CidError::Read {
+        source: e,
+        filename: path.to_path_buf(),
+    })
+}
+
+/// Computes the CIDv0 of in-memory data.
+pub fn cid_v0_of_bytes(bytes: &[u8]) -> FileCid {
+    cid_v0(bytes).expect("Reading from a slice does not fail")
+}
+
+/// Fills the buffer unless the end of the data is reached first.
+fn read_chunk(rdr: &mut impl Read, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match rdr.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Leaf node holding file data.
+fn leaf(data: &[u8]) -> NodeSummary {
+    let mut unixfs = vec![];
+    put_varint_field(&mut unixfs, 1, UNIXFS_FILE);
+    if !data.is_empty() {
+        put_bytes_field(&mut unixfs, 2, data);
+    }
+    put_varint_field(&mut unixfs, 3, data.len() as u64);
+    let mut node = vec![];
+    put_bytes_field(&mut node, 1, &unixfs);
+    NodeSummary {
+        multihash: multihash(&node),
+        cumulative_size: node.len() as u64,
+        file_size: data.len() as u64,
+    }
+}
+
+/// Node linking to child nodes.
+fn parent(children: &[NodeSummary]) -> NodeSummary {
+    let file_size: u64 = children.iter().map(|c| c.file_size).sum();
+    let mut unixfs = vec![];
+    put_varint_field(&mut unixfs, 1, UNIXFS_FILE);
+    put_varint_field(&mut unixfs, 3, file_size);
+    for child in children {
+        put_varint_field(&mut unixfs, 4, child.file_size);
+    }
+    // dag-pb places links before data.
+    let mut node = vec![];
+    for child in children {
+        let mut link = vec![];
+        put_bytes_field(&mut link, 1, &child.multihash);
+        put_bytes_field(&mut link, 2, &[]);
+        put_varint_field(&mut link, 3, child.cumulative_size);
+        put_bytes_field(&mut node, 2, &link);
+    }
+    put_bytes_field(&mut node, 1, &unixfs);
+    NodeSummary {
+        multihash: multihash(&node),
+        cumulative_size: node.len() as u64
+            + children.iter().map(|c| c.cumulative_size).sum::<u64>(),
+        file_size,
+    }
+}
+
+fn multihash(block: &[u8]) -> Vec<u8> {
+    let mut hash = SHA2_256_PREFIX.to_vec();
+    hash.extend_from_slice(&Sha256::digest(block));
+    hash
+}
+
+/// Protobuf base 128 varint.
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Protobuf field with wire type 0 (varint).
+fn put_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(buf, field << 3);
+    put_varint(buf, value);
+}
+
+/// Protobuf field with wire type 2 (length delimited).
+fn put_bytes_field(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_varint(buf, (field << 3) | 2);
+    put_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// Multicodec code of dag-pb.
+pub const DAG_PB: u64 = 0x70;
+
+/// Multicodec code of raw binary.
+pub const RAW: u64 = 0x55;
+
+/// Multihash code of sha2-256.
+pub const SHA2_256: u64 = 0x12;
+
+/// A content identifier (CIDv0 or CIDv1).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cid {
+    pub version: u64,
+    /// Multicodec of the content, e.g. [DAG_PB].
+    pub codec: u64,
+    /// Multihash: code, digest length and digest.
+    pub multihash: Vec<u8>,
+}
+
+impl Cid {
+    /// Binary form, as used in CAR files.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.version {
+            0 => self.multihash.clone(),
+            _ => {
+                let mut bytes = vec![];
+                put_varint(&mut bytes, self.version);
+                put_varint(&mut bytes, self.codec);
+                bytes.extend_from_slice(&self.multihash);
+                bytes
+            }
+        }
+    }
+    /// Parses the binary form, returning the CID and the bytes it used.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), CidError> {
+        if bytes.starts_with(&SHA2_256_PREFIX) {
+            let multihash = bytes
+                .get(..34)
+                .ok_or_else(|| CidError::InvalidCid("Truncated CIDv0".to_string()))?;
+            let cid = Cid {
+                version: 0,
+                codec: DAG_PB,
+                multihash: multihash.to_vec(),
+            };
+            return Ok((cid, 34));
+        }
+        let mut position = 0;
+        let version = take_varint(bytes, &mut position)?;
+        if version != 1 {
+            return Err(CidError::InvalidCid(format!(
+                "Unsupported version {}",
+                version
+            )));
+        }
+        let codec = take_varint(bytes, &mut position)?;
+        let start = position;
+        take_varint(bytes, &mut position)?;
+        let digest_len = take_varint(bytes, &mut position)? as usize;
+        let end = position + digest_len;
+        let multihash = bytes
+            .get(start..end)
+            .ok_or_else(|| CidError::InvalidCid("Truncated multihash".to_string()))?;
+        let cid = Cid {
+            version,
+            codec,
+            multihash: multihash.to_vec(),
+        };
+        Ok((cid, end))
+    }
+    /// Multihash code and digest.
+    pub fn digest(&self) -> Result<(u64, &[u8]), CidError> {
+        let mut position = 0;
+        let code = take_varint(&self.multihash, &mut position)?;
+        let len = take_varint(&self.multihash, &mut position)? as usize;
+        let digest = self
+            .multihash
+            .get(position..position + len)
+            .ok_or_else(|| CidError::InvalidCid("Truncated multihash".to_string()))?;
+        Ok((code, digest))
+    }
+}
+
+impl std::str::FromStr for Cid {
+    type Err = CidError;
+    /// Parses a CIDv0 ("Qm...", base58btc) or CIDv1 ("b..." base32 or "z..." base58btc).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CidError::InvalidCid(s.to_string());
+        let bytes = match s.chars().next() {
+            _ if s.len() == 46 && s.starts_with("Qm") => {
+                bs58::decode(s).into_vec().map_err(|_| invalid())?
+            }
+            Some('b') => base32_decode(&s[1..]).ok_or_else(invalid)?,
+            Some('z') => bs58::decode(&s[1..]).into_vec().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        let (cid, used) = Cid::from_bytes(&bytes)?;
+        if used != bytes.len() {
+            return Err(invalid());
+        }
+        Ok(cid)
+    }
+}
+
+impl std::fmt::Display for Cid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.version {
+            0 => write!(f, "{}", bs58::encode(&self.multihash).into_string()),
+            _ => write!(f, "b{}", base32_encode(&self.to_bytes())),
+        }
+    }
+}
+
+/// Reads a varint from a buffer, advancing the position.
+pub(crate) fn take_varint(bytes: &[u8], position: &mut usize) -> Result<u64, CidError> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes
+            .get(*position)
+            .ok_or_else(|| CidError::InvalidCid("Truncated varint".to_string()))?;
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(CidError::InvalidCid("Varint too long".to_string()))
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// RFC 4648 base32, lowercase without padding.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Repeating bytes 0..251, so that no two adjacent leaves are identical.
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|n| (n % 251) as u8).collect()
+    }
+
+    #[test]
+    fn single_leaf() {
+        let empty = cid_v0_of_bytes(&[]);
+        assert_eq!(empty.cid, "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH");
+        assert_eq!(empty.size, 0);
+        let hello = cid_v0_of_bytes(b"hello world\n");
+        assert_eq!(hello.cid, "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o");
+        assert_eq!(hello.size, 12);
+    }
+
+    // The multi-leaf CIDs below come from a separate implementation of the
+    // `ipfs add` balanced layout that reproduces the single leaf CIDs above.
+    #[test]
+    fn one_level_of_links() {
+        let data = pattern(3 * CHUNK_SIZE + 7);
+        let found = cid_v0_of_bytes(&data);
+        assert_eq!(found.cid, "QmYKHnGmGcTZUhG63wV6bUNX5acWmpjrgM5BcUNBdxVcuS");
+        assert_eq!(found.size, data.len() as u64);
+    }
+
+    #[test]
+    fn two_levels_of_links() {
+        // 176 leaves: a full node of 174 and a second node with the last two.
+        let data = pattern((MAX_LINKS + 1) * CHUNK_SIZE + 1000);
+        let found = cid_v0_of_bytes(&data);
+        assert_eq!(found.cid, "Qmdzf5S4xjJu3i5XVUyWtM8apot3s87W1y1qhDn9XE4jhU");
+        assert_eq!(found.size, data.len() as u64);
+    }
+}
@@ -1,4 +1,4 @@
-/// Constants particular to Unchained Index files.
+//! Constants particular to Unchained Index files.
 
 /*
 The unchained index files (block-a_block_b.bin) have structure:
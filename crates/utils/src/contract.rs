@@ -0,0 +1,311 @@
+//! Reads the manifest CID from the Unchained Index contract.
+//!
+//! Publishers record the CID of the latest manifest for each chain in the
+//! contract's `manifestHashMap(address publisher, string chain)` mapping. The
+//! value is read with an `eth_call` to any JSON-RPC endpoint (the `http`
+//! feature is required for [manifest_cid]). The call data and the ABI encoded
+//! string returned can also be built and decoded without a network connection.
+//!
+//! See also: <https://etherscan.io/address/0x0c316b7042b419d07d343f2f4f5bd54ff731183d#readContract>
+
+#[cfg(feature = "http")]
+use serde::Deserialize;
+use tiny_keccak::{Hasher, Keccak};
+
+use super::address::Address;
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum ContractError {
+    #[error("Request to {url} failed: {reason}")]
+    Request { url: String, reason: String },
+    #[error("Response from {url} is not valid JSON-RPC {source}")]
+    InvalidResponse {
+        source: serde_json::Error,
+        url: String,
+    },
+    #[error("JSON-RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Return data is not hex {0}")]
+    InvalidHex(#[from] hex::FromHexError),
+    #[error("Return data is not an ABI encoded string: {0}")]
+    InvalidAbi(String),
+    #[error("No manifest is published for chain {chain} by {publisher}")]
+    NoManifest { chain: String, publisher: Address },
+}
+
+/// Unchained Index contract on Ethereum mainnet.
+pub const UNCHAINED_INDEX_CONTRACT: Address = Address([
+    0x0c, 0x31, 0x6b, 0x70, 0x42, 0xb4, 0x19, 0xd0, 0x7d, 0x34, 0x3f, 0x2f, 0x4f, 0x5b, 0xd5, 0x4f,
+    0xf7, 0x31, 0x18, 0x3d,
+]);
+
+/// Address that publishes the trueblocks-core manifests.
+pub const DEFAULT_PUBLISHER: Address = Address([
+    0xf5, 0x03, 0x01, 0x7d, 0x7b, 0xaf, 0x7f, 0xbc, 0x0f, 0xff, 0x74, 0x92, 0xb7, 0x51, 0x02, 0x5c,
+    0x6a, 0x78, 0x17, 0x9b,
+]);
+
+/// Solidity signature of the mapping getter.
+pub const MANIFEST_HASH_MAP_SIGNATURE: &str = "manifestHashMap(address,string)";
+
+/// Bytes in an ABI word.
+const WORD: usize = 32;
+
+/// First four bytes of the keccak256 hash of a function signature.
+pub fn selector(signature: &str) -> [u8; 4] {
+    let mut hash = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(signature.as_bytes());
+    hasher.finalize(&mut hash);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Call data for `manifestHashMap(publisher, chain)`.
+pub fn manifest_hash_map_call(publisher: &Address, chain: &str) -> Vec<u8> {
+    let mut data = selector(MANIFEST_HASH_MAP_SIGNATURE).to_vec();
+    // Head: address, then offset of the string.
+    data.extend_from_slice(&[0u8; WORD - 20]);
+    data.extend_from_slice(publisher.as_bytes());
+    data.extend_from_slice(&word(2 * WORD));
+    // Tail: string length, then padded bytes.
+    data.extend_from_slice(&word(chain.len()));
+    data.extend_from_slice(chain.as_bytes());
+    let padding = (WORD - chain.len() % WORD) % WORD;
+    data.resize(data.len() + padding, 0);
+    data
+}
+
+/// Decodes return data holding a single ABI encoded string.
+pub fn decode_string(data: &[u8]) -> Result<String, ContractError> {
+    let offset = read_word(data, 0)?;
+    let length = read_word(data, offset)?;
+    let start = offset + WORD;
+    let bytes = start
+        .checked_add(length)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| {
+            ContractError::InvalidAbi(format!(
+                "String of {length} bytes at {start} exceeds {} bytes",
+                data.len()
+            ))
+        })?;
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| ContractError::InvalidAbi("String is not UTF-8".to_string()))
+}
+
+/// Big-endian word holding a small integer.
+fn word(value: usize) -> [u8; WORD] {
+    let mut word = [0u8; WORD];
+    word[WORD - 8..].copy_from_slice(&(value as u64).to_be_bytes());
+    word
+}
+
+/// Reads a word at a byte position as an integer that fits in usize.
+fn read_word(data: &[u8], position: usize) -> Result<usize, ContractError> {
+    let bytes = position
+        .checked_add(WORD)
+        .and_then(|end| data.get(position..end))
+        .ok_or_else(|| {
+            ContractError::InvalidAbi(format!("Word at {position} exceeds {} bytes", data.len()))
+        })?;
+    let (high, low) = bytes.split_at(WORD - 8);
+    if high.iter().any(|b| *b != 0) {
+        return Err(ContractError::InvalidAbi(format!(
+            "Word at {position} is too large"
+        )));
+    }
+    let mut value = [0u8; 8];
+    value.copy_from_slice(low);
+    usize::try_from(u64::from_be_bytes(value))
+        .map_err(|_| ContractError::InvalidAbi(format!("Word at {position} is too large")))
+}
+
+/// JSON-RPC response body.
+#[cfg(feature = "http")]
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<String>,
+    error: Option<RpcErrorObject>,
+}
+
+#[cfg(feature = "http")]
+#[derive(Debug, Deserialize)]
+struct RpcErrorObject {
+    code: i64,
+    message: String,
+}
+
+/// Reads the latest manifest CID for a chain from the contract.
+///
+/// `rpc_url` is a JSON-RPC endpoint for Ethereum mainnet (where the contract
+/// is deployed), whichever chain the manifest is for.
+#[cfg(feature = "http")]
+pub fn manifest_cid(
+    rpc_url: &str,
+    publisher: &Address,
+    chain: &str,
+) -> Result<String, ContractError> {
+    let call = serde_json::json!({
+        "to": UNCHAINED_INDEX_CONTRACT,
+        "data": format!("0x{}", hex::encode(manifest_hash_map_call(publisher, chain))),
+    });
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_call",
+        "params": [call, "latest"],
+    });
+    let response = ureq::post(rpc_url)
+        .set("Content-Type", "application/json")
+        .send_string(&request.to_string())
+        .map_err(|e| ContractError::Request {
+            url: rpc_url.to_string(),
+            reason: e.to_string(),
+        })?;
+    let response: RpcResponse = serde_json::from_reader(response.into_reader()).map_err(|e| {
+        ContractError::InvalidResponse {
+            source: e,
+            url: rpc_url.to_string(),
+        }
+    })?;
+    if let Some(error) = response.error {
+        return Err(ContractError::Rpc {
+            code: error.code,
+            message: error.message,
+        });
+    }
+    let result = response.result.unwrap_or_default();
+    let data = hex::decode(result.strip_prefix("0x").unwrap_or(&result))?;
+    // No return data ("0x" or a null result) means nothing is stored.
+    let cid = match data.is_empty() {
+        true => String::new(),
+        false => decode_string(&data)?,
+    };
+    if cid.is_empty() {
+        return Err(ContractError::NoManifest {
+            chain: chain.to_string(),
+            publisher: *publisher,
+        });
+    }
+    Ok(cid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_call_data() {
+        assert_eq!(
+            hex::encode(selector(MANIFEST_HASH_MAP_SIGNATURE)),
+            "7087e4bd"
+        );
+        let expected = concat!(
+            "7087e4bd",
+            "000000000000000000000000f503017d7baf7fbc0fff7492b751025c6a78179b",
+            "0000000000000000000000000000000000000000000000000000000000000040",
+            "0000000000000000000000000000000000000000000000000000000000000007",
+            "6d61696e6e657400000000000000000000000000000000000000000000000000",
+        );
+        assert_eq!(
+            hex::encode(manifest_hash_map_call(&DEFAULT_PUBLISHER, "mainnet")),
+            expected
+        );
+    }
+
+    #[test]
+    fn decodes_strings() {
+        let data = hex::decode(concat!(
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "000000000000000000000000000000000000000000000000000000000000002e",
+            "516d5542533833716a526d586d536745765a4144567632636834373133376a6b",
+            "674e627166565678516570355931000000000000000000000000000000000000",
+        ))
+        .unwrap();
+        assert_eq!(
+            decode_string(&data).unwrap(),
+            "QmUBS83qjRmXmSgEvZADVv2ch47137jkgNbqfVVxQep5Y1"
+        );
+
+        let empty = hex::decode(concat!(
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        ))
+        .unwrap();
+        assert_eq!(decode_string(&empty).unwrap(), "");
+
+        // String cut short, length word cut short, and no data at all.
+        for truncated in [&data[..3 * WORD], &data[..WORD + 4], &[][..]] {
+            assert!(matches!(
+                decode_string(truncated),
+                Err(ContractError::InvalidAbi(_))
+            ));
+        }
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn reads_manifest_cid_from_rpc() {
+        use crate::fixtures::serve_http;
+
+        let cid = "QmUBS83qjRmXmSgEvZADVv2ch47137jkgNbqfVVxQep5Y1";
+        let mut encoded = word(WORD).to_vec();
+        encoded.extend_from_slice(&word(cid.len()));
+        encoded.extend_from_slice(cid.as_bytes());
+        encoded.resize(4 * WORD, 0);
+        let results = [
+            format!(
+                r#"{{"jsonrpc":"2.0","id":1,"result":"0x{}"}}"#,
+                hex::encode(&encoded)
+            ),
+            r#"{"jsonrpc":"2.0","id":1,"result":null}"#.to_string(),
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x"}"#.to_string(),
+            format!(
+                r#"{{"jsonrpc":"2.0","id":1,"result":"0x{}"}}"#,
+                hex::encode(word(WORD)) + &hex::encode(word(0))
+            ),
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"execution reverted"}}"#
+                .to_string(),
+        ];
+        let responses = std::sync::Mutex::new(results.into_iter());
+        let (url, server) = serve_http(5, move |_| {
+            (200, responses.lock().unwrap().next().unwrap().into_bytes())
+        });
+
+        assert_eq!(
+            manifest_cid(&url, &DEFAULT_PUBLISHER, "mainnet").unwrap(),
+            cid
+        );
+        // Null, empty and empty string results.
+        for _ in 0..3 {
+            assert!(matches!(
+                manifest_cid(&url, &DEFAULT_PUBLISHER, "sepolia"),
+                Err(ContractError::NoManifest { chain, publisher })
+                    if chain == "sepolia" && publisher == DEFAULT_PUBLISHER
+            ));
+        }
+        assert!(matches!(
+            manifest_cid(&url, &DEFAULT_PUBLISHER, "mainnet"),
+            Err(ContractError::Rpc { code: -32000, .. })
+        ));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].request_line, "POST / HTTP/1.1");
+        assert_eq!(requests[0].header("content-type"), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["method"], "eth_call");
+        assert_eq!(body["params"][1], "latest");
+        assert_eq!(
+            body["params"][0]["to"],
+            "0x0c316b7042b419d07d343f2f4f5bd54ff731183d"
+        );
+        assert_eq!(
+            body["params"][0]["data"],
+            format!(
+                "0x{}",
+                hex::encode(manifest_hash_map_call(&DEFAULT_PUBLISHER, "mainnet"))
+            )
+        );
+    }
+}
@@ -0,0 +1,460 @@
+//! Downloads the chunks and blooms listed in a manifest from an IPFS gateway.
+//!
+//! Files are written under their range name (e.g., "017190314-017193246.bin")
+//! so the output directory can be read with [crate::files::ChunksDir]. Data is
+//! first written to a ".part" file next to the destination. If a download is
+//! interrupted, the next attempt requests only the missing bytes. A file is
+//! only moved into place once its size and CID match the manifest.
+//!
+//! The source of the data is a [Gateway]. With the `http` feature,
+//! [HttpGateway] fetches from an HTTP gateway such as
+//! "https://ipfs.unchainedindex.io".
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    cid::{cid_v0_of_file, CidError},
+    files::{bloom_filename, chunk_filename, partial_path, FilesError},
+    manifest::{FileKind, Manifest},
+    parse::BlockRange,
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("Gateway request for {cid} failed: {reason}")]
+    Gateway { cid: String, reason: String },
+    #[error("Unable to write to {filename} {source}")]
+    Write {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Download of {cid} was interrupted {source}")]
+    Interrupted { source: std::io::Error, cid: String },
+    #[error("{filename} has {found} bytes, expected {expected}")]
+    SizeMismatch {
+        filename: PathBuf,
+        expected: u64,
+        found: u64,
+    },
+    #[error("{filename} has CID {found}, expected {expected}")]
+    CidMismatch {
+        filename: PathBuf,
+        expected: String,
+        found: String,
+    },
+    #[error("Manifest {cid} is not valid JSON {source}")]
+    InvalidManifest {
+        source: serde_json::Error,
+        cid: String,
+    },
+    #[error("CidError {0}")]
+    CidError(#[from] CidError),
+    #[error("FilesError {0}")]
+    FilesError(#[from] FilesError),
+}
+
+/// Gateway used by trueblocks-core to publish the Unchained Index.
+pub const DEFAULT_GATEWAY: &str = "https://ipfs.unchainedindex.io";
+
+/// File data returned by a gateway.
+pub struct Fetched {
+    /// Position in the file of the first byte of the body.
+    ///
+    /// Either the requested offset, or 0 if the gateway ignored it.
+    pub offset: u64,
+    pub body: Box<dyn Read + Send>,
+}
+
+/// A source of files by CID.
+pub trait Gateway {
+    /// Fetches a file, starting at a byte offset if the source supports it.
+    fn fetch(&self, cid: &str, offset: u64) -> Result<Fetched, DownloadError>;
+}
+
+/// Fetches files from an HTTP gateway at "{url}/ipfs/{cid}".
+///
+/// Partial downloads are resumed with a Range request.
+#[cfg(feature = "http")]
+pub struct HttpGateway {
+    pub url: String,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "http")]
+impl HttpGateway {
+    /// Gateway at a base URL, e.g. "https://ipfs.unchainedindex.io".
+    pub fn new(url: &str) -> Self {
+        HttpGateway {
+            url: url.trim_end_matches('/').to_string(),
+            agent: ureq::Agent::new(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Gateway for HttpGateway {
+    fn fetch(&self, cid: &str, offset: u64) -> Result<Fetched, DownloadError> {
+        let mut request = self.agent.get(&format!("{}/ipfs/{}", self.url, cid));
+        if offset > 0 {
+            request = request.set("Range", &format!("bytes={offset}-"));
+        }
+        let response = request.call().map_err(|e| DownloadError::Gateway {
+            cid: cid.to_string(),
+            reason: e.to_string(),
+        })?;
+        let offset = match response.status() {
+            206 => offset,
+            _ => 0,
+        };
+        Ok(Fetched {
+            offset,
+            body: Box::new(response.into_reader()),
+        })
+    }
+}
+
+/// How a file came to be in place.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DownloadStatus {
+    /// Already present with the expected CID.
+    Existing,
+    /// Fetched in full.
+    Downloaded,
+    /// Completed from a partial download.
+    Resumed,
+}
+
+/// Details of a file obtained for a manifest entry.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadedFile {
+    pub path: PathBuf,
+    pub range: BlockRange,
+    pub kind: FileKind,
+    pub cid: String,
+    pub size: u64,
+    pub status: DownloadStatus,
+}
+
+/// Fetches and parses a manifest by CID.
+pub fn fetch_manifest(gateway: &impl Gateway, cid: &str) -> Result<Manifest, DownloadError> {
+    let fetched = gateway.fetch(cid, 0)?;
+    serde_json::from_reader(fetched.body).map_err(|e| DownloadError::InvalidManifest {
+        source: e,
+        cid: cid.to_string(),
+    })
+}
+
+/// Obtains the manifest chunks (and optionally blooms) that intersect a block range.
+///
+/// Files already present with the expected CID are not fetched again. The
+/// directories are created if needed.
+pub fn download_chunks(
+    gateway: &impl Gateway,
+    manifest: &Manifest,
+    range: &BlockRange,
+    chunks_dir: &Path,
+    blooms_dir: Option<&Path>,
+) -> Result<Vec<DownloadedFile>, DownloadError> {
+    for dir in [Some(chunks_dir), blooms_dir].into_iter().flatten() {
+        fs::create_dir_all(dir).map_err(|e| DownloadError::Write {
+            source: e,
+            filename: dir.to_path_buf(),
+        })?;
+    }
+    let mut downloaded = vec![];
+    for chunk in &manifest.chunks {
+        let chunk_range = chunk.block_range()?;
+        if !chunk_range.intersection_exists(range) {
+            continue;
+        }
+        let index_name = chunk_filename(&chunk_range);
+        let mut targets = vec![(
+            FileKind::Index,
+            &chunk.index_hash,
+            chunk.index_size,
+            chunks_dir.join(&index_name),
+        )];
+        if let Some(dir) = blooms_dir {
+            targets.push((
+                FileKind::Bloom,
+                &chunk.bloom_hash,
+                chunk.bloom_size,
+                dir.join(bloom_filename(&chunk_range)),
+            ));
+        }
+        for (kind, cid, size, path) in targets {
+            let status = download_file(gateway, cid, size, &path)?;
+            downloaded.push(DownloadedFile {
+                path,
+                range: chunk_range,
+                kind,
+                cid: cid.to_owned(),
+                size,
+                status,
+            });
+        }
+    }
+    Ok(downloaded)
+}
+
+/// Obtains a single file, resuming from a ".part" file if one exists.
+fn download_file(
+    gateway: &impl Gateway,
+    cid: &str,
+    size: u64,
+    path: &Path,
+) -> Result<DownloadStatus, DownloadError> {
+    if path.exists() && verify(path, cid, size).is_ok() {
+        return Ok(DownloadStatus::Existing);
+    }
+    let part = partial_path(path);
+    let mut present = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+    if present > size {
+        present = 0;
+    }
+    let mut status = DownloadStatus::Downloaded;
+    if present < size || size == 0 {
+        let mut fetched = gateway.fetch(cid, present)?;
+        let mut file = match fetched.offset {
+            0 => File::create(&part),
+            _ => OpenOptions::new().append(true).open(&part),
+        }
+        .map_err(|e| DownloadError::Write {
+            source: e,
+            filename: part.to_path_buf(),
+        })?;
+        if fetched.offset > 0 {
+            status = DownloadStatus::Resumed;
+        }
+        io::copy(&mut fetched.body, &mut file).map_err(|e| DownloadError::Interrupted {
+            source: e,
+            cid: cid.to_string(),
+        })?;
+    } else {
+        status = DownloadStatus::Resumed;
+    }
+    if let Err(e) = verify(&part, cid, size) {
+        if let DownloadError::CidMismatch { .. } = e {
+            // Corrupt data cannot be resumed from.
+            let _ = fs::remove_file(&part);
+        }
+        return Err(e);
+    }
+    fs::rename(&part, path).map_err(|e| DownloadError::Write {
+        source: e,
+        filename: path.to_path_buf(),
+    })?;
+    Ok(status)
+}
+
+/// Checks the size and CID of a file against the manifest.
+///
+/// The CID is only computed if the size matches.
+fn verify(path: &Path, cid: &str, size: u64) -> Result<(), DownloadError> {
+    let found = fs::metadata(path)
+        .map_err(|e| CidError::FileOpener {
+            source: e,
+            filename: path.to_path_buf(),
+        })?
+        .len();
+    if found != size {
+        return Err(DownloadError::SizeMismatch {
+            filename: path.to_path_buf(),
+            expected: size,
+            found,
+        });
+    }
+    let computed = cid_v0_of_file(path)?;
+    if computed.cid != cid {
+        return Err(DownloadError::CidMismatch {
+            filename: path.to_path_buf(),
+            expected: cid.to_string(),
+            found: computed.cid,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cid::cid_v0_of_bytes;
+    use std::{cell::RefCell, io::Cursor};
+
+    /// Serves one file from memory and records the requested offsets.
+    struct MockGateway {
+        data: Vec<u8>,
+        supports_range: bool,
+        requests: RefCell<Vec<u64>>,
+    }
+
+    impl MockGateway {
+        fn new(data: Vec<u8>, supports_range: bool) -> Self {
+            MockGateway {
+                data,
+                supports_range,
+                requests: RefCell::new(vec![]),
+            }
+        }
+    }
+
+    impl Gateway for MockGateway {
+        fn fetch(&self, _cid: &str, offset: u64) -> Result<Fetched, DownloadError> {
+            self.requests.borrow_mut().push(offset);
+            let offset = if self.supports_range { offset } else { 0 };
+            let body = self.data[offset as usize..].to_vec();
+            Ok(Fetched {
+                offset,
+                body: Box::new(Cursor::new(body)),
+            })
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "unchained-download-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_data() -> Vec<u8> {
+        (0..5000u32).map(|n| (n % 251) as u8).collect()
+    }
+
+    #[test]
+    fn resumes_partial_download() {
+        let dir = test_dir("resume");
+        let path = dir.join("017190314-017193246.bin");
+        let data = file_data();
+        let cid = cid_v0_of_bytes(&data).cid;
+        fs::write(partial_path(&path), &data[..1234]).unwrap();
+
+        let gateway = MockGateway::new(data.clone(), true);
+        let status = download_file(&gateway, &cid, data.len() as u64, &path).unwrap();
+        assert_eq!(status, DownloadStatus::Resumed);
+        assert_eq!(*gateway.requests.borrow(), vec![1234]);
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert!(!partial_path(&path).exists());
+
+        // Already in place, so not fetched again.
+        let status = download_file(&gateway, &cid, data.len() as u64, &path).unwrap();
+        assert_eq!(status, DownloadStatus::Existing);
+        assert_eq!(gateway.requests.borrow().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restarts_if_range_is_ignored() {
+        let dir = test_dir("restart");
+        let path = dir.join("017190314-017193246.bin");
+        let data = file_data();
+        let cid = cid_v0_of_bytes(&data).cid;
+        fs::write(partial_path(&path), &data[..1234]).unwrap();
+
+        let gateway = MockGateway::new(data.clone(), false);
+        let status = download_file(&gateway, &cid, data.len() as u64, &path).unwrap();
+        assert_eq!(status, DownloadStatus::Downloaded);
+        assert_eq!(fs::read(&path).unwrap(), data);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_corrupted_body() {
+        let dir = test_dir("corrupt");
+        let path = dir.join("017190314-017193246.bin");
+        let data = file_data();
+        let cid = cid_v0_of_bytes(&data).cid;
+        fs::write(partial_path(&path), &data[..1234]).unwrap();
+
+        let mut corrupted = data.clone();
+        corrupted[4000] ^= 0xff;
+        let gateway = MockGateway::new(corrupted, true);
+        let result = download_file(&gateway, &cid, data.len() as u64, &path);
+        assert!(matches!(result, Err(DownloadError::CidMismatch { .. })));
+        assert!(!path.exists());
+        // The next attempt starts from the beginning.
+        assert!(!partial_path(&path).exists());
+
+        let short = MockGateway::new(data[..4000].to_vec(), true);
+        let result = download_file(&short, &cid, data.len() as u64, &path);
+        assert!(matches!(
+            result,
+            Err(DownloadError::SizeMismatch { found: 4000, .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn http_gateway_range_requests() {
+        use crate::fixtures::serve_http;
+
+        let data = file_data();
+        let served = data.clone();
+        // "ranged" honours the Range header, "full" ignores it.
+        let (url, server) = serve_http(4, move |request| {
+            let path = request.request_line.split(' ').nth(1).unwrap();
+            let range = request
+                .header("range")
+                .map(|r| r.trim_start_matches("bytes=").trim_end_matches('-'));
+            match (path, range) {
+                ("/ipfs/ranged", Some(start)) => {
+                    (206, served[start.parse::<usize>().unwrap()..].to_vec())
+                }
+                ("/ipfs/ranged" | "/ipfs/full", _) => (200, served.clone()),
+                _ => (404, b"not found".to_vec()),
+            }
+        });
+        let gateway = HttpGateway::new(&format!("{url}/"));
+        let read = |fetched: Fetched| {
+            let mut body = vec![];
+            let mut reader = fetched.body;
+            reader.read_to_end(&mut body).unwrap();
+            (fetched.offset, body)
+        };
+
+        assert_eq!(read(gateway.fetch("ranged", 0).unwrap()), (0, data.clone()));
+        assert_eq!(
+            read(gateway.fetch("ranged", 1234).unwrap()),
+            (1234, data[1234..].to_vec())
+        );
+        // 200 with the whole file: the offset is reset.
+        assert_eq!(
+            read(gateway.fetch("full", 1234).unwrap()),
+            (0, data.clone())
+        );
+        assert!(matches!(
+            gateway.fetch("missing", 0),
+            Err(DownloadError::Gateway { cid, .. }) if cid == "missing"
+        ));
+
+        let requests = server.join().unwrap();
+        let lines: Vec<&str> = requests.iter().map(|r| r.request_line.as_str()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "GET /ipfs/ranged HTTP/1.1",
+                "GET /ipfs/ranged HTTP/1.1",
+                "GET /ipfs/full HTTP/1.1",
+                "GET /ipfs/missing HTTP/1.1",
+            ]
+        );
+        let ranges: Vec<Option<&str>> = requests.iter().map(|r| r.header("range")).collect();
+        assert_eq!(
+            ranges,
+            vec![None, Some("bytes=1234-"), Some("bytes=1234-"), None]
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Exports appearances as Apache Arrow record batches and Parquet files.
+//!
+//! Each row is one appearance with columns:
+//! - address: 0x-prefixed lowercase hex
+//! - block: block number
+//! - tx_index: raw transaction index (may be a sentinel value)
+//! - location_kind: see [crate::structure::LocationKind::as_str], using the
+//!   sentinels of the chain (see [crate::chain::ChainConfig::location_kind])
+//! - chunk_range: range of the source chunk, e.g. "017190314-017193246"
+//!
+//! Requires the `parquet` feature.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt32Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use parquet::{
+    arrow::ArrowWriter, basic::Compression, errors::ParquetError,
+    file::properties::WriterProperties,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    chain::ChainConfig,
+    files::ChunkFile,
+    parse::{BlockRange, ParseError, UnchainedFile},
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("ArrowError {0}")]
+    ArrowError(#[from] ArrowError),
+    #[error("File {filename} could not be created {source}")]
+    FileCreator {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("ParquetError {0}")]
+    ParquetError(#[from] ParquetError),
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+}
+
+/// Details of an export that has been written.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportSummary {
+    pub path: PathBuf,
+    pub chunks: usize,
+    pub rows: usize,
+}
+
+/// Arrow schema of exported appearances.
+pub fn appearance_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("address", DataType::Utf8, false),
+        Field::new("block", DataType::UInt32, false),
+        Field::new("tx_index", DataType::UInt32, false),
+        Field::new("location_kind", DataType::Utf8, false),
+        Field::new("chunk_range", DataType::Utf8, false),
+    ]))
+}
+
+/// Reads the appearances of a chunk in the desired range into a record batch.
+pub fn chunk_record_batch(
+    chunk: &ChunkFile,
+    desired: &BlockRange,
+    chain: &ChainConfig,
+) -> Result<RecordBatch, ExportError> {
+    let mut file = UnchainedFile::from_file(chunk.path.to_owned(), *desired)?;
+    let data = file.read_all()?;
+    let chunk_range = format!("{:09}-{:09}", chunk.range.old, chunk.range.new);
+
+    let rows: usize = data.iter().map(|d| d.appearances.len()).sum();
+    let mut addresses: Vec<String> = Vec::with_capacity(rows);
+    let mut blocks: Vec<u32> = Vec::with_capacity(rows);
+    let mut indices: Vec<u32> = Vec::with_capacity(rows);
+    let mut kinds: Vec<&'static str> = Vec::with_capacity(rows);
+    for entry in &data {
+        let address = entry.address.to_string();
+        for appearance in &entry.appearances {
+            addresses.push(address.clone());
+            blocks.push(appearance.block);
+            indices.push(appearance.index);
+            kinds.push(chain.location_kind(appearance.index).as_str());
+        }
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(addresses)),
+        Arc::new(UInt32Array::from(blocks)),
+        Arc::new(UInt32Array::from(indices)),
+        Arc::new(StringArray::from(kinds)),
+        Arc::new(StringArray::from(vec![chunk_range; rows])),
+    ];
+    Ok(RecordBatch::try_new(appearance_schema(), columns)?)
+}
+
+/// Writes the appearances of the chunks in the desired range to a Parquet file.
+///
+/// Each chunk is read and written as a separate record batch, so memory use is
+/// bounded by the largest chunk.
+pub fn write_parquet(
+    chunks: &[&ChunkFile],
+    desired: &BlockRange,
+    chain: &ChainConfig,
+    path: &Path,
+) -> Result<ExportSummary, ExportError> {
+    let file = File::create(path).map_err(|e| ExportError::FileCreator {
+        source: e,
+        filename: path.to_path_buf(),
+    })?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(file, appearance_schema(), Some(properties))?;
+    let mut rows = 0;
+    for chunk in chunks {
+        let batch = chunk_record_batch(chunk, desired, chain)?;
+        rows += batch.num_rows();
+        writer.write(&batch)?;
+        // One row group per chunk.
+        writer.flush()?;
+    }
+    writer.close()?;
+    Ok(ExportSummary {
+        path: path.to_path_buf(),
+        chunks: chunks.len(),
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{self, ADDRESS_A, ADDRESS_B, ADDRESS_C},
+        structure::AddressData,
+    };
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    /// Values of a string column across all batches.
+    fn strings(batches: &[RecordBatch], column: &str) -> Vec<String> {
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let array = batch
+                    .column_by_name(column)
+                    .unwrap()
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .unwrap();
+                (0..array.len())
+                    .map(|i| array.value(i).to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Values of a number column across all batches.
+    fn numbers(batches: &[RecordBatch], column: &str) -> Vec<u32> {
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let array = batch
+                    .column_by_name(column)
+                    .unwrap()
+                    .as_any()
+                    .downcast_ref::<UInt32Array>()
+                    .unwrap();
+                array.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let dir = fixtures::test_dir("export");
+        let sample = fixtures::write_sample(&dir);
+        let later = fixtures::write_fixture(
+            &dir,
+            BlockRange::new(17_190_876, 17_190_877).unwrap(),
+            vec![AddressData {
+                address: fixtures::address(ADDRESS_B),
+                appearances: vec![fixtures::tx(17_190_877, 99996)],
+            }],
+        );
+        let path = dir.join("appearances.parquet");
+        let desired = BlockRange::new(0, u32::MAX).unwrap();
+        let summary =
+            write_parquet(&[&sample, &later], &desired, &ChainConfig::gnosis(), &path).unwrap();
+        assert_eq!(summary.chunks, 2);
+        assert_eq!(summary.rows, 6);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        assert_eq!(reader.schema(), &appearance_schema());
+        let batches: Vec<RecordBatch> = reader.build().unwrap().map(|b| b.unwrap()).collect();
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 6);
+        assert_eq!(
+            strings(&batches, "address"),
+            vec![ADDRESS_C, ADDRESS_A, ADDRESS_A, ADDRESS_B, ADDRESS_B, ADDRESS_B]
+        );
+        assert_eq!(
+            numbers(&batches, "block"),
+            vec![17190873, 17190873, 17190875, 17190874, 17190874, 17190877]
+        );
+        assert_eq!(
+            numbers(&batches, "tx_index"),
+            vec![2, 5, 99999, 0, 99997, 99996]
+        );
+        assert_eq!(
+            strings(&batches, "location_kind"),
+            vec![
+                "transaction",
+                "transaction",
+                "miner",
+                "transaction",
+                "null_recipient",
+                "external"
+            ]
+        );
+        let mut ranges = vec!["017190873-017190875"; 5];
+        ranges.push("017190876-017190877");
+        assert_eq!(strings(&batches, "chunk_range"), ranges);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
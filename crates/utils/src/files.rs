@@ -1,14 +1,17 @@
 use regex::Regex;
 use std::{
+    collections::BTreeMap,
     fs,
     num::ParseIntError,
     path::{Path, PathBuf},
 };
 
 use super::{
+    address::Address,
     constants::{AD_ENTRY, AP_ENTRY, HEAD},
-    parse::{BlockRange, UnchainedFile},
-    structure::{Body, Header, Section, TransactionId},
+    parse::{BlockRange, ParseError, UnchainedFile},
+    query::{Order, Page, Pagination},
+    structure::{AddressData, Body, Header, Section, TransactionId},
 };
 
 use thiserror::{self, Error};
@@ -51,6 +54,8 @@ pub struct ChunksDir {
 impl ChunksDir {
     /// Obtains information about all the available chunk files.
     ///
+    /// Files may be raw (".bin") or compressed (".bin.gz", ".bin.zst"). Other
+    /// files, such as blooms and partial downloads (".part"), are ignored.
     /// # Example
     /// If the chunk files are in "xyz/trueblocks/unchained/mainnet/finalized",
     /// then this is the path passed in.
@@ -62,12 +67,17 @@ impl ChunksDir {
         let mut paths: Vec<ChunkFile> = vec![];
         for file in files {
             let path = file.map_err(FilesError::DirEntry)?.path();
+            if ChunkEncoding::of_chunk(&path).is_none() {
+                continue;
+            }
             let range = get_range(&path)?;
             let chunk = ChunkFile { path, range };
             paths.push(chunk);
         }
 
-        paths.sort_by_key(|k| k.range.old);
+        paths.sort_by_key(|k| (k.range.old, k.range.new, k.encoding() != ChunkEncoding::Raw));
+        // Where a chunk is present both raw and compressed, use the raw file.
+        paths.dedup_by_key(|k| k.range);
         Ok(ChunksDir {
             dir: dir_path.to_path_buf(),
             paths,
@@ -88,6 +98,163 @@ impl ChunksDir {
         }
         Some(relevant)
     }
+    /// Obtains all appearances of a single address in the desired block range.
+    ///
+    /// Only chunks that intersect the range are opened. Appearances are returned
+    /// in chunk order (ascending block).
+    pub fn appearances_of(
+        &self,
+        address: &Address,
+        desired_range: &BlockRange,
+    ) -> Result<Vec<TransactionId>, ParseError> {
+        let mut appearances: Vec<TransactionId> = vec![];
+        let Some(relevant) = self.for_range(desired_range) else {
+            return Ok(appearances);
+        };
+        for chunk in relevant {
+            let mut file = UnchainedFile::from_file(chunk.path.to_owned(), *desired_range)?;
+            if let Some(found) = file.appearances_of(address)? {
+                appearances.extend(found);
+            }
+        }
+        Ok(appearances)
+    }
+    /// Obtains the appearances of many addresses in the desired block range.
+    ///
+    /// Each relevant chunk is read once (see [UnchainedFile::lookup_many]).
+    /// Results are sorted by address, with appearances in ascending order.
+    pub fn lookup_many(
+        &self,
+        addresses: &[Address],
+        desired_range: &BlockRange,
+    ) -> Result<Vec<AddressData>, ParseError> {
+        let mut merged: BTreeMap<Address, Vec<TransactionId>> = BTreeMap::new();
+        let Some(relevant) = self.for_range(desired_range) else {
+            return Ok(vec![]);
+        };
+        for chunk in relevant {
+            let mut file = UnchainedFile::from_file(chunk.path.to_owned(), *desired_range)?;
+            for data in file.lookup_many(addresses)? {
+                merged
+                    .entry(data.address)
+                    .or_default()
+                    .extend(data.appearances);
+            }
+        }
+        Ok(merged
+            .into_iter()
+            .map(|(address, appearances)| AddressData {
+                address,
+                appearances,
+            })
+            .collect())
+    }
+    /// Obtains one page of appearances of a single address in the desired block range.
+    ///
+    /// Chunks are visited in the requested order, skipping those before the cursor,
+    /// and reading stops once the page is full.
+    pub fn appearances_page(
+        &self,
+        address: &Address,
+        desired_range: &BlockRange,
+        pagination: &Pagination,
+    ) -> Result<Page, ParseError> {
+        let mut appearances: Vec<TransactionId> = vec![];
+        let Some(mut relevant) = self.for_range(desired_range) else {
+            return Ok(Page::default());
+        };
+        if pagination.order == Order::Descending {
+            relevant.reverse();
+        }
+        for chunk in relevant {
+            let furthest = match pagination.order {
+                Order::Ascending => chunk.range.new,
+                Order::Descending => chunk.range.old,
+            };
+            if !pagination.block_after_cursor(furthest) {
+                // Whole chunk precedes the cursor.
+                continue;
+            }
+            let mut file = UnchainedFile::from_file(chunk.path.to_owned(), *desired_range)?;
+            if let Some(found) = file.appearances_of(address)? {
+                appearances.extend(
+                    found
+                        .into_iter()
+                        .filter(|x| pagination.is_shown(x) && pagination.is_after_cursor(x)),
+                );
+            }
+            // One more than the limit shows whether a further page exists.
+            if let Some(limit) = pagination.limit {
+                if appearances.len() > limit {
+                    break;
+                }
+            }
+        }
+        Ok(pagination.paginate(appearances))
+    }
+}
+
+/// Lists the bloom filter files (".bloom") in a directory, ordered by range.
+///
+/// Other files are ignored.
+pub fn bloom_files(dir_path: &Path) -> Result<Vec<ChunkFile>, FilesError> {
+    let files = fs::read_dir(dir_path).map_err(|e| FilesError::FileOpener {
+        filename: dir_path.to_path_buf(),
+        source: e,
+    })?;
+    let mut blooms: Vec<ChunkFile> = vec![];
+    for file in files {
+        let path = file.map_err(FilesError::DirEntry)?.path();
+        if path.extension().is_some_and(|e| e == "bloom") {
+            blooms.push(ChunkFile::new(&path)?);
+        }
+    }
+    blooms.sort_by_key(|k| (k.range.old, k.range.new));
+    Ok(blooms)
+}
+
+/// Compression of a chunk file, as indicated by its extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkEncoding {
+    /// ".bin"
+    Raw,
+    /// ".bin.gz"
+    Gzip,
+    /// ".bin.zst"
+    Zstd,
+}
+
+impl ChunkEncoding {
+    /// Encoding of a chunk file from its name. Unrecognised names are treated as raw.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => ChunkEncoding::Gzip,
+            Some("zst") => ChunkEncoding::Zstd,
+            _ => ChunkEncoding::Raw,
+        }
+    }
+    /// Encoding of a file named as a chunk (".bin", ".bin.gz" or ".bin.zst"),
+    /// None for any other file.
+    pub fn of_chunk(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name.ends_with(".bin") {
+            Some(ChunkEncoding::Raw)
+        } else if name.ends_with(".bin.gz") {
+            Some(ChunkEncoding::Gzip)
+        } else if name.ends_with(".bin.zst") {
+            Some(ChunkEncoding::Zstd)
+        } else {
+            None
+        }
+    }
+    /// Crate feature required to read the encoding.
+    pub fn feature(&self) -> &'static str {
+        match self {
+            ChunkEncoding::Raw => "default",
+            ChunkEncoding::Gzip => "gzip",
+            ChunkEncoding::Zstd => "zstd",
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -96,6 +263,20 @@ pub struct ChunkFile {
     pub range: BlockRange,
 }
 
+impl ChunkFile {
+    /// Details for a single chunk file, with the range taken from the filename.
+    pub fn new(path: &Path) -> Result<Self, FilesError> {
+        Ok(ChunkFile {
+            path: path.to_path_buf(),
+            range: get_range(path)?,
+        })
+    }
+    /// Compression of the file.
+    pub fn encoding(&self) -> ChunkEncoding {
+        ChunkEncoding::from_path(&self.path)
+    }
+}
+
 /// Determines the byte indices for a given chunk file.
 pub fn file_structure(h: &Header) -> Body {
     let app_start = HEAD + h.n_addresses as usize * AD_ENTRY;
@@ -149,6 +330,33 @@ pub fn get_range(path: &Path) -> Result<BlockRange, FilesError> {
     })
 }
 
+/// Name of the chunk file that covers a block range.
+///
+/// E.g., "017190314-017193246.bin"
+pub fn chunk_filename(range: &BlockRange) -> String {
+    format!("{:09}-{:09}.bin", range.old, range.new)
+}
+
+/// Name of the bloom filter file for a block range.
+///
+/// E.g., "017190314-017193246.bloom"
+pub fn bloom_filename(range: &BlockRange) -> String {
+    format!("{:09}-{:09}.bloom", range.old, range.new)
+}
+
+/// Extension added to files while they are being downloaded or written.
+pub const PARTIAL_EXTENSION: &str = "part";
+
+/// Path a file is written to before it is complete.
+///
+/// E.g., "017190314-017193246.bin.part"
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(PARTIAL_EXTENSION);
+    PathBuf::from(name)
+}
+
 /// Checks that given appearance is within chunk file bounds.
 pub fn no_unexpected_appearances(
     appearance: &TransactionId,
@@ -165,3 +373,107 @@ pub fn no_unexpected_appearances(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn lookup_many_across_chunks() {
+        let dir = fixtures::test_dir("files-lookup-many");
+        fixtures::write_sample(&dir);
+        fixtures::write_fixture(
+            &dir,
+            BlockRange::new(17_190_876, 17_190_880).unwrap(),
+            vec![AddressData {
+                address: fixtures::address(fixtures::ADDRESS_A),
+                appearances: vec![fixtures::tx(17_190_876, 1), fixtures::tx(17_190_880, 0)],
+            }],
+        );
+        let chunks = ChunksDir::new(&dir).unwrap();
+        let missing = fixtures::address("0x00000000000000000000000000000000000000ff");
+        let [a, b, c] = [
+            fixtures::ADDRESS_A,
+            fixtures::ADDRESS_B,
+            fixtures::ADDRESS_C,
+        ]
+        .map(fixtures::address);
+        let requested = [b, missing, a, c, a];
+
+        for desired in [
+            BlockRange::new(0, u32::MAX).unwrap(),
+            BlockRange::new(17_190_874, 17_190_876).unwrap(),
+            BlockRange::new(17_190_900, 17_191_000).unwrap(),
+        ] {
+            let expected: Vec<AddressData> = [c, a, b, missing]
+                .into_iter()
+                .map(|address| AddressData {
+                    address,
+                    appearances: chunks.appearances_of(&address, &desired).unwrap(),
+                })
+                .filter(|d| !d.appearances.is_empty())
+                .collect();
+            assert_eq!(chunks.lookup_many(&requested, &desired).unwrap(), expected);
+        }
+        let all = chunks
+            .lookup_many(&requested, &BlockRange::new(0, u32::MAX).unwrap())
+            .unwrap();
+        assert_eq!(
+            all[1].appearances,
+            vec![
+                fixtures::tx(17_190_873, 5),
+                fixtures::tx(17_190_875, 99999),
+                fixtures::tx(17_190_876, 1),
+                fixtures::tx(17_190_880, 0),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_dir_ignores_other_files() {
+        let dir = std::env::temp_dir().join(format!("unchained-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let names = [
+            "017190314-017193246.bloom",
+            "017190314-017193246.bin",
+            "017193247-017195000.bin.zst",
+            "017195001-017196000.bin.part",
+            "017196001-017197000.bin.gz.part",
+            "017197001-017198000.txt",
+            "README.md",
+        ];
+        for name in names {
+            fs::write(dir.join(name), []).unwrap();
+        }
+        let chunks = ChunksDir::new(&dir).unwrap();
+        let found: Vec<PathBuf> = chunks
+            .paths
+            .iter()
+            .map(|c| c.path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                PathBuf::from("017190314-017193246.bin"),
+                PathBuf::from("017193247-017195000.bin.zst"),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn encoding_of_chunk_names() {
+        let of = |name: &str| ChunkEncoding::of_chunk(Path::new(name));
+        assert_eq!(of("a/017190314-017193246.bin"), Some(ChunkEncoding::Raw));
+        assert_eq!(of("017190314-017193246.bin.gz"), Some(ChunkEncoding::Gzip));
+        assert_eq!(of("017190314-017193246.bin.zst"), Some(ChunkEncoding::Zstd));
+        assert_eq!(of("017190314-017193246.bloom"), None);
+        assert_eq!(of("017190314-017193246.bin.part"), None);
+        assert_eq!(
+            partial_path(Path::new("a/017190314-017193246.bin")),
+            PathBuf::from("a/017190314-017193246.bin.part")
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Small chunks written on the fly for tests.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    address::Address,
+    constants::VER,
+    files::ChunkFile,
+    parse::BlockRange,
+    structure::{AddressData, TransactionId},
+    write::write_chunk,
+};
+
+pub const ADDRESS_A: &str = "0x30a4639850b3ddeaaca4f06280aa751682f11382";
+pub const ADDRESS_B: &str = "0xd2090025857b9c7b24387741f120538e928a3a59";
+pub const ADDRESS_C: &str = "0x0101010101010101010101010101010101010101";
+
+/// Version hash written to fixture chunks.
+pub const VERSION: [u8; VER] = [0xab; VER];
+
+/// An empty directory for a test, unique to the name and process.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("unchained-fixture-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn address(hex: &str) -> Address {
+    Address::from_hex(hex).unwrap()
+}
+
+pub fn tx(block: u32, index: u32) -> TransactionId {
+    TransactionId { block, index }
+}
+
+/// Range of [sample_data].
+pub fn sample_range() -> BlockRange {
+    BlockRange::new(17_190_873, 17_190_875).unwrap()
+}
+
+/// Three addresses over three blocks, including miner (99999) and null
+/// recipient (99997) sentinels. Sorted, as read back from a chunk.
+pub fn sample_data() -> Vec<AddressData> {
+    vec![
+        AddressData {
+            address: address(ADDRESS_C),
+            appearances: vec![tx(17_190_873, 2)],
+        },
+        AddressData {
+            address: address(ADDRESS_A),
+            appearances: vec![tx(17_190_873, 5), tx(17_190_875, 99999)],
+        },
+        AddressData {
+            address: address(ADDRESS_B),
+            appearances: vec![tx(17_190_874, 0), tx(17_190_874, 99997)],
+        },
+    ]
+}
+
+/// Writes a chunk of the data into the directory.
+pub fn write_fixture(dir: &Path, range: BlockRange, data: Vec<AddressData>) -> ChunkFile {
+    let written = write_chunk(dir, range, VERSION, data).unwrap();
+    ChunkFile::new(&written.path).unwrap()
+}
+
+/// Writes the [sample_data] chunk into the directory.
+pub fn write_sample(dir: &Path) -> ChunkFile {
+    write_fixture(dir, sample_range(), sample_data())
+}
+
+/// A request received by [serve_http].
+#[cfg(feature = "http")]
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    /// E.g., "GET /ipfs/Qm.. HTTP/1.1"
+    pub request_line: String,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+#[cfg(feature = "http")]
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serves HTTP on 127.0.0.1 for a number of requests, one per connection.
+///
+/// The handler returns the status and body for each request. Returns the base
+/// URL and a handle that yields the requests once they have all been served.
+#[cfg(feature = "http")]
+pub fn serve_http(
+    requests: usize,
+    handler: impl Fn(&HttpRequest) -> (u16, Vec<u8>) + Send + 'static,
+) -> (String, std::thread::JoinHandle<Vec<HttpRequest>>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let mut received = vec![];
+        for _ in 0..requests {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut headers = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (key, value) = line.split_once(':').unwrap();
+                headers.push((key.trim().to_lowercase(), value.trim().to_string()));
+            }
+            let mut request = HttpRequest {
+                request_line: request_line.trim_end().to_string(),
+                headers,
+                body: String::new(),
+            };
+            let length: usize = request
+                .header("content-length")
+                .map_or(0, |l| l.parse().unwrap());
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+            request.body = String::from_utf8(body).unwrap();
+
+            let (status, body) = handler(&request);
+            write!(
+                stream,
+                "HTTP/1.1 {status} Test\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+            received.push(request);
+        }
+        received
+    });
+    (url, handle)
+}
@@ -2,9 +2,30 @@
 //!
 //! The chunk files map Ethereum addresses to the transactions they appear in.
 //! Functions in this library allow for this data to be extracted for use.
+pub mod address;
+pub mod appearance_index;
+pub mod bloom;
+pub mod car;
+pub mod chain;
+pub mod chunk_index;
+pub mod cid;
 pub(crate) mod constants;
+pub mod contract;
+pub mod download;
+#[cfg(feature = "parquet")]
+pub mod export;
 pub mod files;
+#[cfg(test)]
+mod fixtures;
+pub mod manifest;
 pub mod parse;
+pub mod portal;
+pub mod query;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod ssz;
 pub mod structure;
+pub mod write;
 
+pub use address::Address;
 pub use parse::*;
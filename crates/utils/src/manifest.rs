@@ -0,0 +1,790 @@
+//! Unchained Index manifest and offline verification of local files against it.
+//!
+//! The manifest lists each chunk by block range with the IPFS CIDs and sizes of
+//! the index (.bin) and bloom (.bloom) files. Local files are checked by
+//! computing their CIDs (see [crate::cid]).
+//!
+//! Manifests can also be compared with an older manifest, or with the chunks
+//! in a local directory, to plan the downloads and deletions needed to bring
+//! a local copy up to date.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    cid::{cid_v0, FileCid},
+    files::{bloom_filename, bloom_files, get_range, ChunkFile, ChunksDir, FilesError},
+    parse::{open_chunk, BlockRange, ParseError},
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("File {filename} could not be opened {source}")]
+    FileOpener {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Manifest {filename} is not valid JSON {source}")]
+    InvalidJson {
+        source: serde_json::Error,
+        filename: PathBuf,
+    },
+    #[error("Unable to read {filename} {source}")]
+    Read {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("FilesError {0}")]
+    FilesError(#[from] FilesError),
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+}
+
+/// Version of the manifests published for the current chunk format.
+pub const DEFAULT_MANIFEST_VERSION: &str = "trueblocks-core@v2.0.0-release";
+
+/// Manifest of the Unchained Index for a chain.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    /// E.g., "trueblocks-core@v2.0.0-release"
+    pub version: String,
+    /// E.g., "mainnet"
+    pub chain: String,
+    /// CID of the specification document.
+    pub specification: String,
+    /// Chunks, oldest first.
+    pub chunks: Vec<ManifestChunk>,
+}
+
+/// Manifest entry for a single chunk.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestChunk {
+    /// E.g., "017190314-017193246"
+    pub range: String,
+    pub bloom_hash: String,
+    pub bloom_size: u64,
+    pub index_hash: String,
+    pub index_size: u64,
+}
+
+impl ManifestChunk {
+    /// Block range of the chunk.
+    pub fn block_range(&self) -> Result<BlockRange, FilesError> {
+        get_range(Path::new(&self.range))
+    }
+}
+
+impl Manifest {
+    /// Reads a manifest JSON file.
+    pub fn from_file(path: &Path) -> Result<Self, ManifestError> {
+        let file = File::open(path).map_err(|e| ManifestError::FileOpener {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| ManifestError::InvalidJson {
+            source: e,
+            filename: path.to_path_buf(),
+        })
+    }
+
+    /// Builds a manifest for the chunks in a directory and their bloom filters.
+    ///
+    /// Bloom filters are read from "{range}.bloom" in `blooms_dir`. CIDs are those
+    /// that 'ipfs add' gives the uncompressed files, so compressed chunks must be
+    /// published uncompressed.
+    pub fn from_chunks(
+        dir: &ChunksDir,
+        blooms_dir: &Path,
+        version: &str,
+        chain: &str,
+        specification: &str,
+    ) -> Result<Self, ManifestError> {
+        let mut chunks = vec![];
+        for file in &dir.paths {
+            let index = cid_v0(open_chunk(&file.path)?).map_err(|e| ManifestError::Read {
+                source: e,
+                filename: file.path.to_path_buf(),
+            })?;
+            let bloom_path = blooms_dir.join(bloom_filename(&file.range));
+            let bloom_file = File::open(&bloom_path).map_err(|e| ManifestError::FileOpener {
+                source: e,
+                filename: bloom_path.to_path_buf(),
+            })?;
+            let bloom = cid_v0(BufReader::new(bloom_file)).map_err(|e| ManifestError::Read {
+                source: e,
+                filename: bloom_path.to_path_buf(),
+            })?;
+            chunks.push(ManifestChunk {
+                range: format!("{:09}-{:09}", file.range.old, file.range.new),
+                bloom_hash: bloom.cid,
+                bloom_size: bloom.size,
+                index_hash: index.cid,
+                index_size: index.size,
+            });
+        }
+        Ok(Manifest {
+            version: version.to_string(),
+            chain: chain.to_string(),
+            specification: specification.to_string(),
+            chunks,
+        })
+    }
+
+    /// Manifest entry for the chunk with exactly the given range.
+    pub fn chunk_for(&self, range: &BlockRange) -> Option<&ManifestChunk> {
+        self.chunks
+            .iter()
+            .find(|c| c.block_range().is_ok_and(|r| r == *range))
+    }
+
+    /// Checks every chunk in a directory against the index CIDs in the manifest.
+    ///
+    /// Compressed chunks are checked by the CID of their decompressed contents.
+    pub fn verify_index_files(&self, dir: &ChunksDir) -> Result<CidReport, ManifestError> {
+        self.verify(&dir.paths, FileKind::Index)
+    }
+
+    /// Checks every bloom filter (".bloom") in a directory against the bloom CIDs
+    /// in the manifest.
+    pub fn verify_bloom_files(&self, blooms_dir: &Path) -> Result<CidReport, ManifestError> {
+        self.verify(&bloom_files(blooms_dir)?, FileKind::Bloom)
+    }
+
+    /// Compares an older manifest with this one.
+    pub fn diff_from(&self, older: &Manifest) -> Result<ManifestDiff, ManifestError> {
+        let mut previous = vec![];
+        for chunk in &older.chunks {
+            previous.push(PreviousChunk {
+                range: chunk.block_range()?,
+                path: None,
+                index_hash: Some(chunk.index_hash.to_owned()),
+                bloom_hash: Some(chunk.bloom_hash.to_owned()),
+            });
+        }
+        let mut diff = self.diff_chunks(previous)?;
+        diff.old_version = Some(older.version.to_owned());
+        diff.specification_changed = older.specification != self.specification;
+        Ok(diff)
+    }
+
+    /// Compares the chunks in a local directory with this manifest.
+    ///
+    /// The CIDs of local files are not computed, so chunks with a range in the
+    /// manifest are listed as unverified and planned for re-verification (see
+    /// [Manifest::verify_index_files]).
+    pub fn diff_from_dir(&self, dir: &ChunksDir) -> Result<ManifestDiff, ManifestError> {
+        let previous = dir
+            .paths
+            .iter()
+            .map(|file| PreviousChunk {
+                range: file.range,
+                path: Some(file.path.to_path_buf()),
+                index_hash: None,
+                bloom_hash: None,
+            })
+            .collect();
+        self.diff_chunks(previous)
+    }
+
+    fn diff_chunks(&self, previous: Vec<PreviousChunk>) -> Result<ManifestDiff, ManifestError> {
+        let mut diff = ManifestDiff {
+            new_version: self.version.to_owned(),
+            ..Default::default()
+        };
+        let mut current: BTreeMap<(u32, u32), (BlockRange, &ManifestChunk)> = BTreeMap::new();
+        for chunk in &self.chunks {
+            let range = chunk.block_range()?;
+            current.insert((range.old, range.new), (range, chunk));
+        }
+        let mut old_only: Vec<PreviousChunk> = vec![];
+        let mut matched: Vec<(u32, u32)> = vec![];
+        for prev in previous {
+            let key = (prev.range.old, prev.range.new);
+            let Some((range, chunk)) = current.get(&key) else {
+                old_only.push(prev);
+                continue;
+            };
+            matched.push(key);
+            match (&prev.index_hash, &prev.bloom_hash) {
+                (Some(index), Some(bloom))
+                    if *index == chunk.index_hash && *bloom == chunk.bloom_hash =>
+                {
+                    diff.unchanged += 1
+                }
+                (Some(index), Some(bloom)) => {
+                    diff.rehashed.push(RehashedChunk {
+                        range: *range,
+                        old_index_hash: index.to_owned(),
+                        new_index_hash: chunk.index_hash.to_owned(),
+                        old_bloom_hash: bloom.to_owned(),
+                        new_bloom_hash: chunk.bloom_hash.to_owned(),
+                    });
+                    diff.plan.push(PlanAction::download(*range, chunk));
+                }
+                _ => {
+                    diff.unverified.push(*range);
+                    diff.plan.push(PlanAction::Reverify {
+                        range: *range,
+                        path: prev.path,
+                        index_hash: chunk.index_hash.to_owned(),
+                    });
+                }
+            }
+        }
+        for key in matched {
+            current.remove(&key);
+        }
+        // Chunks only on one side are grouped by overlapping ranges. A group with
+        // chunks on both sides is a re-consolidation of that part of the index.
+        let mut sides: Vec<(BlockRange, Option<PreviousChunk>, Option<&ManifestChunk>)> = old_only
+            .into_iter()
+            .map(|prev| (prev.range, Some(prev), None))
+            .chain(
+                current
+                    .into_values()
+                    .map(|(range, chunk)| (range, None, Some(chunk))),
+            )
+            .collect();
+        sides.sort_by_key(|(range, _, _)| (range.old, range.new));
+        let mut groups: Vec<OverlapGroup> = vec![];
+        for (range, prev, chunk) in sides {
+            let joins = groups.last().is_some_and(|group| range.old <= group.end);
+            if !joins {
+                groups.push(OverlapGroup {
+                    end: range.new,
+                    old: vec![],
+                    new: vec![],
+                });
+            }
+            let group = groups.last_mut().expect("Group was just added");
+            group.end = group.end.max(range.new);
+            if let Some(prev) = prev {
+                group.old.push(prev);
+            }
+            if let Some(chunk) = chunk {
+                group.new.push((range, chunk));
+            }
+        }
+        for group in groups {
+            if !group.old.is_empty() && !group.new.is_empty() {
+                diff.reranged.push(RerangedChunks {
+                    old: group.old.iter().map(|prev| prev.range).collect(),
+                    new: group.new.iter().map(|(range, _)| *range).collect(),
+                });
+            } else {
+                diff.removed.extend(group.old.iter().map(|prev| prev.range));
+                diff.added.extend(group.new.iter().map(|(range, _)| *range));
+            }
+            for (range, chunk) in group.new {
+                diff.plan.push(PlanAction::download(range, chunk));
+            }
+            for prev in group.old {
+                diff.plan.push(PlanAction::Delete {
+                    range: prev.range,
+                    path: prev.path,
+                });
+            }
+        }
+        diff.plan.sort_by_key(|action| action.order());
+        Ok(diff)
+    }
+
+    fn verify(&self, files: &[ChunkFile], kind: FileKind) -> Result<CidReport, ManifestError> {
+        let mut report = CidReport::default();
+        for file in files {
+            let check = self.check_file(file, kind)?;
+            match check.status {
+                CidStatus::Valid => report.valid += 1,
+                CidStatus::Invalid => report.invalid += 1,
+                CidStatus::NotInManifest => report.not_in_manifest += 1,
+            }
+            report.checks.push(check);
+        }
+        Ok(report)
+    }
+
+    fn check_file(&self, file: &ChunkFile, kind: FileKind) -> Result<CidCheck, ManifestError> {
+        let reader = open_chunk(&file.path)?;
+        let computed = cid_v0(reader).map_err(|e| ManifestError::Read {
+            source: e,
+            filename: file.path.to_path_buf(),
+        })?;
+        let expected: Option<FileCid> = self.chunk_for(&file.range).map(|c| match kind {
+            FileKind::Index => FileCid {
+                cid: c.index_hash.to_owned(),
+                size: c.index_size,
+            },
+            FileKind::Bloom => FileCid {
+                cid: c.bloom_hash.to_owned(),
+                size: c.bloom_size,
+            },
+        });
+        let status = match &expected {
+            Some(expected) if *expected == computed => CidStatus::Valid,
+            Some(_) => CidStatus::Invalid,
+            None => CidStatus::NotInManifest,
+        };
+        Ok(CidCheck {
+            path: file.path.to_path_buf(),
+            range: file.range,
+            kind,
+            status,
+            expected_cid: expected.as_ref().map(|e| e.cid.to_owned()),
+            expected_size: expected.as_ref().map(|e| e.size),
+            computed_cid: computed.cid,
+            size: computed.size,
+        })
+    }
+}
+
+/// Kind of file listed in the manifest.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileKind {
+    Index,
+    Bloom,
+}
+
+/// Result of checking a file against the manifest.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CidStatus {
+    /// CID and size match the manifest.
+    Valid,
+    /// CID or size differ from the manifest.
+    Invalid,
+    /// No manifest entry has the range of the file.
+    NotInManifest,
+}
+
+/// Details of a file checked against the manifest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CidCheck {
+    pub path: PathBuf,
+    pub range: BlockRange,
+    pub kind: FileKind,
+    pub status: CidStatus,
+    pub expected_cid: Option<String>,
+    pub expected_size: Option<u64>,
+    pub computed_cid: String,
+    pub size: u64,
+}
+
+/// Files checked against the manifest.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CidReport {
+    pub valid: usize,
+    pub invalid: usize,
+    pub not_in_manifest: usize,
+    pub checks: Vec<CidCheck>,
+}
+
+impl CidReport {
+    /// Combines the checks of two reports.
+    pub fn extend(&mut self, other: CidReport) {
+        self.valid += other.valid;
+        self.invalid += other.invalid;
+        self.not_in_manifest += other.not_in_manifest;
+        self.checks.extend(other.checks);
+    }
+}
+
+/// A chunk from an older manifest or a local directory.
+struct PreviousChunk {
+    range: BlockRange,
+    path: Option<PathBuf>,
+    index_hash: Option<String>,
+    bloom_hash: Option<String>,
+}
+
+/// Chunks from either side whose ranges overlap, ending at block `end`.
+struct OverlapGroup<'a> {
+    end: u32,
+    old: Vec<PreviousChunk>,
+    new: Vec<(BlockRange, &'a ManifestChunk)>,
+}
+
+/// Chunk with the same range but different files in the newer manifest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RehashedChunk {
+    pub range: BlockRange,
+    pub old_index_hash: String,
+    pub new_index_hash: String,
+    pub old_bloom_hash: String,
+    pub new_bloom_hash: String,
+}
+
+/// Overlapping chunks that were replaced by chunks with different ranges.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RerangedChunks {
+    pub old: Vec<BlockRange>,
+    pub new: Vec<BlockRange>,
+}
+
+/// Step needed to bring a local copy up to date with a manifest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum PlanAction {
+    /// Fetch the files of a chunk.
+    #[serde(rename_all = "camelCase")]
+    Download {
+        range: BlockRange,
+        index_hash: String,
+        index_size: u64,
+        bloom_hash: String,
+        bloom_size: u64,
+    },
+    /// Check a local file against the manifest CID.
+    #[serde(rename_all = "camelCase")]
+    Reverify {
+        range: BlockRange,
+        path: Option<PathBuf>,
+        index_hash: String,
+    },
+    /// Remove the files of a chunk that is no longer in the manifest.
+    Delete {
+        range: BlockRange,
+        path: Option<PathBuf>,
+    },
+}
+
+impl PlanAction {
+    fn download(range: BlockRange, chunk: &ManifestChunk) -> Self {
+        PlanAction::Download {
+            range,
+            index_hash: chunk.index_hash.to_owned(),
+            index_size: chunk.index_size,
+            bloom_hash: chunk.bloom_hash.to_owned(),
+            bloom_size: chunk.bloom_size,
+        }
+    }
+    /// Downloads come before deletions so that replaced ranges stay covered.
+    fn order(&self) -> (u8, u32, u32) {
+        match self {
+            PlanAction::Download { range, .. } => (0, range.old, range.new),
+            PlanAction::Reverify { range, .. } => (1, range.old, range.new),
+            PlanAction::Delete { range, .. } => (2, range.old, range.new),
+        }
+    }
+}
+
+/// Differences between a newer manifest and an older manifest or local directory.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestDiff {
+    /// Version of the older manifest (None for a local directory).
+    pub old_version: Option<String>,
+    pub new_version: String,
+    /// Whether the manifests reference different specifications.
+    pub specification_changed: bool,
+    /// Number of chunks with the same range and files.
+    pub unchanged: usize,
+    /// Chunks only in the newer manifest that do not overlap removed chunks.
+    pub added: Vec<BlockRange>,
+    /// Chunks no longer present that do not overlap added chunks.
+    pub removed: Vec<BlockRange>,
+    pub rehashed: Vec<RehashedChunk>,
+    pub reranged: Vec<RerangedChunks>,
+    /// Local chunks with a manifest range whose CIDs have not been checked.
+    pub unverified: Vec<BlockRange>,
+    /// Downloads, then re-verifications, then deletions, each by block.
+    pub plan: Vec<PlanAction>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bloom::write_bloom, cid::cid_v0_of_file, fixtures, structure::AddressData};
+    use std::fs;
+
+    #[test]
+    fn corrupted_bloom_is_invalid() {
+        let dir = fixtures::test_dir("manifest-blooms");
+        let chunk = fixtures::write_sample(&dir);
+        let bloom = write_bloom(&chunk, &dir).unwrap();
+        let chunks = ChunksDir::new(&dir).unwrap();
+        let manifest = Manifest::from_chunks(&chunks, &dir, "v", "mainnet", "spec").unwrap();
+
+        // Chunks and blooms may share a directory: each is listed by its extension.
+        let report = manifest.verify_bloom_files(&dir).unwrap();
+        assert_eq!(report.valid, 1);
+        assert_eq!(report.checks[0].kind, FileKind::Bloom);
+        assert_eq!(report.checks[0].path, bloom.path);
+        assert_eq!(manifest.verify_index_files(&chunks).unwrap().valid, 1);
+
+        let mut bytes = fs::read(&bloom.path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&bloom.path, bytes).unwrap();
+        let report = manifest.verify_bloom_files(&dir).unwrap();
+        assert_eq!((report.valid, report.invalid), (0, 1));
+        assert_eq!(report.checks[0].status, CidStatus::Invalid);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn range(old: u32, new: u32) -> BlockRange {
+        BlockRange::new(old, new).unwrap()
+    }
+
+    fn entry(old: u32, new: u32, hash: &str) -> ManifestChunk {
+        ManifestChunk {
+            range: format!("{:09}-{:09}", old, new),
+            bloom_hash: format!("bloom-{hash}"),
+            bloom_size: 1,
+            index_hash: format!("index-{hash}"),
+            index_size: 2,
+        }
+    }
+
+    fn manifest(version: &str, chunks: Vec<ManifestChunk>) -> Manifest {
+        Manifest {
+            version: version.to_string(),
+            chain: "mainnet".to_string(),
+            specification: format!("spec-{version}"),
+            chunks,
+        }
+    }
+
+    /// Kind and range of each action, in plan order.
+    fn plan_ranges(plan: &[PlanAction]) -> Vec<(&'static str, BlockRange)> {
+        plan.iter()
+            .map(|action| match action {
+                PlanAction::Download { range, .. } => ("download", *range),
+                PlanAction::Reverify { range, .. } => ("reverify", *range),
+                PlanAction::Delete { range, .. } => ("delete", *range),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_between_manifests() {
+        let older = manifest(
+            "1",
+            vec![
+                entry(0, 9, "a"),
+                entry(10, 19, "b"),
+                entry(20, 29, "c"),
+                entry(30, 39, "d"),
+                entry(40, 49, "e"),
+                entry(60, 69, "g"),
+            ],
+        );
+        let newer = manifest(
+            "2",
+            vec![
+                // Unchanged.
+                entry(0, 9, "a"),
+                // Re-hashed.
+                entry(10, 19, "b2"),
+                // Consolidated from 20-29 and 30-39.
+                entry(20, 39, "cd"),
+                // Added, while 40-49 is removed.
+                entry(50, 59, "f"),
+                // Split from 60-69.
+                entry(60, 64, "g1"),
+                entry(65, 69, "g2"),
+            ],
+        );
+        let diff = newer.diff_from(&older).unwrap();
+        assert_eq!(diff.old_version.as_deref(), Some("1"));
+        assert_eq!(diff.new_version, "2");
+        assert!(diff.specification_changed);
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(
+            diff.rehashed,
+            vec![RehashedChunk {
+                range: range(10, 19),
+                old_index_hash: "index-b".to_string(),
+                new_index_hash: "index-b2".to_string(),
+                old_bloom_hash: "bloom-b".to_string(),
+                new_bloom_hash: "bloom-b2".to_string(),
+            }]
+        );
+        assert_eq!(
+            diff.reranged,
+            vec![
+                RerangedChunks {
+                    old: vec![range(20, 29), range(30, 39)],
+                    new: vec![range(20, 39)],
+                },
+                RerangedChunks {
+                    old: vec![range(60, 69)],
+                    new: vec![range(60, 64), range(65, 69)],
+                },
+            ]
+        );
+        assert_eq!(diff.removed, vec![range(40, 49)]);
+        assert_eq!(diff.added, vec![range(50, 59)]);
+        assert!(diff.unverified.is_empty());
+        // Downloads first, so that replaced ranges stay covered, then deletions.
+        assert_eq!(
+            plan_ranges(&diff.plan),
+            vec![
+                ("download", range(10, 19)),
+                ("download", range(20, 39)),
+                ("download", range(50, 59)),
+                ("download", range(60, 64)),
+                ("download", range(65, 69)),
+                ("delete", range(20, 29)),
+                ("delete", range(30, 39)),
+                ("delete", range(40, 49)),
+                ("delete", range(60, 69)),
+            ]
+        );
+        assert_eq!(
+            diff.plan[0],
+            PlanAction::Download {
+                range: range(10, 19),
+                index_hash: "index-b2".to_string(),
+                index_size: 2,
+                bloom_hash: "bloom-b2".to_string(),
+                bloom_size: 1,
+            }
+        );
+
+        let same = newer.diff_from(&newer).unwrap();
+        assert_eq!(same.unchanged, newer.chunks.len());
+        assert!(same.plan.is_empty());
+        assert!(!same.specification_changed);
+    }
+
+    #[test]
+    fn diff_against_directory() {
+        let dir = fixtures::test_dir("manifest-diff");
+        for name in [
+            "000000000-000000009.bin",
+            "000000010-000000019.bin",
+            "000000070-000000079.bin",
+        ] {
+            fs::write(dir.join(name), []).unwrap();
+        }
+        let chunks = ChunksDir::new(&dir).unwrap();
+        let newer = manifest(
+            "2",
+            vec![
+                entry(0, 9, "a"),
+                entry(10, 14, "b1"),
+                entry(15, 19, "b2"),
+                entry(20, 29, "c"),
+            ],
+        );
+        let diff = newer.diff_from_dir(&chunks).unwrap();
+        assert_eq!(diff.old_version, None);
+        assert_eq!(diff.unchanged, 0);
+        assert_eq!(diff.unverified, vec![range(0, 9)]);
+        assert_eq!(
+            diff.reranged,
+            vec![RerangedChunks {
+                old: vec![range(10, 19)],
+                new: vec![range(10, 14), range(15, 19)],
+            }]
+        );
+        assert_eq!(diff.added, vec![range(20, 29)]);
+        assert_eq!(diff.removed, vec![range(70, 79)]);
+        assert_eq!(
+            plan_ranges(&diff.plan),
+            vec![
+                ("download", range(10, 14)),
+                ("download", range(15, 19)),
+                ("download", range(20, 29)),
+                ("reverify", range(0, 9)),
+                ("delete", range(10, 19)),
+                ("delete", range(70, 79)),
+            ]
+        );
+        assert_eq!(
+            diff.plan[3],
+            PlanAction::Reverify {
+                range: range(0, 9),
+                path: Some(dir.join("000000000-000000009.bin")),
+                index_hash: "index-a".to_string(),
+            }
+        );
+        assert_eq!(
+            diff.plan[5],
+            PlanAction::Delete {
+                range: range(70, 79),
+                path: Some(dir.join("000000070-000000079.bin")),
+            }
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn generated_manifest_verifies() {
+        let dir = fixtures::test_dir("manifest-generate");
+        let blooms_dir = dir.join("blooms");
+        fs::create_dir_all(&blooms_dir).unwrap();
+        let first = fixtures::write_sample(&dir);
+        let second = fixtures::write_fixture(
+            &dir,
+            range(17_190_876, 17_190_880),
+            vec![AddressData {
+                address: fixtures::address(fixtures::ADDRESS_A),
+                appearances: vec![fixtures::tx(17_190_880, 1)],
+            }],
+        );
+        let first_bloom = write_bloom(&first, &blooms_dir).unwrap();
+        let chunks = ChunksDir::new(&dir).unwrap();
+
+        // The second chunk has no bloom yet.
+        let result = Manifest::from_chunks(&chunks, &blooms_dir, "v", "mainnet", "spec");
+        assert!(matches!(
+            result,
+            Err(ManifestError::FileOpener { filename, .. })
+                if filename == blooms_dir.join("017190876-017190880.bloom")
+        ));
+
+        write_bloom(&second, &blooms_dir).unwrap();
+        let manifest = Manifest::from_chunks(&chunks, &blooms_dir, "v", "mainnet", "spec").unwrap();
+        assert_eq!(manifest.chunks.len(), 2);
+        let entry = &manifest.chunks[0];
+        assert_eq!(entry.range, "017190873-017190875");
+        let index = cid_v0_of_file(&first.path).unwrap();
+        assert_eq!(
+            (&entry.index_hash, entry.index_size),
+            (&index.cid, index.size)
+        );
+        let bloom = cid_v0_of_file(&first_bloom.path).unwrap();
+        assert_eq!(
+            (&entry.bloom_hash, entry.bloom_size),
+            (&bloom.cid, bloom.size)
+        );
+
+        let report = manifest.verify_index_files(&chunks).unwrap();
+        assert_eq!(
+            (report.valid, report.invalid, report.not_in_manifest),
+            (2, 0, 0)
+        );
+        let report = manifest.verify_bloom_files(&blooms_dir).unwrap();
+        assert_eq!(
+            (report.valid, report.invalid, report.not_in_manifest),
+            (2, 0, 0)
+        );
+
+        // A chunk not in the manifest.
+        fixtures::write_fixture(&dir, range(1, 2), vec![]);
+        let report = manifest
+            .verify_index_files(&ChunksDir::new(&dir).unwrap())
+            .unwrap();
+        assert_eq!(
+            (report.valid, report.invalid, report.not_in_manifest),
+            (2, 0, 1)
+        );
+        assert_eq!(report.checks[0].status, CidStatus::NotInManifest);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
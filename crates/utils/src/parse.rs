@@ -1,19 +1,70 @@
+use std::cmp::Ordering;
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
 use hex::FromHexError;
 use log::debug;
+use serde::{Deserialize, Serialize};
 
 use crate::files::FilesError;
 use crate::structure::StructureError;
 
 use super::{
-    constants::{AD_ENTRY, AP_ENTRY},
-    files::{file_structure, get_range, no_unexpected_appearances},
+    address::Address,
+    constants::{AD_ENTRY, AP_ENTRY, VER},
+    files::{chunk_filename, file_structure, get_range, no_unexpected_appearances, ChunkEncoding},
     structure::{AddressData, AddressEntry, Body, Header, TransactionId},
 };
 
+/// Source of chunk bytes that supports random access.
+pub(crate) trait ChunkReader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ChunkReader for T {}
+
+/// Opens a chunk file for random access.
+///
+/// Compressed chunks are not seekable, so they are decompressed into memory.
+pub(crate) fn open_chunk(path: &Path) -> Result<Box<dyn ChunkReader>, ParseError> {
+    let file = File::open(path).map_err(|e| ParseError::FileOpener {
+        filename: path.to_path_buf(),
+        source: e,
+    })?;
+    let encoding = ChunkEncoding::from_path(path);
+    match encoding {
+        ChunkEncoding::Raw => Ok(Box::new(BufReader::new(file))),
+        #[cfg(feature = "gzip")]
+        ChunkEncoding::Gzip => decompress(flate2::read::GzDecoder::new(BufReader::new(file)), path),
+        #[cfg(feature = "zstd")]
+        ChunkEncoding::Zstd => {
+            let decoder = zstd::Decoder::new(file).map_err(|e| ParseError::Decompress {
+                source: e,
+                filename: path.to_path_buf(),
+            })?;
+            decompress(decoder, path)
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(ParseError::UnsupportedEncoding {
+            filename: path.to_path_buf(),
+            encoding,
+            feature: encoding.feature(),
+        }),
+    }
+}
+
+/// Reads a whole decompressed stream into memory.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn decompress(mut decoder: impl Read, path: &Path) -> Result<Box<dyn ChunkReader>, ParseError> {
+    let mut bytes = vec![];
+    decoder
+        .read_to_end(&mut bytes)
+        .map_err(|e| ParseError::Decompress {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+    Ok(Box::new(Cursor::new(bytes)))
+}
+
 use thiserror::{self, Error};
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -29,6 +80,17 @@ pub enum ParseError {
         source: std::io::Error,
         filename: PathBuf,
     },
+    #[error("File {filename} could not be decompressed {source}")]
+    Decompress {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("File {filename} is {encoding:?} compressed, which requires the `{feature}` feature")]
+    UnsupportedEncoding {
+        filename: PathBuf,
+        encoding: ChunkEncoding,
+        feature: &'static str,
+    },
     #[error("Unable to jump to index {address} in addresses table {source}")]
     InvalidJumpToAddresses {
         source: std::io::Error,
@@ -52,7 +114,8 @@ pub enum ParseError {
 /**
 Unchained Index .bin file read and tracker.
 
-Is a helper used in the creation of the address-appearance-index.
+Is a helper used in the creation of the address-appearance-index
+(see [crate::appearance_index]).
 
 # Example
 The following example reads specific chunk files, filtering by block
@@ -64,43 +127,33 @@ Transaction data meets the following requirements:
 - Specific addresses
 
 ```no_run
-use anyhow::bail;
-use min_know::{
-    config::{address_appearance_index::Network, choices::{DataKind, DirNature}},
-    database::types::Todd,
-    specs::address_appearance_index::AAISpec,
-    utils::unchained::{
-        files::ChunksDir,
-        types::{BlockRange, UnchainedFile},
-    },
-};
-let data_kind = DataKind::AddressAppearanceIndex(Network::default());
-let db: Todd<AAISpec> = Todd::init(data_kind, DirNature::Sample)?;
+use std::path::Path;
+use unchained_utils::{files::ChunksDir, BlockRange, UnchainedFile};
 
 let desired_blocks = BlockRange::new(0, 16_000_000)?;
-let chunk_files: ChunksDir = ChunksDir::new(&db.config.raw_source)?;
+let chunk_files = ChunksDir::new(Path::new("trueblocks/unchained/mainnet/finalized"))?;
 let Some(relevant_files) = chunk_files.for_range(&desired_blocks) else {
-    bail!("No relevant files")};
+    return Ok(());
+};
 
-let address_starts_with = String::from("4e");
+let address_starts_with = "4e";
 
 // Counter for the appearances that match the description.
 let mut sum = 0;
 for chunk in relevant_files {
     let path = chunk.path.to_owned();
-    let mut file = UnchainedFile::new(path, desired_blocks)?;
+    let mut file = UnchainedFile::from_file(path, desired_blocks)?;
     // Read appearances that have correct leading char and are in desired range.
-    file.with_parsed(&address_starts_with)?;
+    file.with_parsed(Some(address_starts_with))?;
     sum += file.parsed.len();
 }
-// The sample dir has 6204 addresses that start with 0x4e.
-assert_eq!(sum, 6204);
-# Ok::<(), anyhow::Error>(())
+println!("{sum} addresses start with 0x4e");
+# Ok::<(), Box<dyn std::error::Error>>(())
 ```
 */
 pub struct UnchainedFile {
     pub(crate) path: PathBuf,
-    pub(crate) reader: BufReader<File>,
+    pub(crate) reader: Box<dyn ChunkReader>,
     pub(crate) header: Header,
     pub(crate) body: Body,
     pub present: BlockRange,
@@ -111,16 +164,57 @@ pub struct UnchainedFile {
 
 impl UnchainedFile {
     /// Obtains metadata and prepares Unchained Index file for reading.
+    ///
+    /// Compressed chunks (".bin.gz", ".bin.zst") are decompressed into memory.
     pub fn from_file(path: PathBuf, desired: BlockRange) -> Result<Self, ParseError> {
-        let file = File::open(&path).map_err(|e| ParseError::FileOpener {
-            filename: path.to_path_buf(),
-            source: e,
-        })?;
-        let mut reader: BufReader<File> = BufReader::new(file);
+        let reader = open_chunk(&path)?;
+        let present: BlockRange = get_range(&path)?;
+        UnchainedFile::from_parts(path, reader, present, desired)
+    }
+
+    /// Prepares chunk data from any seekable source for reading.
+    ///
+    /// The block range covered by the chunk (usually taken from the file name)
+    /// must be supplied. The source must hold raw (uncompressed) chunk bytes.
+    pub fn from_reader<R>(
+        reader: R,
+        present: BlockRange,
+        desired: BlockRange,
+    ) -> Result<Self, ParseError>
+    where
+        R: Read + Seek + Send + 'static,
+    {
+        let path = PathBuf::from(chunk_filename(&present));
+        UnchainedFile::from_parts(path, Box::new(reader), present, desired)
+    }
+
+    /// Prepares an in-memory chunk for reading.
+    ///
+    /// Accepts owned buffers (`Vec<u8>`) and static data (`include_bytes!`).
+    /// Borrowed slices can be copied with `to_vec()`.
+    pub fn from_bytes<B>(
+        bytes: B,
+        present: BlockRange,
+        desired: BlockRange,
+    ) -> Result<Self, ParseError>
+    where
+        B: AsRef<[u8]> + Send + 'static,
+    {
+        UnchainedFile::from_reader(Cursor::new(bytes), present, desired)
+    }
+
+    /// Reads the header and checks the chunk intersects the desired range.
+    ///
+    /// The path is used to identify the chunk in errors.
+    fn from_parts(
+        path: PathBuf,
+        mut reader: Box<dyn ChunkReader>,
+        present: BlockRange,
+        desired: BlockRange,
+    ) -> Result<Self, ParseError> {
         let header = Header::from_reader(reader.by_ref(), &path)?;
         let body: Body = file_structure(&header);
         let parsed: Vec<AddressData> = vec![AddressData::default()];
-        let present: BlockRange = get_range(&path)?;
 
         // If no intersection, return error
         if !present.intersection_exists(&desired) {
@@ -167,6 +261,8 @@ impl UnchainedFile {
         let mut txs: Vec<AddressData> = vec![];
         let mut addresses_parsed = 0;
         // 1.
+        self.body.addresses.current = self.body.addresses.start;
+        self.seek_address_table()?;
         while addresses_parsed < self.header.n_addresses {
             // 2.
             let address_entry = AddressEntry::from_reader(self.reader.by_ref())?;
@@ -174,7 +270,7 @@ impl UnchainedFile {
             // 3.
             let app_passed = address_entry.offset as usize * AP_ENTRY;
             self.body.appearances.current = self.body.appearances.start + app_passed;
-            let address = address_entry.address.clone();
+            let address = address_entry.address;
 
             let good_address = match &address_starting_bytes {
                 Some(leading_byte) => address.starts_with(leading_byte),
@@ -198,12 +294,7 @@ impl UnchainedFile {
             // 8.
             self.body.addresses.current += AD_ENTRY;
             // 9.
-            self.reader
-                .seek(SeekFrom::Start(self.body.addresses.current as u64))
-                .map_err(|e| ParseError::InvalidJumpToAddresses {
-                    source: e,
-                    address: self.body.addresses.current,
-                })?;
+            self.seek_address_table()?;
         }
         self.parsed = txs;
 
@@ -216,6 +307,117 @@ impl UnchainedFile {
         Ok(())
     }
 
+    /// Hash identifying the specification version of the file.
+    pub fn version(&self) -> [u8; VER] {
+        self.header.version
+    }
+
+    /// Reads every address and its appearances in the desired range.
+    pub fn read_all(&mut self) -> Result<Vec<AddressData>, ParseError> {
+        self.with_parsed(None)?;
+        Ok(std::mem::take(&mut self.parsed))
+    }
+
+    /// Reads every address in the address table, without reading appearances.
+    ///
+    /// The desired range is not applied.
+    pub fn addresses(&mut self) -> Result<Vec<Address>, ParseError> {
+        self.body.addresses.current = self.body.addresses.start;
+        self.seek_address_table()?;
+        let mut addresses = Vec::with_capacity(self.header.n_addresses as usize);
+        for _ in 0..self.header.n_addresses {
+            let address_entry = AddressEntry::from_reader(self.reader.by_ref())?;
+            self.body.addresses.current += AD_ENTRY;
+            addresses.push(address_entry.address);
+        }
+        Ok(addresses)
+    }
+
+    /// Gets the appearances for a single address in the desired range.
+    ///
+    /// The address table is sorted, so the entry is found by binary search
+    /// without reading the whole table.
+    /// Returns None if the address is absent or has no appearances in range.
+    pub fn appearances_of(
+        &mut self,
+        address: &Address,
+    ) -> Result<Option<Vec<TransactionId>>, ParseError> {
+        let mut low = 0;
+        let mut high = self.header.n_addresses as usize;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let entry = self.address_entry_at(mid)?;
+            match entry.address.cmp(address) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => {
+                    self.body.appearances.current =
+                        self.body.appearances.start + entry.offset as usize * AP_ENTRY;
+                    return self.parse_appearances(&entry);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Gets the appearances in the desired range for many addresses in one pass.
+    ///
+    /// The requested addresses are sorted and walked alongside the sorted address
+    /// table. Appearances are only read for addresses present in the chunk.
+    /// Addresses without appearances in range are omitted from the result.
+    pub fn lookup_many(&mut self, addresses: &[Address]) -> Result<Vec<AddressData>, ParseError> {
+        let mut wanted = addresses.to_vec();
+        wanted.sort();
+        wanted.dedup();
+        let mut next_wanted = 0;
+        let mut found: Vec<AddressData> = vec![];
+
+        self.body.addresses.current = self.body.addresses.start;
+        self.seek_address_table()?;
+        for _ in 0..self.header.n_addresses {
+            if next_wanted == wanted.len() {
+                break;
+            }
+            let address_entry = AddressEntry::from_reader(self.reader.by_ref())?;
+            self.body.addresses.current += AD_ENTRY;
+            while next_wanted < wanted.len() && wanted[next_wanted] < address_entry.address {
+                next_wanted += 1;
+            }
+            if next_wanted < wanted.len() && wanted[next_wanted] == address_entry.address {
+                next_wanted += 1;
+                let app_passed = address_entry.offset as usize * AP_ENTRY;
+                self.body.appearances.current = self.body.appearances.start + app_passed;
+                if let Some(appearances) = self.parse_appearances(&address_entry)? {
+                    found.push(AddressData {
+                        address: address_entry.address,
+                        appearances,
+                    });
+                }
+                // Return to the address table after reading appearances.
+                self.seek_address_table()?;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Reads the entry at the given position in the address table.
+    fn address_entry_at(&mut self, position: usize) -> Result<AddressEntry, ParseError> {
+        self.body.addresses.current = self.body.addresses.start + position * AD_ENTRY;
+        self.seek_address_table()?;
+        Ok(AddressEntry::from_reader(self.reader.by_ref())?)
+    }
+
+    /// Moves the reader to the current byte of interest in the address table.
+    fn seek_address_table(&mut self) -> Result<(), ParseError> {
+        self.reader
+            .seek(SeekFrom::Start(self.body.addresses.current as u64))
+            .map_err(|e| ParseError::InvalidJumpToAddresses {
+                source: e,
+                address: self.body.addresses.current,
+            })?;
+        Ok(())
+    }
+
     /// Processes the appearances (transactions) for a given address
     fn parse_appearances(
         &mut self,
@@ -256,7 +458,7 @@ impl UnchainedFile {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
 pub struct BlockRange {
     pub old: u32,
     pub new: u32,
@@ -298,10 +500,155 @@ impl BlockRange {
         false
     }
     /// True if range contains the specified transaction.
-    fn contains(&self, tx: &TransactionId) -> bool {
+    pub fn contains(&self, tx: &TransactionId) -> bool {
         if self.old <= tx.block && self.new >= tx.block {
             return true;
         }
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use std::fs;
+
+    #[test]
+    fn lookup_many_matches_single_lookups() {
+        let dir = fixtures::test_dir("parse-lookup-many");
+        let chunk = fixtures::write_sample(&dir);
+        let missing = fixtures::address("0x00000000000000000000000000000000000000ff");
+        let [a, b, c] = [
+            fixtures::ADDRESS_A,
+            fixtures::ADDRESS_B,
+            fixtures::ADDRESS_C,
+        ]
+        .map(fixtures::address);
+        // Unsorted, with a duplicate and an address not in the chunk.
+        let requested = [b, missing, a, b, c];
+
+        for desired in [
+            fixtures::sample_range(),
+            BlockRange::new(17_190_874, 17_190_875).unwrap(),
+        ] {
+            let mut file = UnchainedFile::from_file(chunk.path.clone(), desired).unwrap();
+            let mut expected: Vec<AddressData> = vec![];
+            for address in [c, a, b, missing] {
+                if let Some(appearances) = file.appearances_of(&address).unwrap() {
+                    expected.push(AddressData {
+                        address,
+                        appearances,
+                    });
+                }
+            }
+            assert_eq!(file.lookup_many(&requested).unwrap(), expected);
+        }
+
+        let mut file =
+            UnchainedFile::from_file(chunk.path.clone(), fixtures::sample_range()).unwrap();
+        assert_eq!(
+            file.lookup_many(&requested).unwrap(),
+            fixtures::sample_data()
+        );
+        assert!(file.lookup_many(&[missing]).unwrap().is_empty());
+        assert!(file.lookup_many(&[]).unwrap().is_empty());
+        // Outside the range, C has no appearances.
+        let desired = BlockRange::new(17_190_874, 17_190_875).unwrap();
+        let mut file = UnchainedFile::from_file(chunk.path, desired).unwrap();
+        let found: Vec<Address> = file
+            .lookup_many(&requested)
+            .unwrap()
+            .into_iter()
+            .map(|d| d.address)
+            .collect();
+        assert_eq!(found, vec![a, b]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Writes a compressed copy of a chunk next to it and checks that it reads
+    /// the same as the uncompressed chunk.
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn check_compressed(name: &str, extension: &str, compress: impl Fn(&[u8]) -> Vec<u8>) {
+        let dir = fixtures::test_dir(name);
+        let raw = fixtures::write_sample(&dir);
+        let compressed_dir = dir.join("compressed");
+        fs::create_dir_all(&compressed_dir).unwrap();
+        let compressed_path = compressed_dir.join(format!(
+            "{}.{}",
+            raw.path.file_name().unwrap().to_str().unwrap(),
+            extension
+        ));
+        fs::write(&compressed_path, compress(&fs::read(&raw.path).unwrap())).unwrap();
+        assert_ne!(
+            ChunkEncoding::from_path(&compressed_path),
+            ChunkEncoding::Raw
+        );
+
+        let range = fixtures::sample_range();
+        let mut expected = UnchainedFile::from_file(raw.path.clone(), range).unwrap();
+        let mut file = UnchainedFile::from_file(compressed_path, range).unwrap();
+        assert_eq!(file.version(), fixtures::VERSION);
+        assert_eq!(file.read_all().unwrap(), expected.read_all().unwrap());
+        assert_eq!(file.read_all().unwrap(), fixtures::sample_data());
+        // Random access within the decompressed chunk.
+        let a = fixtures::address(fixtures::ADDRESS_A);
+        assert_eq!(
+            file.appearances_of(&a).unwrap(),
+            expected.appearances_of(&a).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_chunk_reads_as_raw() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+        check_compressed("parse-gzip", "gz", |bytes| {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(bytes).unwrap();
+            encoder.finish().unwrap()
+        });
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_chunk_reads_as_raw() {
+        check_compressed("parse-zstd", "zst", |bytes| {
+            zstd::encode_all(bytes, 0).unwrap()
+        });
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn gzip_chunk_requires_feature() {
+        let dir = fixtures::test_dir("parse-no-gzip");
+        let path = dir.join("017190873-017190875.bin.gz");
+        fs::write(&path, [0u8; 8]).unwrap();
+        assert!(matches!(
+            UnchainedFile::from_file(path, fixtures::sample_range()),
+            Err(ParseError::UnsupportedEncoding {
+                feature: "gzip",
+                ..
+            })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_compressed_chunk_is_an_error() {
+        let dir = fixtures::test_dir("parse-corrupt");
+        let path = dir.join("017190873-017190875.bin.zst");
+        fs::write(&path, b"not zstd data").unwrap();
+        let result = UnchainedFile::from_file(path, fixtures::sample_range());
+        #[cfg(feature = "zstd")]
+        assert!(matches!(result, Err(ParseError::Decompress { .. })));
+        #[cfg(not(feature = "zstd"))]
+        assert!(matches!(
+            result,
+            Err(ParseError::UnsupportedEncoding { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
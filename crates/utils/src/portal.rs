@@ -0,0 +1,371 @@
+//! Portal network style content keys and values for appearances.
+//!
+//! Content is identified by a key: a selector byte followed by an SSZ encoded
+//! container. The content id used for DHT routing is the sha256 of the key.
+//! Values are SSZ encoded.
+//!
+//! | Selector | Key | Value |
+//! |---|---|---|
+//! | 0x00 | AddressAppearancesKey | AddressAppearancesValue |
+//! | 0x01 | ChunkContentsKey | ChunkContentsValue |
+//!
+//! ```text
+//! AddressAppearancesKey { address: Vector[uint8, 20], oldest_block: uint32, newest_block: uint32 }
+//! AddressAppearancesValue { appearances: List[AppearanceTx, MAX_APPEARANCES_PER_ADDRESS] }
+//! ChunkContentsKey { oldest_block: uint32, newest_block: uint32 }
+//! ChunkContentsValue {
+//!     version: Vector[uint8, 32],
+//!     addresses: List[AddressAppearances, MAX_ADDRESSES_PER_VOLUME],
+//! }
+//! ```
+//! AppearanceTx and AddressAppearances are as in [crate::appearance_index].
+//! The range of an address key is not restricted: the key (and content id)
+//! is only shared by peers that use the same range.
+//!
+//! See also: <https://github.com/ethereum/portal-network-specs>
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    address::Address,
+    appearance_index::{MAX_ADDRESSES_PER_VOLUME, MAX_APPEARANCES_PER_ADDRESS},
+    constants::{ADDR, VER},
+    files::{ChunkFile, ChunksDir},
+    parse::{BlockRange, ParseError, UnchainedFile},
+    ssz::{check_max, decode_container, ContainerEncoder, Ssz, SszError},
+    structure::{AddressData, TransactionId},
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum PortalError {
+    #[error("Content key is empty")]
+    EmptyKey,
+    #[error("Unknown content key selector {0:#04x}")]
+    UnknownSelector(u8),
+    #[error("Invalid hex {0}")]
+    InvalidHex(#[from] hex::FromHexError),
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+    #[error("SszError {0}")]
+    SszError(#[from] SszError),
+}
+
+/// Selector of an address appearances content key.
+pub const ADDRESS_APPEARANCES_SELECTOR: u8 = 0x00;
+
+/// Selector of a chunk contents content key.
+pub const CHUNK_CONTENTS_SELECTOR: u8 = 0x01;
+
+/// Appearances of an address within a block range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AddressAppearancesKey {
+    pub address: Address,
+    pub range: BlockRange,
+}
+
+/// All address data of a chunk, identified by its block range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkContentsKey {
+    pub range: BlockRange,
+}
+
+/// Key for a piece of content.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentKey {
+    AddressAppearances(AddressAppearancesKey),
+    ChunkContents(ChunkContentsKey),
+}
+
+impl Ssz for AddressAppearancesKey {
+    const FIXED_LEN: Option<usize> = Some(ADDR + 4 + 4);
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        self.address.ssz_append(buf);
+        self.range.old.ssz_append(buf);
+        self.range.new.ssz_append(buf);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        let fields = decode_container(bytes, &[Some(ADDR), Some(4), Some(4)])?;
+        Ok(AddressAppearancesKey {
+            address: Address::from_ssz_bytes(fields[0])?,
+            range: range_from_ssz(fields[1], fields[2])?,
+        })
+    }
+}
+
+impl Ssz for ChunkContentsKey {
+    const FIXED_LEN: Option<usize> = Some(4 + 4);
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        self.range.old.ssz_append(buf);
+        self.range.new.ssz_append(buf);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        let fields = decode_container(bytes, &[Some(4), Some(4)])?;
+        Ok(ChunkContentsKey {
+            range: range_from_ssz(fields[0], fields[1])?,
+        })
+    }
+}
+
+/// Decodes the oldest and newest blocks of a key, which must be in order.
+fn range_from_ssz(old: &[u8], new: &[u8]) -> Result<BlockRange, SszError> {
+    let (old, new) = (u32::from_ssz_bytes(old)?, u32::from_ssz_bytes(new)?);
+    BlockRange::new(old, new).map_err(|_| SszError::RangeReversed { old, new })
+}
+
+impl ContentKey {
+    /// Selector byte followed by the SSZ encoded key.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            ContentKey::AddressAppearances(key) => {
+                let mut buf = vec![ADDRESS_APPEARANCES_SELECTOR];
+                key.ssz_append(&mut buf);
+                buf
+            }
+            ContentKey::ChunkContents(key) => {
+                let mut buf = vec![CHUNK_CONTENTS_SELECTOR];
+                key.ssz_append(&mut buf);
+                buf
+            }
+        }
+    }
+    /// Decodes a key from its selector and SSZ bytes.
+    pub fn decode(bytes: &[u8]) -> Result<Self, PortalError> {
+        let Some((selector, key)) = bytes.split_first() else {
+            return Err(PortalError::EmptyKey);
+        };
+        match *selector {
+            ADDRESS_APPEARANCES_SELECTOR => Ok(ContentKey::AddressAppearances(
+                AddressAppearancesKey::from_ssz_bytes(key)?,
+            )),
+            CHUNK_CONTENTS_SELECTOR => Ok(ContentKey::ChunkContents(
+                ChunkContentsKey::from_ssz_bytes(key)?,
+            )),
+            other => Err(PortalError::UnknownSelector(other)),
+        }
+    }
+    /// Content id used for DHT routing: sha256 of the encoded key.
+    pub fn content_id(&self) -> [u8; 32] {
+        Sha256::digest(self.encode()).into()
+    }
+    /// 0x-prefixed hex of the encoded key.
+    pub fn to_hex(&self) -> String {
+        format!("0x{}", hex::encode(self.encode()))
+    }
+    /// Decodes a key from hex, with optional 0x prefix.
+    pub fn from_hex(key: &str) -> Result<Self, PortalError> {
+        let key = key.strip_prefix("0x").unwrap_or(key);
+        ContentKey::decode(&hex::decode(key)?)
+    }
+}
+
+/// Appearances of the address in the key range, in ascending order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AddressAppearancesValue {
+    pub appearances: Vec<TransactionId>,
+}
+
+impl Ssz for AddressAppearancesValue {
+    const FIXED_LEN: Option<usize> = None;
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        let mut encoder = ContainerEncoder::new(4);
+        encoder.append(&self.appearances);
+        encoder.finish(buf);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        let fields = decode_container(bytes, &[None])?;
+        let appearances = Vec::<TransactionId>::from_ssz_bytes(fields[0])?;
+        check_max(&appearances, MAX_APPEARANCES_PER_ADDRESS)?;
+        Ok(AddressAppearancesValue { appearances })
+    }
+}
+
+/// Contents of a chunk file, sorted by address.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkContentsValue {
+    pub version: [u8; VER],
+    pub addresses: Vec<AddressData>,
+}
+
+impl Ssz for ChunkContentsValue {
+    const FIXED_LEN: Option<usize> = None;
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        let mut encoder = ContainerEncoder::new(VER + 4);
+        encoder.append(&self.version);
+        encoder.append(&self.addresses);
+        encoder.finish(buf);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        let fields = decode_container(bytes, &[Some(VER), None])?;
+        let addresses = Vec::<AddressData>::from_ssz_bytes(fields[1])?;
+        check_max(&addresses, MAX_ADDRESSES_PER_VOLUME)?;
+        Ok(ChunkContentsValue {
+            version: <[u8; VER]>::from_ssz_bytes(fields[0])?,
+            addresses,
+        })
+    }
+}
+
+/// Encoded content, as hex strings for use over JSON-RPC.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodedContent {
+    pub content_key: String,
+    pub content_id: String,
+    pub content_value: String,
+}
+
+impl EncodedContent {
+    /// Encodes a key and value.
+    pub fn new(key: &ContentKey, value: &impl Ssz) -> Self {
+        EncodedContent {
+            content_key: key.to_hex(),
+            content_id: format!("0x{}", hex::encode(key.content_id())),
+            content_value: format!("0x{}", hex::encode(value.to_ssz_bytes())),
+        }
+    }
+}
+
+/// Content for the appearances of an address within a block range.
+pub fn address_appearances_content(
+    chunks: &ChunksDir,
+    address: &Address,
+    range: &BlockRange,
+) -> Result<(ContentKey, AddressAppearancesValue), PortalError> {
+    let key = ContentKey::AddressAppearances(AddressAppearancesKey {
+        address: *address,
+        range: *range,
+    });
+    let appearances = chunks.appearances_of(address, range)?;
+    Ok((key, AddressAppearancesValue { appearances }))
+}
+
+/// Content for the whole of a chunk file.
+pub fn chunk_contents(chunk: &ChunkFile) -> Result<(ContentKey, ChunkContentsValue), PortalError> {
+    let key = ContentKey::ChunkContents(ChunkContentsKey { range: chunk.range });
+    let mut file = UnchainedFile::from_file(chunk.path.to_owned(), chunk.range)?;
+    let addresses = file.read_all()?;
+    let value = ChunkContentsValue {
+        version: file.version(),
+        addresses,
+    };
+    Ok((key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0x30a4639850b3ddeaaca4f06280aa751682f11382";
+
+    fn address_key() -> ContentKey {
+        ContentKey::AddressAppearances(AddressAppearancesKey {
+            address: Address::from_hex(ADDRESS).unwrap(),
+            range: BlockRange::new(17_100_000, 17_199_999).unwrap(),
+        })
+    }
+
+    fn chunk_key() -> ContentKey {
+        ContentKey::ChunkContents(ChunkContentsKey {
+            range: BlockRange::new(17_190_314, 17_193_246).unwrap(),
+        })
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for key in [address_key(), chunk_key()] {
+            assert_eq!(ContentKey::decode(&key.encode()).unwrap(), key);
+            assert_eq!(ContentKey::from_hex(&key.to_hex()).unwrap(), key);
+        }
+        assert_eq!(
+            address_key().to_hex(),
+            "0x0030a4639850b3ddeaaca4f06280aa751682f11382e0ec04017f730601"
+        );
+        assert_eq!(chunk_key().to_hex(), "0x01aa4d06011e590601");
+    }
+
+    #[test]
+    fn content_id_is_sha256_of_key() {
+        // sha256 of the key hex above, computed independently.
+        assert_eq!(
+            hex::encode(address_key().content_id()),
+            "1c3436f54e5abb38447fc4d2ad9126d09eb46e430bbacb78d9b3b1af495709d0"
+        );
+        assert_eq!(
+            hex::encode(chunk_key().content_id()),
+            "598b438eed15d9eeb24d8722d9e6cae1b7f4552cb42b2e619a5b0c4da2ba56b4"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        assert!(matches!(
+            ContentKey::decode(&[]),
+            Err(PortalError::EmptyKey)
+        ));
+        assert!(matches!(
+            ContentKey::decode(&[0x02, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(PortalError::UnknownSelector(0x02))
+        ));
+        let mut truncated = chunk_key().encode();
+        truncated.pop();
+        assert!(ContentKey::decode(&truncated).is_err());
+
+        // Older block after the newer block.
+        let reversed = |range: &mut BlockRange| (range.old, range.new) = (range.new, range.old);
+        for mut key in [address_key(), chunk_key()] {
+            match &mut key {
+                ContentKey::AddressAppearances(key) => reversed(&mut key.range),
+                ContentKey::ChunkContents(key) => reversed(&mut key.range),
+            }
+            assert!(matches!(
+                ContentKey::decode(&key.encode()),
+                Err(PortalError::SszError(SszError::RangeReversed { .. }))
+            ));
+        }
+        // A single block is a valid range.
+        let single = ContentKey::ChunkContents(ChunkContentsKey {
+            range: BlockRange::new(5, 5).unwrap(),
+        });
+        assert_eq!(ContentKey::decode(&single.encode()).unwrap(), single);
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let tx = |block, index| TransactionId { block, index };
+        let appearances = AddressAppearancesValue {
+            appearances: vec![tx(17_190_873, 5), tx(17_190_875, 99999)],
+        };
+        let bytes = appearances.to_ssz_bytes();
+        assert_eq!(bytes.len(), 4 + 2 * 8);
+        assert_eq!(
+            AddressAppearancesValue::from_ssz_bytes(&bytes).unwrap(),
+            appearances
+        );
+
+        let empty = AddressAppearancesValue::default();
+        assert_eq!(
+            AddressAppearancesValue::from_ssz_bytes(&empty.to_ssz_bytes()).unwrap(),
+            empty
+        );
+
+        let contents = ChunkContentsValue {
+            version: [7; VER],
+            addresses: vec![
+                AddressData {
+                    address: Address([1; ADDR]),
+                    appearances: vec![tx(1, 2)],
+                },
+                AddressData {
+                    address: Address::from_hex(ADDRESS).unwrap(),
+                    appearances: vec![tx(17_190_873, 5), tx(17_190_874, 0)],
+                },
+            ],
+        };
+        assert_eq!(
+            ChunkContentsValue::from_ssz_bytes(&contents.to_ssz_bytes()).unwrap(),
+            contents
+        );
+    }
+}
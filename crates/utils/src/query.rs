@@ -0,0 +1,144 @@
+//! Ordering and pagination for queries of appearances.
+//!
+//! Pages are continued by passing the last appearance of a page as the cursor
+//! for the next query.
+
+use serde::{Deserialize, Serialize};
+
+use super::structure::TransactionId;
+
+/// Order in which appearances are returned.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum Order {
+    /// Oldest block (then lowest index) first.
+    #[default]
+    Ascending,
+    /// Newest block (then highest index) first.
+    Descending,
+}
+
+/// Limits the appearances returned by a query.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pagination {
+    pub order: Order,
+    /// Maximum number of appearances in a page. None for no limit.
+    pub limit: Option<usize>,
+    /// Only return appearances that come after this one (exclusive) in the given order.
+    pub after: Option<TransactionId>,
+    /// Transaction indices (e.g., sentinels) that are left out. They do not
+    /// count towards the limit and are never used as the cursor.
+    pub hidden: Vec<u32>,
+}
+
+/// A single page of appearances.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Page {
+    pub appearances: Vec<TransactionId>,
+    /// Cursor for the next page. None if this is the last page.
+    pub next: Option<TransactionId>,
+}
+
+impl Pagination {
+    /// True if the appearance comes after the cursor in the requested order.
+    pub fn is_after_cursor(&self, tx: &TransactionId) -> bool {
+        match (&self.after, self.order) {
+            (None, _) => true,
+            (Some(cursor), Order::Ascending) => tx > cursor,
+            (Some(cursor), Order::Descending) => tx < cursor,
+        }
+    }
+    /// True if the appearance is returned, ignoring the cursor.
+    pub fn is_shown(&self, tx: &TransactionId) -> bool {
+        !self.hidden.contains(&tx.index)
+    }
+    /// True if a block may hold appearances after the cursor in the requested order.
+    pub fn block_after_cursor(&self, block: u32) -> bool {
+        match (&self.after, self.order) {
+            (None, _) => true,
+            (Some(cursor), Order::Ascending) => block >= cursor.block,
+            (Some(cursor), Order::Descending) => block <= cursor.block,
+        }
+    }
+    /// Orders, applies the cursor and limits a set of appearances.
+    ///
+    /// Duplicate and hidden appearances are removed.
+    pub fn paginate(&self, mut appearances: Vec<TransactionId>) -> Page {
+        appearances.sort();
+        appearances.dedup();
+        if self.order == Order::Descending {
+            appearances.reverse();
+        }
+        let mut remaining = appearances
+            .into_iter()
+            .filter(|tx| self.is_shown(tx) && self.is_after_cursor(tx))
+            .peekable();
+        let appearances: Vec<TransactionId> = match self.limit {
+            Some(limit) => remaining.by_ref().take(limit).collect(),
+            None => remaining.by_ref().collect(),
+        };
+        let next = match remaining.peek() {
+            Some(_) => appearances.last().cloned(),
+            None => None,
+        };
+        Page { appearances, next }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(block: u32, index: u32) -> TransactionId {
+        TransactionId { block, index }
+    }
+
+    /// Appearances with null recipient sentinels between transactions.
+    fn appearances() -> Vec<TransactionId> {
+        vec![tx(1, 0), tx(1, 99997), tx(2, 3), tx(2, 99997), tx(3, 1)]
+    }
+
+    #[test]
+    fn hidden_appearances_do_not_count_towards_limit() {
+        let pagination = Pagination {
+            limit: Some(2),
+            hidden: vec![99997],
+            ..Default::default()
+        };
+        let page = pagination.paginate(appearances());
+        assert_eq!(page.appearances, vec![tx(1, 0), tx(2, 3)]);
+        assert_eq!(page.next, Some(tx(2, 3)));
+
+        let pagination = Pagination {
+            after: page.next,
+            ..pagination
+        };
+        let page = pagination.paginate(appearances());
+        assert_eq!(page.appearances, vec![tx(3, 1)]);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn no_cursor_when_only_hidden_remain() {
+        let pagination = Pagination {
+            limit: Some(2),
+            hidden: vec![99997],
+            ..Default::default()
+        };
+        let page = pagination.paginate(vec![tx(1, 0), tx(2, 3), tx(2, 99997)]);
+        assert_eq!(page.appearances, vec![tx(1, 0), tx(2, 3)]);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn descending_pages_continue_from_cursor() {
+        let pagination = Pagination {
+            order: Order::Descending,
+            limit: Some(2),
+            after: Some(tx(2, 99997)),
+            hidden: vec![],
+        };
+        let page = pagination.paginate(appearances());
+        assert_eq!(page.appearances, vec![tx(2, 3), tx(1, 99997)]);
+        assert_eq!(page.next, Some(tx(1, 99997)));
+    }
+}
@@ -0,0 +1,499 @@
+//! Loads appearances into a SQLite database and queries them.
+//!
+//! Tables:
+//! - chunks: one row per loaded chunk file (filename, block range, CID).
+//! - addresses: one row per address (0x-prefixed lowercase hex).
+//! - appearances: one row per appearance (address, block, tx_index, chunk).
+//!
+//! Appearances are indexed by address and by block. Chunks that have already
+//! been loaded are skipped, so new chunks can be appended incrementally. A
+//! chunk whose file has changed since it was loaded (a different CID for the
+//! same range) is reloaded.
+//!
+//! Requires the `sqlite` feature.
+
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    address::Address,
+    cid::{cid_v0_of_file, CidError},
+    files::{chunk_filename, ChunkFile, ChunksDir},
+    parse::{BlockRange, ParseError, UnchainedFile},
+    query::{Order, Page, Pagination},
+    structure::{AddressData, TransactionId},
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum SqliteError {
+    #[error("CidError {0}")]
+    CidError(#[from] CidError),
+    #[error("Database {path} could not be opened {source}")]
+    DatabaseOpener {
+        source: rusqlite::Error,
+        path: PathBuf,
+    },
+    #[error("Database contains invalid address {0}")]
+    InvalidAddress(#[from] crate::address::AddressError),
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+    #[error("SQLite error {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS chunks (
+    id INTEGER PRIMARY KEY,
+    filename TEXT NOT NULL UNIQUE,
+    range_old INTEGER NOT NULL,
+    range_new INTEGER NOT NULL,
+    cid TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS addresses (
+    id INTEGER PRIMARY KEY,
+    address TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS appearances (
+    address_id INTEGER NOT NULL REFERENCES addresses(id),
+    block INTEGER NOT NULL,
+    tx_index INTEGER NOT NULL,
+    chunk_id INTEGER NOT NULL REFERENCES chunks(id),
+    PRIMARY KEY (address_id, block, tx_index)
+) WITHOUT ROWID;
+CREATE INDEX IF NOT EXISTS appearances_block ON appearances(block);
+CREATE INDEX IF NOT EXISTS appearances_chunk ON appearances(chunk_id);
+";
+
+/// Details of chunks loaded into the database.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadSummary {
+    pub chunks_loaded: usize,
+    /// Loaded chunks that replaced a changed chunk with the same range.
+    pub chunks_reloaded: usize,
+    pub chunks_skipped: usize,
+    pub appearances: usize,
+}
+
+/// SQLite database of appearances.
+pub struct AppearanceDb {
+    conn: Connection,
+}
+
+impl AppearanceDb {
+    /// Opens (or creates) a database and ensures the tables exist.
+    pub fn open(path: &Path) -> Result<Self, SqliteError> {
+        let conn = Connection::open(path).map_err(|e| SqliteError::DatabaseOpener {
+            source: e,
+            path: path.to_path_buf(),
+        })?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(AppearanceDb { conn })
+    }
+
+    /// Loads the chunks in the directory that are not yet in the database.
+    pub fn load_chunks(&mut self, dir: &ChunksDir) -> Result<LoadSummary, SqliteError> {
+        let mut summary = LoadSummary::default();
+        for chunk in &dir.paths {
+            let reload = self.loaded_cid(chunk)?.is_some();
+            match self.load_chunk(chunk)? {
+                Some(count) => {
+                    summary.chunks_loaded += 1;
+                    if reload {
+                        summary.chunks_reloaded += 1;
+                    }
+                    summary.appearances += count;
+                }
+                None => summary.chunks_skipped += 1,
+            }
+        }
+        Ok(summary)
+    }
+
+    /// ID and CID of a loaded chunk with the same range, if any.
+    fn loaded_cid(&self, chunk: &ChunkFile) -> Result<Option<(i64, String)>, SqliteError> {
+        let loaded = self
+            .conn
+            .query_row(
+                "SELECT id, cid FROM chunks WHERE filename = ?1",
+                params![chunk_filename(&chunk.range)],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(loaded)
+    }
+
+    /// Loads a single chunk in one transaction.
+    ///
+    /// If a chunk with the same range was loaded from a different file, its
+    /// appearances are replaced.
+    ///
+    /// Returns the number of appearances added, or None if already loaded.
+    pub fn load_chunk(&mut self, chunk: &ChunkFile) -> Result<Option<usize>, SqliteError> {
+        let filename = chunk_filename(&chunk.range);
+        let cid = cid_v0_of_file(&chunk.path)?.cid;
+        let existing = self.loaded_cid(chunk)?;
+        if let Some((_, loaded)) = &existing {
+            if loaded == &cid {
+                return Ok(None);
+            }
+        }
+        let mut file = UnchainedFile::from_file(chunk.path.to_owned(), chunk.range)?;
+        let data = file.read_all()?;
+
+        let tx = self.conn.transaction()?;
+        if let Some((id, _)) = existing {
+            tx.execute("DELETE FROM appearances WHERE chunk_id = ?1", params![id])?;
+            tx.execute("DELETE FROM chunks WHERE id = ?1", params![id])?;
+        }
+        tx.execute(
+            "INSERT INTO chunks (filename, range_old, range_new, cid) VALUES (?1, ?2, ?3, ?4)",
+            params![filename, chunk.range.old, chunk.range.new, cid],
+        )?;
+        let chunk_id = tx.last_insert_rowid();
+        let mut count = 0;
+        {
+            let mut insert_address =
+                tx.prepare("INSERT OR IGNORE INTO addresses (address) VALUES (?1)")?;
+            let mut address_id = tx.prepare("SELECT id FROM addresses WHERE address = ?1")?;
+            let mut insert_appearance = tx.prepare(
+                "INSERT OR IGNORE INTO appearances (address_id, block, tx_index, chunk_id)
+                VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for entry in data {
+                let address = entry.address.to_string();
+                insert_address.execute(params![address])?;
+                let id: i64 = address_id.query_row(params![address], |row| row.get(0))?;
+                for appearance in entry.appearances {
+                    count += insert_appearance.execute(params![
+                        id,
+                        appearance.block,
+                        appearance.index,
+                        chunk_id
+                    ])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(Some(count))
+    }
+
+    /// Block ranges of the chunks in the database, oldest first.
+    pub fn chunk_ranges(&self) -> Result<Vec<BlockRange>, SqliteError> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT range_old, range_new FROM chunks ORDER BY range_old")?;
+        let ranges = statement
+            .query_map([], |row| {
+                Ok(BlockRange {
+                    old: row.get(0)?,
+                    new: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<BlockRange>, rusqlite::Error>>()?;
+        Ok(ranges)
+    }
+
+    /// Obtains one page of appearances of a single address in the desired block range.
+    pub fn appearances_page(
+        &self,
+        address: &Address,
+        desired_range: &BlockRange,
+        pagination: &Pagination,
+    ) -> Result<Page, SqliteError> {
+        let (direction, comparison) = match pagination.order {
+            Order::Ascending => ("ASC", ">"),
+            Order::Descending => ("DESC", "<"),
+        };
+        let (cursor_block, cursor_index) = match &pagination.after {
+            Some(cursor) => (Some(cursor.block), Some(cursor.index)),
+            None => (None, None),
+        };
+        // One more than the limit shows whether a further page exists.
+        let limit: i64 = match pagination.limit {
+            Some(limit) => limit as i64 + 1,
+            None => -1,
+        };
+        // Integers only, so they can be written into the statement.
+        let hidden = match pagination.hidden.is_empty() {
+            true => String::new(),
+            false => format!(
+                "AND ap.tx_index NOT IN ({})",
+                pagination
+                    .hidden
+                    .iter()
+                    .map(|index| index.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        };
+        let sql = format!(
+            "SELECT ap.block, ap.tx_index FROM appearances ap
+            JOIN addresses ad ON ad.id = ap.address_id
+            WHERE ad.address = ?1 AND ap.block BETWEEN ?2 AND ?3
+            AND (?4 IS NULL OR (ap.block, ap.tx_index) {comparison} (?4, ?5))
+            {hidden}
+            ORDER BY ap.block {direction}, ap.tx_index {direction}
+            LIMIT ?6"
+        );
+        let mut statement = self.conn.prepare(&sql)?;
+        let appearances = statement
+            .query_map(
+                params![
+                    address.to_string(),
+                    desired_range.old,
+                    desired_range.new,
+                    cursor_block,
+                    cursor_index,
+                    limit
+                ],
+                |row| {
+                    Ok(TransactionId {
+                        block: row.get(0)?,
+                        index: row.get(1)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<TransactionId>, rusqlite::Error>>()?;
+        Ok(pagination.paginate(appearances))
+    }
+
+    /// Obtains all appearances of a single address in the desired block range.
+    pub fn appearances_of(
+        &self,
+        address: &Address,
+        desired_range: &BlockRange,
+    ) -> Result<Vec<TransactionId>, SqliteError> {
+        let page = self.appearances_page(address, desired_range, &Pagination::default())?;
+        Ok(page.appearances)
+    }
+
+    /// Obtains every address that appears in a block, sorted by address.
+    pub fn addresses_in_block(&self, block: u32) -> Result<Vec<AddressData>, SqliteError> {
+        let mut statement = self.conn.prepare(
+            "SELECT ad.address, ap.tx_index FROM appearances ap
+            JOIN addresses ad ON ad.id = ap.address_id
+            WHERE ap.block = ?1
+            ORDER BY ad.address, ap.tx_index",
+        )?;
+        let rows = statement
+            .query_map(params![block], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+            })?
+            .collect::<Result<Vec<(String, u32)>, rusqlite::Error>>()?;
+        let mut data: Vec<AddressData> = vec![];
+        for (address, index) in rows {
+            let address = Address::from_hex(&address)?;
+            let appearance = TransactionId { block, index };
+            match data.last_mut() {
+                Some(last) if last.address == address => last.appearances.push(appearance),
+                _ => data.push(AddressData {
+                    address,
+                    appearances: vec![appearance],
+                }),
+            }
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, address, tx, ADDRESS_A, ADDRESS_B, ADDRESS_C};
+
+    const SENTINELS: [u32; 3] = [99999, 99998, 99997];
+
+    /// A chunk after the sample chunk, with more appearances of address A.
+    fn later_range() -> BlockRange {
+        BlockRange::new(17_190_876, 17_190_878).unwrap()
+    }
+
+    fn later_data() -> Vec<AddressData> {
+        vec![AddressData {
+            address: address(ADDRESS_A),
+            appearances: vec![
+                tx(17_190_876, 1),
+                tx(17_190_876, 3),
+                tx(17_190_877, 0),
+                tx(17_190_878, 99999),
+            ],
+        }]
+    }
+
+    fn all_blocks() -> BlockRange {
+        BlockRange::new(0, u32::MAX).unwrap()
+    }
+
+    #[test]
+    fn loads_chunks_incrementally() {
+        let dir = fixtures::test_dir("sqlite-load");
+        let chunks_dir = dir.join("chunks");
+        std::fs::create_dir_all(&chunks_dir).unwrap();
+        fixtures::write_sample(&chunks_dir);
+        let mut db = AppearanceDb::open(&dir.join("appearances.db")).unwrap();
+
+        let summary = db
+            .load_chunks(&ChunksDir::new(&chunks_dir).unwrap())
+            .unwrap();
+        assert_eq!(
+            summary,
+            LoadSummary {
+                chunks_loaded: 1,
+                chunks_reloaded: 0,
+                chunks_skipped: 0,
+                appearances: 5,
+            }
+        );
+        assert_eq!(db.chunk_ranges().unwrap(), vec![fixtures::sample_range()]);
+        for entry in fixtures::sample_data() {
+            assert_eq!(
+                db.appearances_of(&entry.address, &all_blocks()).unwrap(),
+                entry.appearances
+            );
+        }
+
+        // Only the new chunk is loaded.
+        fixtures::write_fixture(&chunks_dir, later_range(), later_data());
+        let summary = db
+            .load_chunks(&ChunksDir::new(&chunks_dir).unwrap())
+            .unwrap();
+        assert_eq!(
+            summary,
+            LoadSummary {
+                chunks_loaded: 1,
+                chunks_reloaded: 0,
+                chunks_skipped: 1,
+                appearances: 4,
+            }
+        );
+        assert_eq!(
+            db.chunk_ranges().unwrap(),
+            vec![fixtures::sample_range(), later_range()]
+        );
+        assert_eq!(
+            db.appearances_of(&address(ADDRESS_A), &all_blocks())
+                .unwrap()
+                .len(),
+            6
+        );
+
+        // A changed chunk with the same range replaces the loaded one.
+        fixtures::write_fixture(
+            &chunks_dir,
+            fixtures::sample_range(),
+            vec![AddressData {
+                address: address(ADDRESS_C),
+                appearances: vec![tx(17_190_874, 1)],
+            }],
+        );
+        let summary = db
+            .load_chunks(&ChunksDir::new(&chunks_dir).unwrap())
+            .unwrap();
+        assert_eq!(
+            summary,
+            LoadSummary {
+                chunks_loaded: 1,
+                chunks_reloaded: 1,
+                chunks_skipped: 1,
+                appearances: 1,
+            }
+        );
+        assert_eq!(
+            db.appearances_of(&address(ADDRESS_C), &all_blocks())
+                .unwrap(),
+            vec![tx(17_190_874, 1)]
+        );
+        assert!(db
+            .appearances_of(&address(ADDRESS_B), &all_blocks())
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            db.appearances_of(&address(ADDRESS_A), &all_blocks())
+                .unwrap()
+                .len(),
+            4
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pages_and_blocks() {
+        let dir = fixtures::test_dir("sqlite-query");
+        let chunks_dir = dir.join("chunks");
+        std::fs::create_dir_all(&chunks_dir).unwrap();
+        fixtures::write_sample(&chunks_dir);
+        fixtures::write_fixture(&chunks_dir, later_range(), later_data());
+        let mut db = AppearanceDb::open(&dir.join("appearances.db")).unwrap();
+        db.load_chunks(&ChunksDir::new(&chunks_dir).unwrap())
+            .unwrap();
+
+        // Newest first, two at a time, without sentinels.
+        let a = address(ADDRESS_A);
+        let mut pagination = Pagination {
+            order: Order::Descending,
+            limit: Some(2),
+            after: None,
+            hidden: SENTINELS.to_vec(),
+        };
+        let first = db.appearances_page(&a, &all_blocks(), &pagination).unwrap();
+        assert_eq!(
+            first,
+            Page {
+                appearances: vec![tx(17_190_877, 0), tx(17_190_876, 3)],
+                next: Some(tx(17_190_876, 3)),
+            }
+        );
+        pagination.after = first.next;
+        let second = db.appearances_page(&a, &all_blocks(), &pagination).unwrap();
+        assert_eq!(
+            second,
+            Page {
+                appearances: vec![tx(17_190_876, 1), tx(17_190_873, 5)],
+                next: None,
+            }
+        );
+
+        // Ascending within a block range, sentinels shown.
+        let pagination = Pagination {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let range = BlockRange::new(17_190_875, 17_190_877).unwrap();
+        let page = db.appearances_page(&a, &range, &pagination).unwrap();
+        assert_eq!(
+            page,
+            Page {
+                appearances: vec![tx(17_190_875, 99999), tx(17_190_876, 1)],
+                next: Some(tx(17_190_876, 1)),
+            }
+        );
+
+        // Sorted by address, including sentinels.
+        assert_eq!(
+            db.addresses_in_block(17_190_873).unwrap(),
+            vec![
+                AddressData {
+                    address: address(ADDRESS_C),
+                    appearances: vec![tx(17_190_873, 2)],
+                },
+                AddressData {
+                    address: address(ADDRESS_A),
+                    appearances: vec![tx(17_190_873, 5)],
+                },
+            ]
+        );
+        assert_eq!(
+            db.addresses_in_block(17_190_874).unwrap(),
+            vec![AddressData {
+                address: address(ADDRESS_B),
+                appearances: vec![tx(17_190_874, 0), tx(17_190_874, 99997)],
+            }]
+        );
+        assert!(db.addresses_in_block(17_190_879).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! Minimal SimpleSerialize (SSZ) encoding and decoding.
+//!
+//! Covers the types used for appearance data: uintN, fixed byte vectors,
+//! lists and containers.
+//!
+//! See also: <https://github.com/ethereum/consensus-specs/blob/dev/ssz/simple-serialize.md>
+
+use super::{address::Address, constants::ADDR, structure::TransactionId};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum SszError {
+    #[error("Expected {expected} bytes, found {found}")]
+    InvalidLength { expected: usize, found: usize },
+    #[error("List of {found} bytes is not a multiple of the item size {item}")]
+    InvalidListLength { found: usize, item: usize },
+    #[error("Offset {offset} is out of bounds (previous {previous}, length {length})")]
+    InvalidOffset {
+        offset: usize,
+        previous: usize,
+        length: usize,
+    },
+    #[error("List has {found} items, more than the maximum {max}")]
+    TooManyItems { found: usize, max: usize },
+    #[error("Block range {old}-{new} has the older block after the newer block")]
+    RangeReversed { old: u32, new: u32 },
+}
+
+/// Byte size of an offset to variable size data.
+pub const OFFSET: usize = 4;
+
+/// A type that can be SSZ encoded and decoded.
+pub trait Ssz: Sized {
+    /// Byte length if the type is fixed size, None if variable size.
+    const FIXED_LEN: Option<usize>;
+
+    /// Appends the encoding to the buffer.
+    fn ssz_append(&self, buf: &mut Vec<u8>);
+
+    /// Decodes from exactly the given bytes.
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError>;
+
+    /// Encodes to a new buffer.
+    fn to_ssz_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        self.ssz_append(&mut buf);
+        buf
+    }
+
+    /// Bytes taken by the field in the fixed part of a container.
+    fn fixed_part_len() -> usize {
+        Self::FIXED_LEN.unwrap_or(OFFSET)
+    }
+}
+
+fn check_len(bytes: &[u8], expected: usize) -> Result<(), SszError> {
+    if bytes.len() != expected {
+        return Err(SszError::InvalidLength {
+            expected,
+            found: bytes.len(),
+        });
+    }
+    Ok(())
+}
+
+impl Ssz for u8 {
+    const FIXED_LEN: Option<usize> = Some(1);
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        buf.push(*self);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        check_len(bytes, 1)?;
+        Ok(bytes[0])
+    }
+}
+
+impl Ssz for u32 {
+    const FIXED_LEN: Option<usize> = Some(4);
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        check_len(bytes, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// Vector[uint8, N]
+impl<const N: usize> Ssz for [u8; N] {
+    const FIXED_LEN: Option<usize> = Some(N);
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        check_len(bytes, N)?;
+        let mut value = [0u8; N];
+        value.copy_from_slice(bytes);
+        Ok(value)
+    }
+}
+
+/// Vector[uint8, 20]
+impl Ssz for Address {
+    const FIXED_LEN: Option<usize> = Some(ADDR);
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        Ok(Address(<[u8; ADDR]>::from_ssz_bytes(bytes)?))
+    }
+}
+
+/// Container { block: uint32, index: uint32 }
+impl Ssz for TransactionId {
+    const FIXED_LEN: Option<usize> = Some(8);
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        self.block.ssz_append(buf);
+        self.index.ssz_append(buf);
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        check_len(bytes, 8)?;
+        Ok(TransactionId {
+            block: u32::from_ssz_bytes(&bytes[..4])?,
+            index: u32::from_ssz_bytes(&bytes[4..])?,
+        })
+    }
+}
+
+/// List[T, N]. The maximum length is checked by the containing type.
+impl<T: Ssz> Ssz for Vec<T> {
+    const FIXED_LEN: Option<usize> = None;
+    fn ssz_append(&self, buf: &mut Vec<u8>) {
+        match T::FIXED_LEN {
+            Some(_) => {
+                for item in self {
+                    item.ssz_append(buf);
+                }
+            }
+            None => {
+                let mut encoder = ContainerEncoder::new(self.len() * OFFSET);
+                for item in self {
+                    encoder.append(item);
+                }
+                encoder.finish(buf);
+            }
+        }
+    }
+    fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, SszError> {
+        match T::FIXED_LEN {
+            Some(item) => {
+                if !bytes.len().is_multiple_of(item) {
+                    return Err(SszError::InvalidListLength {
+                        found: bytes.len(),
+                        item,
+                    });
+                }
+                bytes.chunks(item).map(T::from_ssz_bytes).collect()
+            }
+            None => {
+                if bytes.is_empty() {
+                    return Ok(vec![]);
+                }
+                if bytes.len() < OFFSET {
+                    return Err(SszError::InvalidLength {
+                        expected: OFFSET,
+                        found: bytes.len(),
+                    });
+                }
+                let first = u32::from_ssz_bytes(&bytes[..OFFSET])? as usize;
+                if !first.is_multiple_of(OFFSET) || first == 0 {
+                    return Err(SszError::InvalidOffset {
+                        offset: first,
+                        previous: 0,
+                        length: bytes.len(),
+                    });
+                }
+                let fields = vec![None; first / OFFSET];
+                decode_container(bytes, &fields)?
+                    .into_iter()
+                    .map(T::from_ssz_bytes)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Checks the number of items in a list against its maximum.
+pub fn check_max<T>(list: &[T], max: usize) -> Result<(), SszError> {
+    if list.len() > max {
+        return Err(SszError::TooManyItems {
+            found: list.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// Builds a container (or list of variable size items) from its fields in order.
+///
+/// Fixed size fields are written in place. Variable size fields are replaced by
+/// an offset and their data is appended after the fixed part.
+pub struct ContainerEncoder {
+    fixed_len: usize,
+    fixed: Vec<u8>,
+    variable: Vec<u8>,
+}
+
+impl ContainerEncoder {
+    /// Starts a container whose fixed part has the given byte length.
+    pub fn new(fixed_len: usize) -> Self {
+        ContainerEncoder {
+            fixed_len,
+            fixed: Vec::with_capacity(fixed_len),
+            variable: vec![],
+        }
+    }
+    /// Adds the next field.
+    pub fn append<T: Ssz>(&mut self, field: &T) {
+        match T::FIXED_LEN {
+            Some(_) => field.ssz_append(&mut self.fixed),
+            None => {
+                let offset = (self.fixed_len + self.variable.len()) as u32;
+                offset.ssz_append(&mut self.fixed);
+                field.ssz_append(&mut self.variable);
+            }
+        }
+    }
+    /// Appends the container to the buffer.
+    pub fn finish(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.fixed);
+        buf.extend_from_slice(&self.variable);
+    }
+}
+
+/// Splits a container into the bytes of each field.
+///
+/// Fields are described by their fixed length, or None if variable size.
+pub fn decode_container<'a>(
+    bytes: &'a [u8],
+    fields: &[Option<usize>],
+) -> Result<Vec<&'a [u8]>, SszError> {
+    let fixed_len: usize = fields.iter().map(|f| f.unwrap_or(OFFSET)).sum();
+    if bytes.len() < fixed_len {
+        return Err(SszError::InvalidLength {
+            expected: fixed_len,
+            found: bytes.len(),
+        });
+    }
+    let mut position = 0;
+    let mut offsets: Vec<(usize, usize)> = vec![];
+    let mut slices: Vec<Option<&[u8]>> = vec![];
+    for (n, field) in fields.iter().enumerate() {
+        match field {
+            Some(len) => {
+                slices.push(Some(&bytes[position..position + len]));
+                position += len;
+            }
+            None => {
+                let offset = u32::from_ssz_bytes(&bytes[position..position + OFFSET])? as usize;
+                offsets.push((n, offset));
+                slices.push(None);
+                position += OFFSET;
+            }
+        }
+    }
+    if offsets.is_empty() && bytes.len() != fixed_len {
+        return Err(SszError::InvalidLength {
+            expected: fixed_len,
+            found: bytes.len(),
+        });
+    }
+    let mut previous = fixed_len;
+    for (i, (n, offset)) in offsets.iter().enumerate() {
+        let end = match offsets.get(i + 1) {
+            Some((_, next)) => *next,
+            None => bytes.len(),
+        };
+        let first_mismatch = i == 0 && *offset != fixed_len;
+        if first_mismatch || *offset < previous || end < *offset || end > bytes.len() {
+            return Err(SszError::InvalidOffset {
+                offset: *offset,
+                previous,
+                length: bytes.len(),
+            });
+        }
+        slices[*n] = Some(&bytes[*offset..end]);
+        previous = *offset;
+    }
+    Ok(slices.into_iter().map(|s| s.unwrap_or_default()).collect())
+}
@@ -5,7 +5,10 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use serde::{Deserialize, Serialize};
 use std::{io::Read, path::PathBuf};
 
-use super::constants::{ADDR, MAGIC, VAL, VER};
+use super::{
+    address::Address,
+    constants::{ADDR, MAGIC, VAL, VER},
+};
 
 use thiserror::{self, Error};
 #[derive(Debug, Error)]
@@ -45,6 +48,8 @@ pub enum StructureError {
 #[derive(Default)]
 /// Stores values extracted from file header.
 pub struct Header {
+    /// Hash identifying the specification version of the file.
+    pub version: [u8; VER],
     pub n_addresses: u32,
     pub n_appearances: u32,
 }
@@ -78,6 +83,7 @@ impl Header {
             .read_u32::<LittleEndian>()
             .map_err(|e| StructureError::InvalidNumAppearances { source: e })?;
         Ok(Header {
+            version,
             n_addresses,
             n_appearances,
         })
@@ -106,7 +112,7 @@ pub struct Section {
 #[derive(Clone)]
 pub struct AddressEntry {
     /// Address bytes. Length 20 bytes.
-    pub address: Vec<u8>,
+    pub address: Address,
     pub offset: u32,
     pub count: u32,
 }
@@ -117,7 +123,7 @@ impl AddressEntry {
         let mut addr_buf: [u8; ADDR] = [0; ADDR];
         rdr.read_exact(&mut addr_buf)
             .map_err(|e| StructureError::InvalidAddress { source: e })?;
-        let address = addr_buf.to_vec();
+        let address = Address(addr_buf);
         let offset = rdr
             .read_u32::<LittleEndian>()
             .map_err(|e| StructureError::InvalidOffset { source: e })?;
@@ -134,16 +140,16 @@ impl AddressEntry {
 }
 
 /// Holds selected transactions for a given address.
-#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct AddressData {
     /// The address that appeared in a transaction.
-    pub address: Vec<u8>,
+    pub address: Address,
     /// The transactions where the address appeared.
     pub appearances: Vec<TransactionId>,
 }
 
 /// Content of an entry in the Appearances (transactions) table.
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct TransactionId {
     /// The Ethereum execution block number.
     pub block: u32,
@@ -163,3 +169,35 @@ impl TransactionId {
         Ok(TransactionId { block, index })
     }
 }
+
+/// What a transaction index in the Unchained Index refers to.
+///
+/// Extra-block appearances are stored with sentinel transaction indices.
+/// See also: <https://github.com/TrueBlocks/trueblocks-core/blob/master/src/libs/etherlib/node.cpp#L300>
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocationKind {
+    /// A transaction at the given index.
+    Transaction,
+    /// Block reward recipient (99999).
+    Miner,
+    /// Uncle reward recipient (99998).
+    Uncle,
+    /// Miner did not set a recipient (99997).
+    NullRecipient,
+    /// External rewards, gnosis specific (99996).
+    External,
+}
+
+impl LocationKind {
+    /// Name used in exported data.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LocationKind::Transaction => "transaction",
+            LocationKind::Miner => "miner",
+            LocationKind::Uncle => "uncle",
+            LocationKind::NullRecipient => "null_recipient",
+            LocationKind::External => "external",
+        }
+    }
+}
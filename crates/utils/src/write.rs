@@ -0,0 +1,515 @@
+//! Writes Unchained Index chunk files.
+//!
+//! Chunks are produced from parsed address data, for example to consolidate
+//! several chunks into one, or to cut small fixtures from a full size chunk.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    address::Address,
+    constants::{MAGIC, VER},
+    files::{chunk_filename, partial_path, ChunkFile},
+    parse::{BlockRange, ParseError, UnchainedFile},
+    structure::{AddressData, TransactionId},
+};
+
+use thiserror::{self, Error};
+#[derive(Debug, Error)]
+pub enum WriteError {
+    #[error("File {filename} could not be created {source}")]
+    FileCreator {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Unable to write to {filename} {source}")]
+    Write {
+        source: std::io::Error,
+        filename: PathBuf,
+    },
+    #[error("Chunk {next:?} does not follow on from chunk {previous:?}")]
+    NonAdjacent {
+        previous: BlockRange,
+        next: BlockRange,
+    },
+    #[error("Chunk {path} has version {found}, but expected {expected}")]
+    VersionMismatch {
+        path: PathBuf,
+        found: String,
+        expected: String,
+    },
+    #[error("Appearance (block {block} index {index}) is outside of chunk range {range:?}")]
+    AppearanceOutOfRange {
+        block: u32,
+        index: u32,
+        range: BlockRange,
+    },
+    #[error("Too many entries ({count}) for a chunk file")]
+    TooManyEntries { count: usize },
+    #[error("At least {required} chunks are needed, found {found}")]
+    NotEnoughChunks { required: usize, found: usize },
+    #[error("ParseError {0}")]
+    ParseError(#[from] ParseError),
+}
+
+/// Details of a chunk file that has been written.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WrittenChunk {
+    pub path: PathBuf,
+    pub range: BlockRange,
+    pub n_addresses: u32,
+    pub n_appearances: u32,
+}
+
+/// Combines address data, keeping one entry per address.
+///
+/// Addresses are sorted and appearances are sorted and deduplicated,
+/// as required for a chunk file.
+pub fn merge_address_data(data: impl IntoIterator<Item = AddressData>) -> Vec<AddressData> {
+    let mut merged: BTreeMap<Address, Vec<TransactionId>> = BTreeMap::new();
+    for entry in data {
+        merged
+            .entry(entry.address)
+            .or_default()
+            .extend(entry.appearances);
+    }
+    merged
+        .into_iter()
+        .filter(|(_, appearances)| !appearances.is_empty())
+        .map(|(address, mut appearances)| {
+            appearances.sort();
+            appearances.dedup();
+            AddressData {
+                address,
+                appearances,
+            }
+        })
+        .collect()
+}
+
+/// Writes a chunk file named for the block range into the given directory.
+///
+/// The data is merged first (see [merge_address_data]), so it may be unsorted.
+/// The file is written alongside and then renamed, so an interrupted write
+/// does not leave a truncated chunk.
+pub fn write_chunk(
+    dir: &Path,
+    range: BlockRange,
+    version: [u8; VER],
+    data: Vec<AddressData>,
+) -> Result<WrittenChunk, WriteError> {
+    let data = merge_address_data(data);
+    for appearance in data.iter().flat_map(|d| &d.appearances) {
+        if !range.contains(appearance) {
+            return Err(WriteError::AppearanceOutOfRange {
+                block: appearance.block,
+                index: appearance.index,
+                range,
+            });
+        }
+    }
+    let n_addresses = to_u32(data.len())?;
+    let n_appearances = to_u32(data.iter().map(|d| d.appearances.len()).sum())?;
+
+    let path = dir.join(chunk_filename(&range));
+    let partial = partial_path(&path);
+    let file = File::create(&partial).map_err(|e| WriteError::FileCreator {
+        source: e,
+        filename: partial.to_path_buf(),
+    })?;
+    let mut writer = BufWriter::new(file);
+    write_contents(&mut writer, version, n_addresses, n_appearances, &data)
+        .and_then(|_| writer.flush())
+        .and_then(|_| fs::rename(&partial, &path))
+        .map_err(|e| WriteError::Write {
+            source: e,
+            filename: path.to_path_buf(),
+        })?;
+    Ok(WrittenChunk {
+        path,
+        range,
+        n_addresses,
+        n_appearances,
+    })
+}
+
+/// Writes header, address table and appearance table.
+fn write_contents(
+    mut wtr: impl Write,
+    version: [u8; VER],
+    n_addresses: u32,
+    n_appearances: u32,
+    data: &[AddressData],
+) -> Result<(), std::io::Error> {
+    wtr.write_all(&MAGIC)?;
+    wtr.write_all(&version)?;
+    wtr.write_u32::<LittleEndian>(n_addresses)?;
+    wtr.write_u32::<LittleEndian>(n_appearances)?;
+    let mut offset: u32 = 0;
+    for entry in data {
+        let count = entry.appearances.len() as u32;
+        wtr.write_all(entry.address.as_bytes())?;
+        wtr.write_u32::<LittleEndian>(offset)?;
+        wtr.write_u32::<LittleEndian>(count)?;
+        offset += count;
+    }
+    for appearance in data.iter().flat_map(|d| &d.appearances) {
+        wtr.write_u32::<LittleEndian>(appearance.block)?;
+        wtr.write_u32::<LittleEndian>(appearance.index)?;
+    }
+    Ok(())
+}
+
+fn to_u32(count: usize) -> Result<u32, WriteError> {
+    u32::try_from(count).map_err(|_| WriteError::TooManyEntries { count })
+}
+
+/// Merges adjacent chunk files into a single chunk covering their combined range.
+///
+/// The new chunk is written to the given directory. The source chunks are not modified.
+pub fn consolidate(chunks: &[&ChunkFile], dir: &Path) -> Result<WrittenChunk, WriteError> {
+    if chunks.len() < 2 {
+        return Err(WriteError::NotEnoughChunks {
+            required: 2,
+            found: chunks.len(),
+        });
+    }
+    let mut sorted = chunks.to_vec();
+    sorted.sort_by_key(|c| c.range.old);
+    for pair in sorted.windows(2) {
+        // Nothing can follow a chunk ending at the last block.
+        if pair[0].range.new.checked_add(1) != Some(pair[1].range.old) {
+            return Err(WriteError::NonAdjacent {
+                previous: pair[0].range,
+                next: pair[1].range,
+            });
+        }
+    }
+    let range = BlockRange::new(sorted[0].range.old, sorted[sorted.len() - 1].range.new)?;
+
+    let mut version: Option<[u8; VER]> = None;
+    let mut data: Vec<AddressData> = vec![];
+    for chunk in sorted {
+        let mut file = UnchainedFile::from_file(chunk.path.to_owned(), chunk.range)?;
+        match version {
+            None => version = Some(file.version()),
+            Some(expected) if expected != file.version() => {
+                return Err(WriteError::VersionMismatch {
+                    path: chunk.path.to_owned(),
+                    found: hex::encode(file.version()),
+                    expected: hex::encode(expected),
+                })
+            }
+            Some(_) => {}
+        }
+        data.extend(file.read_all()?);
+    }
+    write_chunk(dir, range, version.unwrap_or_default(), data)
+}
+
+/// Writes the part of a chunk that falls within a block range as a new chunk.
+///
+/// The new chunk covers the intersection of the chunk and the desired range and
+/// only holds addresses that still have appearances in that range.
+pub fn extract_range(
+    chunk: &ChunkFile,
+    desired: BlockRange,
+    dir: &Path,
+) -> Result<WrittenChunk, WriteError> {
+    // Fails if the chunk and desired range do not intersect.
+    let mut file = UnchainedFile::from_file(chunk.path.to_owned(), desired)?;
+    let range = BlockRange::new(
+        chunk.range.old.max(desired.old),
+        chunk.range.new.min(desired.new),
+    )?;
+    let data = file.read_all()?;
+    write_chunk(dir, range, file.version(), data)
+}
+
+/// Writes a chunk holding only the given addresses, optionally limited to a block range.
+///
+/// The new chunk covers the chunk range (or its intersection with the desired range).
+/// Requested addresses without appearances are left out.
+pub fn extract_addresses(
+    chunk: &ChunkFile,
+    addresses: &[Address],
+    desired: Option<BlockRange>,
+    dir: &Path,
+) -> Result<WrittenChunk, WriteError> {
+    let desired = desired.unwrap_or(chunk.range);
+    let mut file = UnchainedFile::from_file(chunk.path.to_owned(), desired)?;
+    let range = BlockRange::new(
+        chunk.range.old.max(desired.old),
+        chunk.range.new.min(desired.new),
+    )?;
+    let data = file.lookup_many(addresses)?;
+    write_chunk(dir, range, file.version(), data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, address, tx, ADDRESS_A, ADDRESS_B, ADDRESS_C};
+
+    /// An empty directory for the test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("unchained-write-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn chunk_ending_at_last_block_is_not_adjacent() {
+        let last = ChunkFile {
+            path: PathBuf::from("a.bin"),
+            range: BlockRange::new(100, u32::MAX).unwrap(),
+        };
+        let first = ChunkFile {
+            path: PathBuf::from("b.bin"),
+            range: BlockRange::new(0, 99).unwrap(),
+        };
+        let other = ChunkFile {
+            path: PathBuf::from("c.bin"),
+            range: BlockRange::new(100, 200).unwrap(),
+        };
+        let result = consolidate(&[&first, &last, &other], Path::new("."));
+        assert!(matches!(result, Err(WriteError::NonAdjacent { .. })));
+    }
+
+    #[test]
+    fn written_chunk_reads_back_without_partial_file() {
+        let dir = test_dir("read-back");
+        let range = BlockRange::new(10, 20).unwrap();
+        let address = Address([7; 20]);
+        let appearances = vec![
+            TransactionId {
+                block: 12,
+                index: 1,
+            },
+            TransactionId {
+                block: 10,
+                index: 3,
+            },
+        ];
+        let written = write_chunk(
+            &dir,
+            range,
+            [0; VER],
+            vec![AddressData {
+                address,
+                appearances: appearances.clone(),
+            }],
+        )
+        .unwrap();
+        assert_eq!(written.path, dir.join("000000010-000000020.bin"));
+        assert!(!partial_path(&written.path).exists());
+        let mut file = UnchainedFile::from_file(written.path, range).unwrap();
+        let mut expected = appearances;
+        expected.sort();
+        assert_eq!(file.appearances_of(&address).unwrap(), Some(expected));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Reads a written chunk back over its whole range.
+    fn read_back(written: &WrittenChunk) -> Vec<AddressData> {
+        let mut file = UnchainedFile::from_file(written.path.clone(), written.range).unwrap();
+        assert_eq!(file.version(), fixtures::VERSION);
+        file.read_all().unwrap()
+    }
+
+    #[test]
+    fn extract_range_keeps_edges() {
+        let dir = test_dir("extract-range");
+        let chunk = fixtures::write_sample(&dir);
+        let output = dir.join("out");
+        fs::create_dir_all(&output).unwrap();
+
+        // Range past the end of the chunk: C only appears in the first block.
+        let written = extract_range(
+            &chunk,
+            BlockRange::new(17_190_874, 17_190_900).unwrap(),
+            &output,
+        )
+        .unwrap();
+        assert_eq!(
+            written.range,
+            BlockRange::new(17_190_874, 17_190_875).unwrap()
+        );
+        assert_eq!(written.path, output.join("017190874-017190875.bin"));
+        assert_eq!((written.n_addresses, written.n_appearances), (2, 3));
+        assert_eq!(
+            read_back(&written),
+            vec![
+                AddressData {
+                    address: address(ADDRESS_A),
+                    appearances: vec![tx(17_190_875, 99999)],
+                },
+                AddressData {
+                    address: address(ADDRESS_B),
+                    appearances: vec![tx(17_190_874, 0), tx(17_190_874, 99997)],
+                },
+            ]
+        );
+
+        // Single block at the start of the chunk.
+        let written = extract_range(
+            &chunk,
+            BlockRange::new(17_190_800, 17_190_873).unwrap(),
+            &output,
+        )
+        .unwrap();
+        assert_eq!(
+            written.range,
+            BlockRange::new(17_190_873, 17_190_873).unwrap()
+        );
+        assert_eq!(
+            read_back(&written),
+            vec![
+                AddressData {
+                    address: address(ADDRESS_C),
+                    appearances: vec![tx(17_190_873, 2)],
+                },
+                AddressData {
+                    address: address(ADDRESS_A),
+                    appearances: vec![tx(17_190_873, 5)],
+                },
+            ]
+        );
+
+        // The whole chunk is copied unchanged.
+        let written = extract_range(&chunk, fixtures::sample_range(), &output).unwrap();
+        assert_eq!(read_back(&written), fixtures::sample_data());
+
+        let outside = extract_range(&chunk, BlockRange::new(0, 100).unwrap(), &output);
+        assert!(matches!(
+            outside,
+            Err(WriteError::ParseError(
+                ParseError::AppearancesOutOfRange { .. }
+            ))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn consolidate_merges_sorts_and_dedups() {
+        let dir = test_dir("consolidate");
+        let sample = fixtures::write_sample(&dir);
+        // Written directly, so the addresses and appearances are unsorted and
+        // an appearance is repeated.
+        let range = BlockRange::new(17_190_876, 17_190_878).unwrap();
+        let path = dir.join(chunk_filename(&range));
+        let data = vec![
+            AddressData {
+                address: address(ADDRESS_A),
+                appearances: vec![tx(17_190_877, 0), tx(17_190_876, 1), tx(17_190_877, 0)],
+            },
+            AddressData {
+                address: address(ADDRESS_C),
+                appearances: vec![tx(17_190_876, 2)],
+            },
+        ];
+        write_contents(File::create(&path).unwrap(), fixtures::VERSION, 2, 4, &data).unwrap();
+        let unsorted = ChunkFile::new(&path).unwrap();
+
+        let output = dir.join("out");
+        fs::create_dir_all(&output).unwrap();
+        let written = consolidate(&[&unsorted, &sample], &output).unwrap();
+        assert_eq!(
+            written.range,
+            BlockRange::new(17_190_873, 17_190_878).unwrap()
+        );
+        assert_eq!((written.n_addresses, written.n_appearances), (3, 8));
+        assert_eq!(
+            read_back(&written),
+            vec![
+                AddressData {
+                    address: address(ADDRESS_C),
+                    appearances: vec![tx(17_190_873, 2), tx(17_190_876, 2)],
+                },
+                AddressData {
+                    address: address(ADDRESS_A),
+                    appearances: vec![
+                        tx(17_190_873, 5),
+                        tx(17_190_875, 99999),
+                        tx(17_190_876, 1),
+                        tx(17_190_877, 0),
+                    ],
+                },
+                AddressData {
+                    address: address(ADDRESS_B),
+                    appearances: vec![tx(17_190_874, 0), tx(17_190_874, 99997)],
+                },
+            ]
+        );
+
+        // Chunks must share a version.
+        let range = BlockRange::new(17_190_879, 17_190_880).unwrap();
+        // Sample appearances are outside of the range.
+        let other = write_chunk(&dir, range, [0; VER], fixtures::sample_data());
+        assert!(matches!(
+            other,
+            Err(WriteError::AppearanceOutOfRange { .. })
+        ));
+        let other = write_chunk(
+            &dir,
+            range,
+            [0; VER],
+            vec![AddressData {
+                address: address(ADDRESS_B),
+                appearances: vec![tx(17_190_880, 1)],
+            }],
+        )
+        .unwrap();
+        let other = ChunkFile::new(&other.path).unwrap();
+        assert!(matches!(
+            consolidate(&[&unsorted, &other], &output),
+            Err(WriteError::VersionMismatch { .. })
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extract_addresses_keeps_requested() {
+        let dir = test_dir("extract-addresses");
+        let chunk = fixtures::write_sample(&dir);
+        let output = dir.join("out");
+        fs::create_dir_all(&output).unwrap();
+        let missing = address("0x00000000000000000000000000000000000000ff");
+
+        // Whole chunk, including sentinels at the range edge.
+        let requested = [address(ADDRESS_B), missing, address(ADDRESS_A)];
+        let written = extract_addresses(&chunk, &requested, None, &output).unwrap();
+        assert_eq!(written.range, fixtures::sample_range());
+        assert_eq!((written.n_addresses, written.n_appearances), (2, 4));
+        assert_eq!(read_back(&written), fixtures::sample_data().split_off(1));
+
+        // C has no appearances in the range, so it is left out.
+        let requested = [address(ADDRESS_C), address(ADDRESS_A)];
+        let desired = BlockRange::new(17_190_875, 17_190_875).unwrap();
+        let written = extract_addresses(&chunk, &requested, Some(desired), &output).unwrap();
+        assert_eq!(written.range, desired);
+        assert_eq!(
+            read_back(&written),
+            vec![AddressData {
+                address: address(ADDRESS_A),
+                appearances: vec![tx(17_190_875, 99999)],
+            }]
+        );
+
+        // No requested address appears: an empty chunk.
+        let written = extract_addresses(&chunk, &[missing], None, &output).unwrap();
+        assert_eq!((written.n_addresses, written.n_appearances), (0, 0));
+        assert!(read_back(&written).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}